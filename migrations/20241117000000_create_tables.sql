@@ -0,0 +1,73 @@
+-- Base schema. Column names and types follow the structs in src/data.rs:
+-- items are stored as JSON on contracts and repair orders, the claim and
+-- contract indexes are JSON arrays of UUIDs.
+
+-- Table for users
+CREATE TABLE users (
+    username TEXT PRIMARY KEY,
+    password TEXT NOT NULL,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    contract_index JSONB NOT NULL DEFAULT '[]' -- Array of contract UUIDs
+);
+
+-- Table for contract types
+CREATE TABLE contract_types (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    shop_type TEXT NOT NULL,
+    formula_per_day TEXT NOT NULL,
+    max_sum_insured REAL NOT NULL CHECK (max_sum_insured >= 0),
+    theft_insured BOOLEAN NOT NULL,
+    description TEXT,
+    conditions TEXT,
+    active BOOLEAN NOT NULL,
+    min_duration_days INTEGER NOT NULL CHECK (min_duration_days >= 0),
+    max_duration_days INTEGER NOT NULL,
+    CHECK (max_duration_days >= min_duration_days)
+);
+
+CREATE INDEX idx_contract_types_shop_type ON contract_types (shop_type);
+
+-- Table for contracts
+CREATE TABLE contracts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    username TEXT NOT NULL REFERENCES users(username),
+    item JSONB NOT NULL, -- Serialized Item
+    start_date TIMESTAMP NOT NULL,
+    end_date TIMESTAMP NOT NULL,
+    void BOOLEAN NOT NULL DEFAULT FALSE,
+    contract_type_uuid UUID NOT NULL REFERENCES contract_types(id),
+    claim_index JSONB NOT NULL DEFAULT '[]', -- Array of claim UUIDs
+    CHECK (end_date > start_date)
+);
+
+CREATE INDEX idx_contracts_username ON contracts (username);
+CREATE INDEX idx_contracts_contract_type_uuid ON contracts (contract_type_uuid);
+
+-- Table for claims
+CREATE TABLE claims (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    contract_uuid UUID NOT NULL REFERENCES contracts(id),
+    date TIMESTAMP NOT NULL,
+    description TEXT NOT NULL,
+    is_theft BOOLEAN NOT NULL,
+    status TEXT NOT NULL, -- Name of the ClaimStatus variant
+    reimbursable REAL NOT NULL DEFAULT 0 CHECK (reimbursable >= 0),
+    repaired BOOLEAN NOT NULL DEFAULT FALSE,
+    file_reference TEXT NOT NULL DEFAULT ''
+);
+
+CREATE INDEX idx_claims_contract_uuid ON claims (contract_uuid);
+CREATE INDEX idx_claims_is_theft_status ON claims (is_theft, status);
+
+-- Table for repair orders
+CREATE TABLE repair_orders (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    claim_uuid UUID NOT NULL REFERENCES claims(id),
+    contract_uuid UUID NOT NULL REFERENCES contracts(id),
+    item JSONB NOT NULL, -- Serialized Item
+    ready BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX idx_repair_orders_ready ON repair_orders (ready);
+CREATE INDEX idx_repair_orders_claim_uuid ON repair_orders (claim_uuid);
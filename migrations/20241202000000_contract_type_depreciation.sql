@@ -0,0 +1,3 @@
+-- Depreciation rule used to compute the insured value of an item on the claim date
+-- Stored as JSON, e.g. {"method": "straight_line", "lifetime_days": 1095, "residual_pct": 0.1}
+ALTER TABLE contract_types ADD COLUMN depreciation JSONB;
@@ -0,0 +1,4 @@
+-- Early cancellation of a contract with the refunded unused premium
+ALTER TABLE contracts ADD COLUMN cancelled_at TIMESTAMP;
+ALTER TABLE contracts ADD COLUMN cancel_reason TEXT;
+ALTER TABLE contracts ADD COLUMN refund REAL;
@@ -0,0 +1,17 @@
+-- Expiry processing and renewal of contracts
+ALTER TABLE contracts ADD COLUMN expired BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE contracts ADD COLUMN renewed_from UUID REFERENCES contracts(id);
+ALTER TABLE contracts ADD COLUMN reminder_sent_at TIMESTAMP;
+
+CREATE UNIQUE INDEX idx_contracts_renewed_from ON contracts (renewed_from);
+
+-- Events emitted for contracts (expiry, reminders, renewals)
+CREATE TABLE contract_events (
+    id UUID PRIMARY KEY,
+    contract_uuid UUID NOT NULL REFERENCES contracts(id),
+    kind TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    payload JSONB
+);
+
+CREATE INDEX idx_contract_events_contract_uuid ON contract_events (contract_uuid);
@@ -0,0 +1,9 @@
+-- Immutable contract type versions; every version of a type shares the id of its first version
+ALTER TABLE contract_types ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE contract_types ADD COLUMN base_uuid UUID;
+ALTER TABLE contract_types ADD COLUMN superseded_by UUID REFERENCES contract_types(id);
+
+UPDATE contract_types SET base_uuid = id WHERE base_uuid IS NULL;
+ALTER TABLE contract_types ALTER COLUMN base_uuid SET NOT NULL;
+
+CREATE UNIQUE INDEX idx_contract_types_base_version ON contract_types (base_uuid, version);
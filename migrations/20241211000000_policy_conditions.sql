@@ -0,0 +1,6 @@
+-- Structured policy conditions evaluated on claims, e.g.
+-- {"excluded_damage_categories": ["water"], "covered_regions": ["EU"], "max_claims_per_year": 2, "waiting_period_days": 14}
+ALTER TABLE contract_types ADD COLUMN policy_conditions JSONB;
+
+ALTER TABLE claims ADD COLUMN damage_category TEXT;
+ALTER TABLE claims ADD COLUMN region TEXT;
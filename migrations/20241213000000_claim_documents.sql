@@ -0,0 +1,15 @@
+-- Evidence attached to claims; the content lives in the blob store under its SHA-256
+CREATE TABLE claim_documents (
+    id UUID PRIMARY KEY,
+    claim_uuid UUID NOT NULL REFERENCES claims(id),
+    uploaded_by TEXT NOT NULL,
+    role TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_claim_documents_claim_uuid ON claim_documents (claim_uuid);
@@ -0,0 +1,5 @@
+-- Fraud score and triggered rules attached to claims when they are filed
+ALTER TABLE claims ADD COLUMN fraud_score INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE claims ADD COLUMN fraud_rules TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX idx_contracts_item_serial_no ON contracts ((item->>'serial_no'));
@@ -0,0 +1,13 @@
+-- Insurer staff handling claims
+CREATE TABLE adjusters (
+    username TEXT PRIMARY KEY,
+    role TEXT NOT NULL CHECK (role IN ('adjuster', 'supervisor')),
+    active BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+-- Assignment of claims to adjusters
+ALTER TABLE claims ADD COLUMN assignee TEXT REFERENCES adjusters(username);
+ALTER TABLE claims ADD COLUMN assigned_at TIMESTAMP;
+ALTER TABLE claims ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_claims_assignee ON claims (assignee);
@@ -0,0 +1,16 @@
+-- Time a claim entered its current status, used for SLA tracking
+ALTER TABLE claims ADD COLUMN status_changed_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE claims ADD COLUMN sla_breached_at TIMESTAMP;
+
+CREATE INDEX idx_claims_status_changed_at ON claims (status, status_changed_at);
+
+-- Events recorded against claims (SLA breaches, escalations)
+CREATE TABLE claim_events (
+    id UUID PRIMARY KEY,
+    claim_uuid UUID NOT NULL REFERENCES claims(id),
+    kind TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    payload JSONB
+);
+
+CREATE INDEX idx_claim_events_claim_uuid ON claim_events (claim_uuid);
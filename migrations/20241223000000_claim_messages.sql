@@ -0,0 +1,12 @@
+-- Message thread per claim between insurer, police, repair shop and claimant
+CREATE TABLE claim_messages (
+    id UUID PRIMARY KEY,
+    claim_uuid UUID NOT NULL REFERENCES claims(id),
+    author TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('claimant', 'police', 'repair_shop', 'insurer')),
+    visibility TEXT NOT NULL CHECK (visibility IN ('internal', 'shared')),
+    body TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_claim_messages_claim_uuid ON claim_messages (claim_uuid, created_at);
@@ -0,0 +1,12 @@
+-- Appeals against rejected claims, one per claim, decided separately from the claim
+CREATE TABLE claim_appeals (
+    id UUID PRIMARY KEY,
+    claim_uuid UUID NOT NULL UNIQUE REFERENCES claims(id),
+    username TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    filed_at TIMESTAMP NOT NULL,
+    decision TEXT CHECK (decision IN ('upheld', 'overturned')),
+    decision_reason TEXT,
+    decided_by TEXT REFERENCES adjusters(username),
+    decided_at TIMESTAMP
+);
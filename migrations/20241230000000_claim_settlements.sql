@@ -0,0 +1,12 @@
+-- Settlement line items of a claim; claims.reimbursable holds their total
+CREATE TABLE claim_settlements (
+    id UUID PRIMARY KEY,
+    claim_uuid UUID NOT NULL REFERENCES claims(id),
+    kind TEXT NOT NULL CHECK (kind IN ('repair', 'cash', 'deductible', 'salvage')),
+    amount REAL NOT NULL CHECK (amount > 0),
+    note TEXT,
+    created_by TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_claim_settlements_claim_uuid ON claim_settlements (claim_uuid);
@@ -0,0 +1,7 @@
+-- Claim statuses written by the handlers (names of the ClaimStatus variants)
+ALTER TABLE claims ADD CONSTRAINT claims_status_check CHECK (
+    status IN ('New', 'Rejected', 'Repair', 'Reimbursement', 'TheftConfirmed', 'ManualReview', 'UnderAppeal')
+);
+
+-- A contract type version can only be superseded by another version of the same type
+ALTER TABLE contract_types ADD CONSTRAINT contract_types_not_self_superseded CHECK (superseded_by <> id);
@@ -0,0 +1,4 @@
+-- Contract and claim indexes are derived from the foreign keys
+-- (contracts.username, claims.contract_uuid) instead of being stored.
+ALTER TABLE users DROP COLUMN contract_index;
+ALTER TABLE contracts DROP COLUMN claim_index;
@@ -0,0 +1,10 @@
+-- Full-text search over claim descriptions, contract items and customer names.
+-- The expressions must match the search queries for the indexes to be used.
+-- SQLite has no equivalent, its search scans the tables.
+CREATE INDEX idx_claims_description_fts ON claims
+    USING GIN (to_tsvector('english', description));
+CREATE INDEX idx_contracts_item_fts ON contracts
+    USING GIN (to_tsvector('simple', coalesce(item->>'brand', '') || ' ' || coalesce(item->>'model', '')
+        || ' ' || coalesce(item->>'serial_no', '')));
+CREATE INDEX idx_users_name_fts ON users
+    USING GIN (to_tsvector('simple', first_name || ' ' || last_name));
@@ -0,0 +1,7 @@
+-- Accounts of the shops, police and repair shops calling on behalf of others
+CREATE TABLE peer_accounts (
+    username TEXT PRIMARY KEY,
+    role TEXT NOT NULL CHECK (role IN ('shop', 'police', 'repair_shop')),
+    password TEXT NOT NULL, -- bcrypt hash
+    active BOOLEAN NOT NULL DEFAULT TRUE
+);
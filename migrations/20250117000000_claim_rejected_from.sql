@@ -0,0 +1,2 @@
+-- Status a claim had before it was rejected, restored when an appeal overturns the rejection
+ALTER TABLE claims ADD COLUMN rejected_from TEXT;
@@ -0,0 +1,3 @@
+-- Settlements may be zero, e.g. the cash reimbursement of an item valued at nothing
+ALTER TABLE claim_settlements DROP CONSTRAINT claim_settlements_amount_check;
+ALTER TABLE claim_settlements ADD CONSTRAINT claim_settlements_amount_check CHECK (amount >= 0);
@@ -0,0 +1,2 @@
+-- The expiry scan only looks at contracts that have not expired yet.
+CREATE INDEX idx_contracts_end_date ON contracts (end_date) WHERE expired = FALSE;
@@ -0,0 +1,3 @@
+-- Cancelled contracts are told apart by cancelled_at; void is left for reimbursed thefts.
+-- Contracts could only be cancelled while not void, so the flag was set by the cancellation.
+UPDATE contracts SET void = FALSE WHERE cancelled_at IS NOT NULL;
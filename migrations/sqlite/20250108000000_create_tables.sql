@@ -0,0 +1,173 @@
+-- SQLite schema equivalent to the Postgres migrations in the parent directory
+-- up to 20250106000000. UUIDs are stored as TEXT, JSON documents as TEXT and
+-- arrays (fraud_rules) as JSON arrays. Schema changes must be added to both sets,
+-- including the tables only used by functions that need Postgres (adjusters,
+-- documents, events, messages, appeals, settlements). The full-text search
+-- indexes of 20250110000000 have no SQLite equivalent.
+
+CREATE TABLE users (
+    username TEXT PRIMARY KEY,
+    password TEXT NOT NULL,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL
+);
+
+CREATE TABLE contract_types (
+    id TEXT PRIMARY KEY,
+    shop_type TEXT NOT NULL,
+    formula_per_day TEXT NOT NULL,
+    max_sum_insured REAL NOT NULL CHECK (max_sum_insured >= 0),
+    theft_insured BOOLEAN NOT NULL,
+    description TEXT,
+    conditions TEXT,
+    active BOOLEAN NOT NULL,
+    min_duration_days INTEGER NOT NULL CHECK (min_duration_days >= 0),
+    max_duration_days INTEGER NOT NULL,
+    depreciation TEXT, -- JSON
+    version INTEGER NOT NULL DEFAULT 1,
+    base_uuid TEXT NOT NULL,
+    superseded_by TEXT REFERENCES contract_types(id) CHECK (superseded_by <> id),
+    policy_conditions TEXT, -- JSON
+    CHECK (max_duration_days >= min_duration_days)
+);
+
+CREATE INDEX idx_contract_types_shop_type ON contract_types (shop_type);
+CREATE UNIQUE INDEX idx_contract_types_base_version ON contract_types (base_uuid, version);
+
+CREATE TABLE contracts (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL REFERENCES users(username),
+    item TEXT NOT NULL, -- Serialized Item
+    start_date TIMESTAMP NOT NULL,
+    end_date TIMESTAMP NOT NULL,
+    void BOOLEAN NOT NULL DEFAULT FALSE,
+    contract_type_uuid TEXT NOT NULL REFERENCES contract_types(id),
+    cancelled_at TIMESTAMP,
+    cancel_reason TEXT,
+    refund REAL,
+    expired BOOLEAN NOT NULL DEFAULT FALSE,
+    renewed_from TEXT REFERENCES contracts(id),
+    reminder_sent_at TIMESTAMP,
+    CHECK (end_date > start_date)
+);
+
+CREATE INDEX idx_contracts_username ON contracts (username);
+CREATE INDEX idx_contracts_contract_type_uuid ON contracts (contract_type_uuid);
+CREATE UNIQUE INDEX idx_contracts_renewed_from ON contracts (renewed_from);
+CREATE INDEX idx_contracts_item_serial_no ON contracts (json_extract(item, '$.serial_no'));
+
+CREATE TABLE contract_events (
+    id TEXT PRIMARY KEY,
+    contract_uuid TEXT NOT NULL REFERENCES contracts(id),
+    kind TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    payload TEXT -- JSON
+);
+
+CREATE INDEX idx_contract_events_contract_uuid ON contract_events (contract_uuid);
+
+CREATE TABLE adjusters (
+    username TEXT PRIMARY KEY,
+    role TEXT NOT NULL CHECK (role IN ('adjuster', 'supervisor')),
+    active BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE claims (
+    id TEXT PRIMARY KEY,
+    contract_uuid TEXT NOT NULL REFERENCES contracts(id),
+    date TIMESTAMP NOT NULL,
+    description TEXT NOT NULL,
+    is_theft BOOLEAN NOT NULL,
+    status TEXT NOT NULL CHECK (
+        status IN ('New', 'Rejected', 'Repair', 'Reimbursement', 'TheftConfirmed', 'ManualReview', 'UnderAppeal')
+    ),
+    reimbursable REAL NOT NULL DEFAULT 0 CHECK (reimbursable >= 0),
+    repaired BOOLEAN NOT NULL DEFAULT FALSE,
+    file_reference TEXT NOT NULL DEFAULT '',
+    damage_category TEXT,
+    region TEXT,
+    fraud_score INTEGER NOT NULL DEFAULT 0,
+    fraud_rules TEXT NOT NULL DEFAULT '[]', -- JSON array
+    assignee TEXT REFERENCES adjusters(username),
+    assigned_at TIMESTAMP,
+    priority INTEGER NOT NULL DEFAULT 0,
+    status_changed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    sla_breached_at TIMESTAMP
+);
+
+CREATE INDEX idx_claims_contract_uuid ON claims (contract_uuid);
+CREATE INDEX idx_claims_is_theft_status ON claims (is_theft, status);
+CREATE INDEX idx_claims_assignee ON claims (assignee);
+CREATE INDEX idx_claims_status_changed_at ON claims (status, status_changed_at);
+
+CREATE TABLE repair_orders (
+    id TEXT PRIMARY KEY,
+    claim_uuid TEXT NOT NULL REFERENCES claims(id),
+    contract_uuid TEXT NOT NULL REFERENCES contracts(id),
+    item TEXT NOT NULL, -- Serialized Item
+    ready BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX idx_repair_orders_ready ON repair_orders (ready);
+CREATE INDEX idx_repair_orders_claim_uuid ON repair_orders (claim_uuid);
+
+CREATE TABLE claim_documents (
+    id TEXT PRIMARY KEY,
+    claim_uuid TEXT NOT NULL REFERENCES claims(id),
+    uploaded_by TEXT NOT NULL,
+    role TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    size INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_claim_documents_claim_uuid ON claim_documents (claim_uuid);
+
+CREATE TABLE claim_events (
+    id TEXT PRIMARY KEY,
+    claim_uuid TEXT NOT NULL REFERENCES claims(id),
+    kind TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    payload TEXT -- JSON
+);
+
+CREATE INDEX idx_claim_events_claim_uuid ON claim_events (claim_uuid);
+
+CREATE TABLE claim_messages (
+    id TEXT PRIMARY KEY,
+    claim_uuid TEXT NOT NULL REFERENCES claims(id),
+    author TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('claimant', 'police', 'repair_shop', 'insurer')),
+    visibility TEXT NOT NULL CHECK (visibility IN ('internal', 'shared')),
+    body TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_claim_messages_claim_uuid ON claim_messages (claim_uuid, created_at);
+
+CREATE TABLE claim_appeals (
+    id TEXT PRIMARY KEY,
+    claim_uuid TEXT NOT NULL UNIQUE REFERENCES claims(id),
+    username TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    filed_at TIMESTAMP NOT NULL,
+    decision TEXT CHECK (decision IN ('upheld', 'overturned')),
+    decision_reason TEXT,
+    decided_by TEXT REFERENCES adjusters(username),
+    decided_at TIMESTAMP
+);
+
+CREATE TABLE claim_settlements (
+    id TEXT PRIMARY KEY,
+    claim_uuid TEXT NOT NULL REFERENCES claims(id),
+    kind TEXT NOT NULL CHECK (kind IN ('repair', 'cash', 'deductible', 'salvage')),
+    amount REAL NOT NULL CHECK (amount > 0),
+    note TEXT,
+    created_by TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_claim_settlements_claim_uuid ON claim_settlements (claim_uuid);
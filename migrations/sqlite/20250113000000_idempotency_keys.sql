@@ -0,0 +1,10 @@
+-- Results of state-changing calls made with an Idempotency-Key, replayed when
+-- the call is retried with the same key. response is NULL while the call runs.
+CREATE TABLE idempotency_keys (
+    key TEXT PRIMARY KEY,
+    request_hash TEXT NOT NULL,
+    response TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_idempotency_keys_created_at ON idempotency_keys (created_at);
@@ -0,0 +1,8 @@
+-- Accounts of the shops, police and repair shops calling on behalf of others.
+-- Kept for parity with Postgres; the functions using them need Postgres.
+CREATE TABLE peer_accounts (
+    username TEXT PRIMARY KEY,
+    role TEXT NOT NULL CHECK (role IN ('shop', 'police', 'repair_shop')),
+    password TEXT NOT NULL, -- bcrypt hash
+    active BOOLEAN NOT NULL DEFAULT TRUE
+);
@@ -0,0 +1,2 @@
+-- Adjusters sign in with a password; adjusters without one cannot sign in
+ALTER TABLE adjusters ADD COLUMN password TEXT; -- bcrypt hash
@@ -0,0 +1,17 @@
+-- Settlements may be zero, e.g. the cash reimbursement of an item valued at nothing.
+-- SQLite cannot change a CHECK constraint, so the table is rebuilt.
+CREATE TABLE claim_settlements_new (
+    id TEXT PRIMARY KEY,
+    claim_uuid TEXT NOT NULL REFERENCES claims(id),
+    kind TEXT NOT NULL CHECK (kind IN ('repair', 'cash', 'deductible', 'salvage')),
+    amount REAL NOT NULL CHECK (amount >= 0),
+    note TEXT,
+    created_by TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+INSERT INTO claim_settlements_new SELECT * FROM claim_settlements;
+DROP TABLE claim_settlements;
+ALTER TABLE claim_settlements_new RENAME TO claim_settlements;
+
+CREATE INDEX idx_claim_settlements_claim_uuid ON claim_settlements (claim_uuid);
@@ -0,0 +1,280 @@
+use sqlx::{Error, Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::data::Claim;
+use crate::peers::PeerCredentials;
+use crate::storage::or_duplicate;
+
+// Claims handling staff of the insurer. Adjusters process the claims assigned to
+// them, supervisors may assign, reassign and process any claim. Every call is
+// made with the adjuster's own credentials; the first supervisor is created with
+// the `supervisor-create` command and creates the other adjusters.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjusterRole {
+    Adjuster,
+    Supervisor,
+}
+
+impl AdjusterRole {
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "adjuster" => Some(AdjusterRole::Adjuster),
+            "supervisor" => Some(AdjusterRole::Supervisor),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(&self) -> &str {
+        match self {
+            AdjusterRole::Adjuster => "adjuster",
+            AdjusterRole::Supervisor => "supervisor",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Adjuster {
+    pub username: String,
+    pub role: String,
+    pub active: bool,
+}
+
+impl Adjuster {
+    pub async fn find(pool: &Pool<Postgres>, username: &str) -> Result<Option<Adjuster>, Error> {
+        sqlx::query_as!(
+            Adjuster,
+            r#"
+            SELECT username, role, active
+            FROM adjusters
+            WHERE username = $1
+            "#,
+            username
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub fn is_supervisor(&self) -> bool {
+        AdjusterRole::from_str(&self.role) == Some(AdjusterRole::Supervisor)
+    }
+}
+
+// Sign in an active adjuster or fail with PermissionDenied
+pub async fn authenticate(pool: &Pool<Postgres>, credentials: &PeerCredentials) -> Result<Adjuster, Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username, role, active, password
+        FROM adjusters
+        WHERE username = $1
+        "#,
+        credentials.username
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match row {
+        Some(row) if row.active && row.password.as_deref().map_or(false, |password| {
+            verify(&credentials.password, password).unwrap_or(false)
+        }) => Ok(Adjuster {
+            username: row.username,
+            role: row.role,
+            active: row.active,
+        }),
+        _ => Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Invalid adjuster credentials.",
+        )))),
+    }
+}
+
+// Load an active adjuster or fail with PermissionDenied
+pub async fn active_adjuster(pool: &Pool<Postgres>, username: &str) -> Result<Adjuster, Error> {
+    match Adjuster::find(pool, username).await? {
+        Some(adjuster) if adjuster.active => Ok(adjuster),
+        _ => Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Unknown or inactive adjuster.",
+        )))),
+    }
+}
+
+// Only the assigned adjuster or a supervisor may process a claim
+pub async fn authorize_processing(
+    pool: &Pool<Postgres>,
+    claim: &Claim,
+    credentials: &PeerCredentials,
+) -> Result<Adjuster, Error> {
+    let adjuster = authenticate(pool, credentials).await?;
+
+    if adjuster.is_supervisor() || claim.assignee.as_deref() == Some(adjuster.username.as_str()) {
+        return Ok(adjuster);
+    }
+
+    Err(Error::Decode(Box::new(std::io::Error::new(
+        std::io::ErrorKind::PermissionDenied,
+        "Claim is not assigned to this adjuster.",
+    ))))
+}
+
+pub async fn create(pool: &Pool<Postgres>, username: &str, role: AdjusterRole, password: &str) -> Result<(), Error> {
+    let password = hash(password, DEFAULT_COST).map_err(|err| {
+        eprintln!("Failed to hash password: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO adjusters (username, role, active, password)
+        VALUES ($1, $2, TRUE, $3)
+        "#,
+        username,
+        role.to_str(),
+        password
+    )
+    .execute(pool)
+    .await
+    .map_err(|err| or_duplicate(err, "Adjuster"))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CreateAdjusterDto {
+    pub username: String,
+    pub password: String,
+    pub role: AdjusterRole,
+    pub created_by: PeerCredentials, // Must be a supervisor
+}
+
+pub async fn adjuster_create(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<(), Error> {
+    // Parse input JSON
+    let dto: CreateAdjusterDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    let creator = authenticate(pool, &dto.created_by).await?;
+    if !creator.is_supervisor() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Only supervisors can create adjusters.",
+        ))));
+    }
+
+    create(pool, &dto.username, dto.role, &dto.password).await
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AssignClaimDto {
+    pub uuid: Uuid,
+    pub adjuster: String, // Adjuster the claim is assigned to
+    pub assigned_by: PeerCredentials,
+    pub priority: Option<i32>,
+}
+
+pub async fn claim_assign(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<(), Error> {
+    // Parse input JSON
+    let dto: AssignClaimDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    let assigner = authenticate(pool, &dto.assigned_by).await?;
+    active_adjuster(pool, &dto.adjuster).await?;
+
+    // Fetch the current assignment
+    let claim = sqlx::query!(
+        r#"
+        SELECT assignee, priority
+        FROM claims
+        WHERE id = $1
+        "#,
+        dto.uuid
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Claim cannot be found.",
+        )))
+    })?;
+
+    // Adjusters may pick up unassigned claims themselves, anything else needs a supervisor
+    let self_pickup = claim.assignee.is_none() && dto.adjuster == assigner.username;
+    if !assigner.is_supervisor() && !self_pickup {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Only supervisors can assign or reassign claims.",
+        ))));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE claims
+        SET assignee = $1, assigned_at = $2, priority = $3
+        WHERE id = $4
+        "#,
+        dto.adjuster,
+        Utc::now().naive_utc(),
+        dto.priority.unwrap_or(claim.priority),
+        dto.uuid
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimQueueDto {
+    pub adjuster: PeerCredentials,
+}
+
+//Index the assignee so each adjuster's queue is a cheap lookup:
+//CREATE INDEX idx_claims_assignee ON claims (assignee);
+pub async fn claim_queue(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let input: ClaimQueueDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    let adjuster = authenticate(pool, &input.adjuster).await?;
+
+    // Open claims of the adjuster, highest priority first, then longest assigned first
+    let claims = sqlx::query_as!(
+        Claim,
+        r#"
+        SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference,
+            damage_category, region, fraud_score, fraud_rules, assignee, assigned_at, priority,
+            status_changed_at, sla_breached_at, rejected_from
+        FROM claims
+        WHERE assignee = $1 AND status IN ('New', 'TheftConfirmed', 'ManualReview', 'UnderAppeal')
+        ORDER BY priority DESC, assigned_at ASC, id
+        "#,
+        adjuster.username
+    )
+    .fetch_all(pool)
+    .await?;
+
+    serde_json::to_string(&claims).map_err(|err| {
+        eprintln!("Failed to serialize claim queue: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
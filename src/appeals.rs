@@ -0,0 +1,265 @@
+use sqlx::{Error, Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use chrono::{Duration, NaiveDateTime, Utc};
+use uuid::Uuid;
+
+use crate::adjusters;
+use crate::config;
+use crate::data::PeerRole;
+use crate::messages::{authorize_claim_access, Caller};
+use crate::peers::PeerCredentials;
+
+// Customers may appeal a rejected claim once, within a window after the rejection.
+// The appeal and its decision are kept apart from the original claim decision.
+
+const DEFAULT_CLAIM_APPEAL_WINDOW_DAYS: i64 = 30;
+
+fn claim_appeal_window() -> Duration {
+    config::days_or("CLAIM_APPEAL_WINDOW_DAYS", DEFAULT_CLAIM_APPEAL_WINDOW_DAYS)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AppealDecision {
+    Upheld,     // The rejection stands
+    Overturned, // The claim goes back to processing
+}
+
+impl AppealDecision {
+    pub fn to_str(&self) -> &str {
+        match self {
+            AppealDecision::Upheld => "upheld",
+            AppealDecision::Overturned => "overturned",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimAppeal {
+    pub id: Uuid,
+    pub claim_uuid: Uuid,
+    pub username: String,
+    pub reason: String,
+    pub filed_at: NaiveDateTime,
+    pub decision: Option<String>,
+    pub decision_reason: Option<String>,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FileAppealDto {
+    pub uuid: Uuid, // Rejected claim
+    pub username: String,
+    pub password: String,
+    pub reason: String,
+}
+
+pub async fn claim_appeal(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let dto: FileAppealDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    // Only the claimant can appeal
+    let caller = Caller {
+        username: dto.username.clone(),
+        role: PeerRole::Claimant,
+        password: Some(dto.password.clone()),
+    };
+    authorize_claim_access(pool, &caller, dto.uuid).await?;
+
+    let claim = sqlx::query!(
+        r#"
+        SELECT status, status_changed_at
+        FROM claims
+        WHERE id = $1
+        "#,
+        dto.uuid
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if claim.status != "Rejected" {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Only rejected claims can be appealed.",
+        ))));
+    }
+
+    let now = Utc::now().naive_utc();
+    if now - claim.status_changed_at > claim_appeal_window() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The appeal window for this claim has closed.",
+        ))));
+    }
+
+    let already_appealed = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM claim_appeals WHERE claim_uuid = $1
+        ) AS "exists!"
+        "#,
+        dto.uuid
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if already_appealed {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "Claim has already been appealed.",
+        ))));
+    }
+
+    let appeal = ClaimAppeal {
+        id: Uuid::new_v4(),
+        claim_uuid: dto.uuid,
+        username: dto.username,
+        reason: dto.reason,
+        filed_at: now,
+        decision: None,
+        decision_reason: None,
+        decided_by: None,
+        decided_at: None,
+    };
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO claim_appeals (id, claim_uuid, username, reason, filed_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        appeal.id,
+        appeal.claim_uuid,
+        appeal.username,
+        appeal.reason,
+        appeal.filed_at
+    )
+    .execute(&mut tx)
+    .await?;
+
+    // Reopen the claim for review
+    sqlx::query!(
+        r#"
+        UPDATE claims
+        SET status = 'UnderAppeal', status_changed_at = $1, sla_breached_at = NULL
+        WHERE id = $2
+        "#,
+        now,
+        appeal.claim_uuid
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    serde_json::to_string(&appeal).map_err(|err| {
+        eprintln!("Failed to serialize appeal: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DecideAppealDto {
+    pub uuid: Uuid,                // Claim under appeal
+    pub adjuster: PeerCredentials, // Must be a supervisor
+    pub decision: AppealDecision,
+    pub reason: String,
+}
+
+pub async fn claim_appeal_decide(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<(), Error> {
+    // Parse input JSON
+    let dto: DecideAppealDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    // Appeals are decided by a supervisor, not by the adjuster who rejected the claim
+    let adjuster = adjusters::authenticate(pool, &dto.adjuster).await?;
+    if !adjuster.is_supervisor() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Only supervisors can decide appeals.",
+        ))));
+    }
+
+    let appeal = sqlx::query!(
+        r#"
+        SELECT claim_appeals.id, claims.status, claims.rejected_from
+        FROM claim_appeals
+        JOIN claims ON claims.id = claim_appeals.claim_uuid
+        WHERE claim_appeals.claim_uuid = $1 AND claim_appeals.decision IS NULL
+        "#,
+        dto.uuid
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "No pending appeal for this claim.",
+        )))
+    })?;
+
+    if appeal.status != "UnderAppeal" {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Claim is not under appeal.",
+        ))));
+    }
+
+    let now = Utc::now().naive_utc();
+    // An overturned claim goes back to where it was rejected; claims rejected
+    // before that was recorded go back to New
+    let (status, rejected_from) = match dto.decision {
+        AppealDecision::Upheld => ("Rejected".to_string(), appeal.rejected_from),
+        AppealDecision::Overturned => (appeal.rejected_from.unwrap_or_else(|| "New".to_string()), None),
+    };
+
+    let mut tx = pool.begin().await?;
+
+    // Record the decision on the appeal
+    sqlx::query!(
+        r#"
+        UPDATE claim_appeals
+        SET decision = $1, decision_reason = $2, decided_by = $3, decided_at = $4
+        WHERE id = $5
+        "#,
+        dto.decision.to_str(),
+        dto.reason,
+        adjuster.username,
+        now,
+        appeal.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE claims
+        SET status = $1, status_changed_at = $2, sla_breached_at = NULL, rejected_from = $3
+        WHERE id = $4
+        "#,
+        status,
+        now,
+        rejected_from,
+        dto.uuid
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
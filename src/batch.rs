@@ -0,0 +1,122 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::Error;
+use std::fmt;
+
+use crate::config;
+use crate::ids::ClientIds;
+use crate::rpc;
+use crate::storage::Backend;
+use crate::workflow;
+
+// Several function calls that succeed or fail together: they run in order in
+// one database transaction, which is committed after the last call and rolled
+// back as soon as a call fails. Calls go through the storage layer, so only the
+// functions served on every backend can be batched (see workflow.rs); claims filed
+// in a batch are checked and fraud scored within the transaction. Functions that
+// need Postgres, such as claim_process, are refused. Calls may choose the ids of
+// the records they create only where BATCH_CLIENT_IDS is set, as for migrations
+// run by the operator; anyone can send a batch.
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchCall {
+    pub function: String,
+    #[serde(default)]
+    pub parameters: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchDto {
+    pub calls: Vec<BatchCall>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchResult {
+    pub results: Vec<Value>, // One per call, null for calls without a result
+}
+
+// Failure of a call, after which nothing of the batch was applied
+#[derive(Debug)]
+pub struct BatchError {
+    pub index: usize,
+    pub function: String,
+    pub source: Error,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.source {
+            Error::Decode(ref source) => source.to_string(),
+            ref err => err.to_string(),
+        };
+        write!(f, "Call {} ({}) failed, batch rolled back: {}", self.index, self.function, message)
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+// Whether batches sent over the API may choose ids
+pub fn client_ids() -> ClientIds {
+    if config::env_or("BATCH_CLIENT_IDS", false) {
+        ClientIds::Allowed
+    } else {
+        ClientIds::Refused
+    }
+}
+
+pub async fn batch(
+    backend: &Backend,
+    args: String, // JSON input as a string
+    client_ids: ClientIds,
+) -> Result<String, Error> {
+    // Parse input JSON
+    let dto: BatchDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    if dto.calls.is_empty() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Batch has no calls.",
+        ))));
+    }
+
+    let transaction = backend.begin().await?;
+    let mut results = Vec::new();
+
+    for (index, call) in dto.calls.into_iter().enumerate() {
+        // Calls allowed to choose the ids of new records can refer to them in later calls
+        let parameters = call.parameters.to_string();
+        let outcome = match workflow::invoke(transaction.storage(), &call.function, parameters, client_ids).await {
+            Some(outcome) => outcome,
+            None => Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Function '{}' cannot be part of a batch, functions that need Postgres cannot be batched.",
+                    call.function
+                ),
+            )))),
+        };
+
+        match outcome {
+            Ok(body) => results.push(rpc::result_value(body)),
+            Err(source) => {
+                transaction.rollback().await?;
+                return Err(Error::Decode(Box::new(BatchError {
+                    index,
+                    function: call.function,
+                    source,
+                })));
+            }
+        }
+    }
+
+    transaction.commit().await?;
+
+    serde_json::to_string(&BatchResult { results }).map_err(|err| {
+        eprintln!("Failed to serialize batch results: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
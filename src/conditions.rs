@@ -0,0 +1,144 @@
+use sqlx::{Error, Executor, Postgres};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use chrono::Duration;
+use std::fmt;
+
+use crate::data::{Claim, Contract};
+use crate::storage::Storage;
+
+// Machine-readable policy conditions of a contract type, evaluated when a claim
+// is filed and again before it is approved.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct PolicyConditions {
+    #[serde(default)]
+    pub excluded_damage_categories: Vec<String>,
+    #[serde(default)]
+    pub covered_regions: Vec<String>, // Empty means covered everywhere
+    pub max_claims_per_year: Option<i64>,
+    pub waiting_period_days: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum ConditionViolation {
+    ExcludedDamageCategory(String),
+    RegionNotCovered(String),
+    RegionRequired,
+    MaxClaimsPerYear { limit: i64 },
+    WaitingPeriod { days: i64 },
+}
+
+impl fmt::Display for ConditionViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConditionViolation::ExcludedDamageCategory(category) => {
+                write!(f, "Condition excluded_damage_categories: '{}' is not covered.", category)
+            }
+            ConditionViolation::RegionNotCovered(region) => {
+                write!(f, "Condition covered_regions: '{}' is not covered.", region)
+            }
+            ConditionViolation::RegionRequired => {
+                write!(f, "Condition covered_regions: the claim must state a region.")
+            }
+            ConditionViolation::MaxClaimsPerYear { limit } => {
+                write!(f, "Condition max_claims_per_year: limit of {} claims reached.", limit)
+            }
+            ConditionViolation::WaitingPeriod { days } => {
+                write!(f, "Condition waiting_period_days: no claims in the first {} days.", days)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConditionViolation {}
+
+impl PolicyConditions {
+    // `claims_in_year` counts the other claims on the contract in the year before the claim date
+    pub fn evaluate(
+        &self,
+        claim: &Claim,
+        contract: &Contract,
+        claims_in_year: i64,
+    ) -> Result<(), ConditionViolation> {
+        if let Some(days) = self.waiting_period_days {
+            if claim.date < contract.start_date + Duration::days(days) {
+                return Err(ConditionViolation::WaitingPeriod { days });
+            }
+        }
+
+        if let Some(category) = &claim.damage_category {
+            if self
+                .excluded_damage_categories
+                .iter()
+                .any(|excluded| excluded.eq_ignore_ascii_case(category))
+            {
+                return Err(ConditionViolation::ExcludedDamageCategory(category.clone()));
+            }
+        }
+
+        if !self.covered_regions.is_empty() {
+            match &claim.region {
+                Some(region) => {
+                    if !self.covered_regions.iter().any(|r| r.eq_ignore_ascii_case(region)) {
+                        return Err(ConditionViolation::RegionNotCovered(region.clone()));
+                    }
+                }
+                None => return Err(ConditionViolation::RegionRequired),
+            }
+        }
+
+        if let Some(limit) = self.max_claims_per_year {
+            if claims_in_year >= limit {
+                return Err(ConditionViolation::MaxClaimsPerYear { limit });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Count the non-rejected claims on the contract in the year before `claim`, excluding itself
+pub async fn claims_in_year<'e, E>(executor: E, claim: &Claim) -> Result<i64, Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM claims
+        WHERE contract_uuid = $1
+            AND id <> $2
+            AND status <> 'Rejected'
+            AND date > $3
+            AND date <= $4
+        "#,
+        claim.contract_uuid,
+        claim.id,
+        claim.date - Duration::days(365),
+        claim.date
+    )
+    .fetch_one(executor)
+    .await
+}
+
+// Evaluate the policy conditions of the contract type the claim's contract was sold under
+pub async fn check_claim(storage: &dyn Storage, claim: &Claim, contract: &Contract) -> Result<(), Error> {
+    let contract_type = storage.find_contract_type(contract.contract_type_uuid).await?.ok_or_else(|| {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract Type could not be found.",
+        )))
+    })?;
+
+    let conditions = match contract_type.policy_conditions {
+        Some(conditions) => conditions,
+        None => return Ok(()),
+    };
+
+    let count = storage.claims_in_year(claim).await?;
+
+    conditions.evaluate(claim, contract, count).map_err(|violation| {
+        eprintln!("Claim {} violates policy conditions: {}", claim.id, violation);
+        Error::Decode(Box::new(violation))
+    })
+}
@@ -0,0 +1,17 @@
+use chrono::Duration;
+use std::env;
+use std::str::FromStr;
+
+// Settings read from the environment, with the default used when a variable is
+// unset or cannot be parsed
+
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+pub fn days_or(name: &str, default_days: i64) -> Duration {
+    Duration::days(env_or(name, default_days))
+}
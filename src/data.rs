@@ -1,12 +1,16 @@
 
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use chrono::NaiveDateTime;
-use serde_json::{from_slice, from_value};
+use serde_json::from_value;
 use uuid::Uuid;
-use sqlx::{Error, Pool, Postgres, query_as};
+use sqlx::{Error, Executor, Pool, Postgres};
+use std::collections::HashMap;
 
+use crate::conditions::PolicyConditions;
 
-#[derive(Serialize, Deserialize, Debug)]
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct ContractType {
     pub id: Uuid,
     pub shop_type: String,
@@ -18,9 +22,105 @@ pub struct ContractType {
     pub active: bool,
     pub min_duration_days: i32,
     pub max_duration_days: i32,
+    #[serde(default)]
+    pub depreciation: Option<Depreciation>,
+    #[serde(default = "first_version")]
+    pub version: i32,
+    #[serde(default)]
+    pub base_uuid: Option<Uuid>, // Id of the first version, shared by all versions
+    #[serde(default)]
+    pub superseded_by: Option<Uuid>,
+    #[serde(default)]
+    pub policy_conditions: Option<PolicyConditions>,
+}
+
+fn first_version() -> i32 {
+    1
+}
+
+// Depreciation rule used to compute the insured value of an item at claim time
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Depreciation {
+    // Linear loss of value over `lifetime_days`, never below `residual_pct` of the price
+    StraightLine { lifetime_days: i64, residual_pct: f32 },
+    // Loses `annual_rate` of the remaining value every year
+    DecliningBalance { annual_rate: f32 },
+    // Percentage of the price by age bracket, brackets sorted by `max_age_days`
+    FixedTable { steps: Vec<DepreciationStep> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DepreciationStep {
+    pub max_age_days: i64,
+    pub pct: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Depreciation {
+    // Rates are fractions of the price and table brackets must be strictly increasing,
+    // `value_at` takes the first bracket the age fits in
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |msg: &str| {
+            Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                msg.to_string(),
+            ))))
+        };
+        let is_rate = |rate: f32| (0.0..=1.0).contains(&rate);
+
+        match self {
+            Depreciation::StraightLine { residual_pct, .. } if !is_rate(*residual_pct) => {
+                invalid("Depreciation residual_pct must be between 0 and 1.")
+            }
+            Depreciation::DecliningBalance { annual_rate } if !is_rate(*annual_rate) => {
+                invalid("Depreciation annual_rate must be between 0 and 1.")
+            }
+            Depreciation::FixedTable { steps } => {
+                if steps.iter().any(|step| !is_rate(step.pct)) {
+                    return invalid("Depreciation pct must be between 0 and 1.");
+                }
+                if steps.windows(2).any(|pair| pair[0].max_age_days >= pair[1].max_age_days) {
+                    return invalid("Depreciation steps must be sorted by strictly increasing max_age_days.");
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn value_at(&self, price: f32, start_date: NaiveDateTime, date: NaiveDateTime) -> f32 {
+        // Claims dated before the contract start are valued at the purchase price
+        let age_days = (date - start_date).num_days().max(0);
+
+        let value = match self {
+            Depreciation::StraightLine { lifetime_days, residual_pct } => {
+                if *lifetime_days <= 0 {
+                    return price * residual_pct;
+                }
+                let used = (age_days as f32 / *lifetime_days as f32).min(1.0);
+                let residual = price * residual_pct;
+                price - (price - residual) * used
+            }
+            Depreciation::DecliningBalance { annual_rate } => {
+                let years = age_days as f32 / 365.0;
+                price * (1.0 - annual_rate).max(0.0).powf(years)
+            }
+            Depreciation::FixedTable { steps } => {
+                // Past the last bracket the item has no insured value left
+                let pct = steps
+                    .iter()
+                    .find(|step| age_days <= step.max_age_days)
+                    .map(|step| step.pct)
+                    .unwrap_or(0.0);
+                price * pct
+            }
+        };
+
+        value.max(0.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Item {
     pub id: i32,
     pub brand: String,
@@ -30,7 +130,7 @@ pub struct Item {
     pub serial_no: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Contract {
     pub id: Uuid,
     pub username: String,
@@ -40,9 +140,19 @@ pub struct Contract {
     pub void: bool,
     pub contract_type_uuid: Uuid,
     pub claim_index: Option<Vec<Uuid>>,
+    #[serde(default)]
+    pub cancelled_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub cancel_reason: Option<String>,
+    #[serde(default)]
+    pub refund: Option<f32>,
+    #[serde(default)]
+    pub expired: bool,
+    #[serde(default)]
+    pub renewed_from: Option<Uuid>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
 pub enum ClaimStatus {
     Unknown,
     New,
@@ -50,6 +160,8 @@ pub enum ClaimStatus {
     Repair,
     Reimbursement,
     TheftConfirmed,
+    ManualReview,
+    UnderAppeal,
 }
 
 impl ClaimStatus {
@@ -60,6 +172,8 @@ impl ClaimStatus {
             "R" => ClaimStatus::Repair,
             "F" => ClaimStatus::Reimbursement,
             "P" => ClaimStatus::TheftConfirmed,
+            "M" => ClaimStatus::ManualReview,
+            "A" => ClaimStatus::UnderAppeal,
             _ => ClaimStatus::Unknown,
         }
     }
@@ -72,11 +186,27 @@ impl ClaimStatus {
             ClaimStatus::Repair => "R",
             ClaimStatus::Reimbursement => "F",
             ClaimStatus::TheftConfirmed => "P",
+            ClaimStatus::ManualReview => "M",
+            ClaimStatus::UnderAppeal => "A",
+        }
+    }
+
+    // Name stored in claims.status; Unknown is never stored
+    pub fn stored_name(&self) -> &str {
+        match self {
+            ClaimStatus::Unknown => "",
+            ClaimStatus::New => "New",
+            ClaimStatus::Rejected => "Rejected",
+            ClaimStatus::Repair => "Repair",
+            ClaimStatus::Reimbursement => "Reimbursement",
+            ClaimStatus::TheftConfirmed => "TheftConfirmed",
+            ClaimStatus::ManualReview => "ManualReview",
+            ClaimStatus::UnderAppeal => "UnderAppeal",
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Claim {
     pub id: Uuid,
     pub contract_uuid: Uuid,
@@ -87,9 +217,50 @@ pub struct Claim {
     pub reimbursable: f32,
     pub repaired: bool,
     pub file_reference: String,
+    #[serde(default)]
+    pub damage_category: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub fraud_score: i32,
+    #[serde(default)]
+    pub fraud_rules: Vec<String>,
+    #[serde(default)]
+    pub assignee: Option<String>,
+    #[serde(default)]
+    pub assigned_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub status_changed_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub sla_breached_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub rejected_from: Option<String>, // Status before the claim was rejected
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// Peer acting on a claim
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerRole {
+    Claimant,
+    Police,
+    RepairShop,
+    Insurer,
+}
+
+impl PeerRole {
+    pub fn to_str(&self) -> &str {
+        match self {
+            PeerRole::Claimant => "claimant",
+            PeerRole::Police => "police",
+            PeerRole::RepairShop => "repair_shop",
+            PeerRole::Insurer => "insurer",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
     pub username: String,
     pub password: String,
@@ -98,87 +269,120 @@ pub struct User {
     pub contract_index: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RepairOrder {
-    //pub id: Uuid,
+    pub id: Uuid,
     pub claim_uuid: Uuid,
-    pub contract_id: Uuid,
-    pub item_id: i32,
+    pub contract_uuid: Uuid,
+    pub item: serde_json::Value, // Serialized Item
     pub ready: bool,
 }
 
+//Contracts are looked up by owner, keep the username column indexed:
+//CREATE INDEX idx_contracts_username ON contracts (username);
 impl User {
     pub async fn contracts(&self, pool: &Pool<Postgres>) -> Result<Vec<Contract>, sqlx::Error> {
-        let mut contracts = Vec::new();
+        Contract::of_user(pool, &self.username).await
+    }
+}
 
-        for contract_id in &self.contract_index {
-            // Fetch the contract data from the database
-            let contract_row = sqlx::query!(
-                "SELECT data FROM contracts WHERE id = $1",
-                contract_id
-            )
-            .fetch_one(pool)
-            .await;
-
-            // If an error occurs, return it immediately
-            let contract_row = match contract_row {
-                Ok(row) => row,
-                Err(e) => {
-                    eprintln!("Failed to fetch contract {}: {:?}", contract_id, e);
-                    return Err(e);
-                }
-            };
-
-            // Parse the contract data from JSON
-            let contract: Contract = match from_slice(&contract_row.data) {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Failed to parse contract {}: {:?}", contract_id, e);
-                    return Err(sqlx::Error::ColumnDecode {
-                        index: "data".into(),
-                        source: Box::new(e),
-                    });
-                }
-            };
+impl Contract {
+    pub async fn of_user<'e, E>(executor: E, username: &str) -> Result<Vec<Contract>, sqlx::Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        // Fetch every contract of the user in one query
+        let rows = sqlx::query!(
+            r#"
+            SELECT 
+                id, 
+                username, 
+                item, 
+                start_date, 
+                end_date, 
+                void, 
+                contract_type_uuid, 
+                ARRAY(SELECT claims.id FROM claims WHERE claims.contract_uuid = contracts.id ORDER BY claims.date) AS "claim_index!", 
+                cancelled_at, 
+                cancel_reason, 
+                refund, 
+                expired, 
+                renewed_from 
+            FROM contracts 
+            WHERE username = $1
+            ORDER BY start_date
+            "#,
+            username
+        )
+        .fetch_all(executor)
+        .await?;
+
+        let mut contracts = Vec::new();
 
-            // Add the contract to the result vector
-            contracts.push(contract);
+        for r in rows {
+            // Parse the item from JSON
+            let item: Item = from_value(r.item).map_err(|e| {
+                eprintln!("Failed to parse item of contract {}: {:?}", r.id, e);
+                Error::Decode(Box::new(e))
+            })?;
+
+            contracts.push(Contract {
+                id: r.id,
+                username: r.username,
+                item,
+                start_date: r.start_date,
+                end_date: r.end_date,
+                void: r.void,
+                contract_type_uuid: r.contract_type_uuid,
+                claim_index: Some(r.claim_index),
+                cancelled_at: r.cancelled_at,
+                cancel_reason: r.cancel_reason,
+                refund: r.refund,
+                expired: r.expired,
+                renewed_from: r.renewed_from,
+            });
         }
 
         Ok(contracts)
     }
 }
 
-//Ensure the claim_id column in the claim table is indexed for efficient lookups:
-//CREATE INDEX idx_claims_claim_id ON claims (claim_id);
+//Claims are looked up by contract, keep the contract_uuid column indexed:
+//CREATE INDEX idx_claims_contract_uuid ON claims (contract_uuid);
 impl Contract {
     pub async fn claims(&self, pool: &Pool<Postgres>) -> Result<Vec<Claim>, Error> {
-        let mut claims = Vec::new();
-
-        for claim_id in &self.claim_index {
-            // Query the database for the claim by its UUID
-            let claim_row = sqlx::query_as!(
-                Claim,
-                r#"
-                SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference
-                FROM claims
-                WHERE id = $1
-                "#,
-                claim_id
-            )
-            .fetch_one(pool)
-            .await;
-
-            match claim_row {
-                Ok(claim) => claims.push(claim),
-                Err(e) => {
-                    eprintln!("Error fetching claim {}: {:?}", claim_id, e);
-                    return Err(e); // Return the error if any claim retrieval fails
-                }
-            }
+        let mut claims = Claim::for_contracts(pool, &[self.id]).await?;
+        Ok(claims.remove(&self.id).unwrap_or_default())
+    }
+}
+
+impl Claim {
+    // Claims of several contracts in one query, grouped by contract
+    pub async fn for_contracts<'e, E>(executor: E, contract_uuids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Claim>>, Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let claims = sqlx::query_as!(
+            Claim,
+            r#"
+            SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference,
+                damage_category, region, fraud_score, fraud_rules, assignee, assigned_at, priority,
+                status_changed_at, sla_breached_at, rejected_from
+            FROM claims
+            WHERE contract_uuid = ANY($1)
+            ORDER BY date
+            "#,
+            contract_uuids
+        )
+        .fetch_all(executor)
+        .await?;
+
+        let mut grouped: HashMap<Uuid, Vec<Claim>> = HashMap::new();
+        for claim in claims {
+            grouped.entry(claim.contract_uuid).or_default().push(claim);
         }
 
-        Ok(claims)
+        Ok(grouped)
     }
 }
 
@@ -202,7 +406,7 @@ impl Contract {
                 password, 
                 first_name, 
                 last_name, 
-                contract_index 
+                ARRAY(SELECT id::TEXT FROM contracts WHERE contracts.username = users.username ORDER BY start_date) AS "contract_index!" 
             FROM users 
             WHERE username = $1
             "#,
@@ -211,32 +415,127 @@ impl Contract {
         .fetch_one(pool)
         .await?;
 
-        // Parse the user data
-        let contract_index: Vec<Uuid> = match serde_json::from_value(row.contract_index) {
-            Ok(index) => index,
-            Err(e) => {
-                eprintln!("Failed to parse contract_index JSON for user {}: {:?}", self.username, e);
-                return Err(Error::Decode(Box::new(e)));
-            }
-        };
-
         Ok(User {
             username: row.username,
             password: row.password,
             first_name: row.first_name,
             last_name: row.last_name,
-            contract_index,
+            contract_index: row.contract_index,
         })
     }
 }
 
+impl ContractType {
+    pub async fn find<'e, E>(executor: E, contract_type_uuid: Uuid) -> Result<Option<ContractType>, Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        // Query the database for a single contract type version
+        let row = sqlx::query!(
+            r#"
+            SELECT 
+                id, 
+                shop_type, 
+                formula_per_day, 
+                max_sum_insured, 
+                theft_insured, 
+                description, 
+                conditions, 
+                active, 
+                min_duration_days, 
+                max_duration_days, 
+                depreciation, 
+                version, 
+                base_uuid, 
+                superseded_by, 
+                policy_conditions 
+            FROM contract_types 
+            WHERE id = $1
+            "#,
+            contract_type_uuid
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        let r = match row {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+
+        // Deserialize the depreciation rule from JSON, if one is configured
+        let depreciation: Option<Depreciation> = match r.depreciation {
+            Some(value) => Some(from_value(value).map_err(|e| {
+                eprintln!("Failed to parse depreciation for contract type {}: {:?}", r.id, e);
+                Error::Decode(Box::new(e))
+            })?),
+            None => None,
+        };
+
+        // Deserialize the structured policy conditions from JSON
+        let policy_conditions: Option<PolicyConditions> = match r.policy_conditions {
+            Some(value) => Some(from_value(value).map_err(|e| {
+                eprintln!("Failed to parse policy conditions for contract type {}: {:?}", r.id, e);
+                Error::Decode(Box::new(e))
+            })?),
+            None => None,
+        };
+
+        Ok(Some(ContractType {
+            id: r.id,
+            shop_type: r.shop_type,
+            formula_per_day: r.formula_per_day,
+            max_sum_insured: r.max_sum_insured,
+            theft_insured: r.theft_insured,
+            description: r.description.unwrap_or_default(),
+            conditions: r.conditions.unwrap_or_default(),
+            active: r.active,
+            min_duration_days: r.min_duration_days,
+            max_duration_days: r.max_duration_days,
+            depreciation,
+            version: r.version,
+            base_uuid: Some(r.base_uuid),
+            superseded_by: r.superseded_by,
+            policy_conditions,
+        }))
+    }
+
+    // Latest version of the contract type family identified by `base_uuid`
+    pub async fn current(pool: &Pool<Postgres>, base_uuid: Uuid) -> Result<Option<ContractType>, Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id
+            FROM contract_types
+            WHERE base_uuid = $1 AND superseded_by IS NULL
+            "#,
+            base_uuid
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        match row {
+            Some(r) => ContractType::find(pool, r.id).await,
+            None => Ok(None),
+        }
+    }
+}
+
+impl Contract {
+    // Contract type version the contract was sold under
+    pub async fn contract_type(&self, pool: &Pool<Postgres>) -> Result<Option<ContractType>, Error> {
+        ContractType::find(pool, self.contract_type_uuid).await
+    }
+}
+
 //Ensure the id column in the contracts table is indexed for fast lookups:
 //CREATE INDEX idx_contracts_uuid ON contracts (id);
 
-impl Claim {
-    pub async fn contract(&self, pool: &Pool<Postgres>) -> Result<Option<Contract>, Error> {
+impl Contract {
+    pub async fn find<'e, E>(executor: E, contract_uuid: Uuid) -> Result<Option<Contract>, Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
         // If the contract_uuid is empty, return None
-        if self.contract_uuid.is_nil() {
+        if contract_uuid.is_nil() {
             return Ok(None);
         }
 
@@ -251,21 +550,27 @@ impl Claim {
                 end_date, 
                 void, 
                 contract_type_uuid, 
-                claim_index 
+                ARRAY(SELECT claims.id FROM claims WHERE claims.contract_uuid = contracts.id ORDER BY claims.date) AS "claim_index!", 
+                cancelled_at, 
+                cancel_reason, 
+                refund, 
+                expired, 
+                renewed_from 
             FROM contracts 
             WHERE id = $1
             "#,
-            self.contract_uuid
+            contract_uuid
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await;
 
         match row {
             Ok(r) => {
-                // Deserialize the `item` and `claim_index` fields from JSON
-                let claim_index: Option<Vec<Uuid>> = r.claim_index
-                    .map(|value| from_value(value).unwrap_or_else(|_| vec![])); // Safely deserialize
-                let item: String = r.item;
+                // Deserialize the `item` field from JSON
+                let item: Item = from_value(r.item).map_err(|e| {
+                    eprintln!("Failed to parse item of contract {}: {:?}", r.id, e);
+                    Error::Decode(Box::new(e))
+                })?;
 
                 Ok(Some(Contract {
                     id: r.id,
@@ -275,21 +580,114 @@ impl Claim {
                     end_date: r.end_date,
                     void: r.void,
                     contract_type_uuid: r.contract_type_uuid,
-                    claim_index,
+                    claim_index: Some(r.claim_index),
+                    cancelled_at: r.cancelled_at,
+                    cancel_reason: r.cancel_reason,
+                    refund: r.refund,
+                    expired: r.expired,
+                    renewed_from: r.renewed_from,
                 }))
             }
             Err(sqlx::Error::RowNotFound) => Ok(None), // Return None if no contract is found
             Err(e) => {
-                eprintln!("Error fetching contract with UUID {}: {:?}", self.contract_uuid, e);
+                eprintln!("Error fetching contract with UUID {}: {:?}", contract_uuid, e);
                 Err(e)
             }
         }
     }
 }
 
+impl Claim {
+    pub async fn contract(&self, pool: &Pool<Postgres>) -> Result<Option<Contract>, Error> {
+        Contract::find(pool, self.contract_uuid).await
+    }
+}
 
+impl Contract {
+    // Several contracts in one query, in no particular order
+    pub async fn find_many<'e, E>(executor: E, contract_uuids: &[Uuid]) -> Result<Vec<Contract>, Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let rows = sqlx::query!(
+            r#"
+            SELECT 
+                id, 
+                username, 
+                item, 
+                start_date, 
+                end_date, 
+                void, 
+                contract_type_uuid, 
+                ARRAY(SELECT claims.id FROM claims WHERE claims.contract_uuid = contracts.id ORDER BY claims.date) AS "claim_index!", 
+                cancelled_at, 
+                cancel_reason, 
+                refund, 
+                expired, 
+                renewed_from 
+            FROM contracts 
+            WHERE id = ANY($1)
+            "#,
+            contract_uuids
+        )
+        .fetch_all(executor)
+        .await?;
 
-/*#[cfg(test)]
+        let mut contracts = Vec::new();
+
+        for r in rows {
+            // Parse the item from JSON
+            let item: Item = from_value(r.item).map_err(|e| {
+                eprintln!("Failed to parse item of contract {}: {:?}", r.id, e);
+                Error::Decode(Box::new(e))
+            })?;
+
+            contracts.push(Contract {
+                id: r.id,
+                username: r.username,
+                item,
+                start_date: r.start_date,
+                end_date: r.end_date,
+                void: r.void,
+                contract_type_uuid: r.contract_type_uuid,
+                claim_index: Some(r.claim_index),
+                cancelled_at: r.cancelled_at,
+                cancel_reason: r.cancel_reason,
+                refund: r.refund,
+                expired: r.expired,
+                renewed_from: r.renewed_from,
+            });
+        }
+
+        Ok(contracts)
+    }
+}
+
+impl Claim {
+    // Several claims in one query, in no particular order
+    pub async fn find_many<'e, E>(executor: E, claim_uuids: &[Uuid]) -> Result<Vec<Claim>, Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as!(
+            Claim,
+            r#"
+            SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference,
+                damage_category, region, fraud_score, fraud_rules, assignee, assigned_at, priority,
+                status_changed_at, sla_breached_at, rejected_from
+            FROM claims
+            WHERE id = ANY($1)
+            "#,
+            claim_uuids
+        )
+        .fetch_all(executor)
+        .await
+    }
+}
+
+
+
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -299,4 +697,93 @@ mod tests {
         assert_eq!(ClaimStatus::New.to_str(), "N");
         assert_eq!(ClaimStatus::from_str("unknown"), ClaimStatus::Unknown);
     }
-}*/
+
+    #[test]
+    fn test_claim_status_stored_names() {
+        // Filters compare against the names written to claims.status
+        assert_eq!(ClaimStatus::from_str("P").stored_name(), "TheftConfirmed");
+        assert_eq!(ClaimStatus::from_str("M").stored_name(), "ManualReview");
+        assert_eq!(ClaimStatus::from_str("A").stored_name(), "UnderAppeal");
+        assert_eq!(ClaimStatus::from_str("j").stored_name(), "Rejected");
+    }
+
+    fn days(n: i64) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap() + chrono::Duration::days(n)
+    }
+
+    fn assert_value(depreciation: &Depreciation, age_days: i64, expected: f32) {
+        let value = depreciation.value_at(1000.0, days(0), days(age_days));
+        assert!((value - expected).abs() < 0.01, "value at {} days is {}, expected {}", age_days, value, expected);
+    }
+
+    #[test]
+    fn test_straight_line_depreciation() {
+        let depreciation = Depreciation::StraightLine { lifetime_days: 1000, residual_pct: 0.2 };
+        assert_value(&depreciation, -10, 1000.0); // Dated before the contract start
+        assert_value(&depreciation, 0, 1000.0);
+        assert_value(&depreciation, 500, 600.0);
+        assert_value(&depreciation, 1000, 200.0);
+        assert_value(&depreciation, 2000, 200.0); // Never below the residual value
+
+        let no_lifetime = Depreciation::StraightLine { lifetime_days: 0, residual_pct: 0.2 };
+        assert_value(&no_lifetime, 10, 200.0);
+    }
+
+    #[test]
+    fn test_declining_balance_depreciation() {
+        let depreciation = Depreciation::DecliningBalance { annual_rate: 0.2 };
+        assert_value(&depreciation, 0, 1000.0);
+        assert_value(&depreciation, 365, 800.0);
+        assert_value(&depreciation, 730, 640.0);
+
+        let total_loss = Depreciation::DecliningBalance { annual_rate: 1.5 };
+        assert_value(&total_loss, 365, 0.0);
+    }
+
+    #[test]
+    fn test_fixed_table_depreciation() {
+        let depreciation = Depreciation::FixedTable {
+            steps: vec![
+                DepreciationStep { max_age_days: 30, pct: 1.0 },
+                DepreciationStep { max_age_days: 365, pct: 0.5 },
+            ],
+        };
+        assert_value(&depreciation, 0, 1000.0);
+        assert_value(&depreciation, 30, 1000.0); // Bracket bounds are inclusive
+        assert_value(&depreciation, 31, 500.0);
+        assert_value(&depreciation, 365, 500.0);
+        assert_value(&depreciation, 366, 0.0); // Past the last bracket
+        assert!(depreciation.validate().is_ok());
+
+        let empty = Depreciation::FixedTable { steps: Vec::new() };
+        assert_value(&empty, 0, 0.0);
+    }
+
+    #[test]
+    fn test_invalid_depreciation() {
+        // Unsorted brackets would shadow each other in `value_at`
+        let unsorted = Depreciation::FixedTable {
+            steps: vec![
+                DepreciationStep { max_age_days: 365, pct: 0.5 },
+                DepreciationStep { max_age_days: 30, pct: 1.0 },
+            ],
+        };
+        assert!(unsorted.validate().is_err());
+
+        let duplicate = Depreciation::FixedTable {
+            steps: vec![
+                DepreciationStep { max_age_days: 30, pct: 1.0 },
+                DepreciationStep { max_age_days: 30, pct: 0.5 },
+            ],
+        };
+        assert!(duplicate.validate().is_err());
+
+        let above_price = Depreciation::FixedTable {
+            steps: vec![DepreciationStep { max_age_days: 30, pct: 1.5 }],
+        };
+        assert!(above_price.validate().is_err());
+
+        assert!(Depreciation::StraightLine { lifetime_days: 1000, residual_pct: -0.1 }.validate().is_err());
+        assert!(Depreciation::DecliningBalance { annual_rate: 2.0 }.validate().is_err());
+    }
+}
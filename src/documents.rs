@@ -0,0 +1,379 @@
+use sqlx::{Error, Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::{env, fs, io, path::PathBuf};
+use uuid::Uuid;
+
+use crate::messages::{authorize_claim_access, Caller};
+
+// Evidence attached to claims (photos, receipts, police reports). The content is
+// kept in a blob store keyed by its SHA-256, the hash is recorded with the claim.
+// Callers sign in and only reach documents of claims they can access.
+
+// Content types accepted on upload; anything else stored earlier is served as
+// application/octet-stream so browsers never render it
+pub const ALLOWED_CONTENT_TYPES: &[&str] = &["application/pdf", "image/heic", "image/jpeg", "image/png", "image/webp"];
+
+pub trait BlobStore: Send + Sync {
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()>;
+    fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+}
+
+// Stores blobs as files under `root`, fanned out by the first two hex digits of the key
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalBlobStore { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let prefix = key.get(..2).unwrap_or("00");
+        self.root.join(prefix).join(key)
+    }
+}
+
+impl BlobStore for LocalBlobStore {
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let path = self.path(key);
+        // Content addressed: an existing blob already holds these bytes
+        if path.exists() {
+            return Ok(());
+        }
+        let dir = match path.parent() {
+            Some(dir) => dir,
+            None => return fs::write(path, bytes),
+        };
+        fs::create_dir_all(dir)?;
+
+        // Write next to the blob and rename it into place, so readers and
+        // concurrent writers of the same key never see a partial file
+        let temp = dir.join(format!(".{}.{}.tmp", key, Uuid::new_v4()));
+        let written = fs::write(&temp, bytes).and_then(|_| fs::rename(&temp, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        written
+    }
+
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(key))
+    }
+}
+
+pub fn blob_store() -> Box<dyn BlobStore> {
+    let root = env::var("BLOB_STORE_PATH").unwrap_or_else(|_| "./blobs".to_string());
+    Box::new(LocalBlobStore::new(root))
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+// Content type a document is served with
+pub fn served_content_type(content_type: &str) -> &str {
+    if ALLOWED_CONTENT_TYPES.contains(&content_type) {
+        content_type
+    } else {
+        "application/octet-stream"
+    }
+}
+
+// Content-Disposition of a download: a plain ASCII file name for old clients
+// and the exact name percent-encoded as in RFC 5987
+pub fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentKind {
+    Photo,
+    Receipt,
+    PoliceReport,
+    RepairReport,
+    Other,
+}
+
+impl DocumentKind {
+    pub fn to_str(&self) -> &str {
+        match self {
+            DocumentKind::Photo => "photo",
+            DocumentKind::Receipt => "receipt",
+            DocumentKind::PoliceReport => "police_report",
+            DocumentKind::RepairReport => "repair_report",
+            DocumentKind::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimDocument {
+    pub id: Uuid,
+    pub claim_uuid: Uuid,
+    pub uploaded_by: String,
+    pub role: String,
+    pub kind: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub sha256: String,
+    pub size: i64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UploadClaimDocumentDto {
+    pub claim_uuid: Uuid,
+    #[serde(flatten)]
+    pub caller: Caller, // Recorded as the uploader
+    pub kind: DocumentKind,
+    pub file_name: String,
+    pub content_type: String,
+    pub content: String, // Base64 encoded file content
+}
+
+pub async fn claim_document_upload(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let dto: UploadClaimDocumentDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    authorize_claim_access(pool, &dto.caller, dto.claim_uuid).await?;
+
+    let content_type = dto.content_type.trim().to_ascii_lowercase();
+    if !ALLOWED_CONTENT_TYPES.contains(&content_type.as_str()) {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Content type must be one of {}.", ALLOWED_CONTENT_TYPES.join(", ")),
+        ))));
+    }
+
+    let bytes = BASE64.decode(dto.content.as_bytes()).map_err(|err| {
+        eprintln!("Failed to decode document content: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    // Store the content under its hash
+    let sha256 = sha256_hex(&bytes);
+    blob_store().put(&sha256, &bytes).map_err(|err| {
+        eprintln!("Failed to store document {}: {:?}", sha256, err);
+        Error::Io(err)
+    })?;
+
+    let document = ClaimDocument {
+        id: Uuid::new_v4(),
+        claim_uuid: dto.claim_uuid,
+        uploaded_by: dto.caller.username,
+        role: dto.caller.role.to_str().to_string(),
+        kind: dto.kind.to_str().to_string(),
+        file_name: dto.file_name,
+        content_type,
+        sha256,
+        size: bytes.len() as i64,
+        created_at: Utc::now().naive_utc(),
+    };
+
+    // Record the document with the claim
+    sqlx::query!(
+        r#"
+        INSERT INTO claim_documents (id, claim_uuid, uploaded_by, role, kind, file_name, content_type, sha256, size, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+        document.id,
+        document.claim_uuid,
+        document.uploaded_by,
+        document.role,
+        document.kind,
+        document.file_name,
+        document.content_type,
+        document.sha256,
+        document.size,
+        document.created_at
+    )
+    .execute(pool)
+    .await?;
+
+    serde_json::to_string(&document).map_err(|err| {
+        eprintln!("Failed to serialize document: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ListClaimDocumentsDto {
+    pub claim_uuid: Uuid,
+    #[serde(flatten)]
+    pub caller: Caller,
+}
+
+pub async fn claim_document_ls(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let input: ListClaimDocumentsDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    authorize_claim_access(pool, &input.caller, input.claim_uuid).await?;
+
+    let documents = sqlx::query_as!(
+        ClaimDocument,
+        r#"
+        SELECT id, claim_uuid, uploaded_by, role, kind, file_name, content_type, sha256, size, created_at
+        FROM claim_documents
+        WHERE claim_uuid = $1
+        ORDER BY created_at
+        "#,
+        input.claim_uuid
+    )
+    .fetch_all(pool)
+    .await?;
+
+    serde_json::to_string(&documents).map_err(|err| {
+        eprintln!("Failed to serialize documents: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+// Load a document of a claim the caller can access and its content, refusing
+// content that no longer matches the recorded hash
+pub async fn load_document(
+    pool: &Pool<Postgres>,
+    caller: &Caller,
+    uuid: Uuid,
+) -> Result<(ClaimDocument, Vec<u8>), Error> {
+    let document = sqlx::query_as!(
+        ClaimDocument,
+        r#"
+        SELECT id, claim_uuid, uploaded_by, role, kind, file_name, content_type, sha256, size, created_at
+        FROM claim_documents
+        WHERE id = $1
+        "#,
+        uuid
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Document cannot be found.",
+        )))
+    })?;
+
+    authorize_claim_access(pool, caller, document.claim_uuid).await?;
+
+    let bytes = blob_store().get(&document.sha256).map_err(|err| {
+        eprintln!("Failed to read document {}: {:?}", document.sha256, err);
+        Error::Io(err)
+    })?;
+
+    if sha256_hex(&bytes) != document.sha256 {
+        eprintln!("Document {} does not match its recorded hash.", document.id);
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Document content has been tampered with.",
+        ))));
+    }
+
+    Ok((document, bytes))
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GetClaimDocumentDto {
+    pub uuid: Uuid,
+    #[serde(flatten)]
+    pub caller: Caller,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimDocumentContent {
+    #[serde(flatten)]
+    pub document: ClaimDocument,
+    pub content: String, // Base64 encoded file content
+}
+
+pub async fn claim_document_get(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let input: GetClaimDocumentDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    let (document, bytes) = load_document(pool, &input.caller, input.uuid).await?;
+
+    let result = ClaimDocumentContent {
+        document,
+        content: BASE64.encode(bytes),
+    };
+
+    serde_json::to_string(&result).map_err(|err| {
+        eprintln!("Failed to serialize document: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_unlisted_content_types_as_binary() {
+        assert_eq!(served_content_type("image/png"), "image/png");
+        assert_eq!(served_content_type("text/html"), "application/octet-stream");
+        assert_eq!(served_content_type("image/svg+xml"), "application/octet-stream");
+    }
+
+    #[test]
+    fn encodes_file_names_in_content_disposition() {
+        assert_eq!(
+            content_disposition("receipt 01.pdf"),
+            "attachment; filename=\"receipt 01.pdf\"; filename*=UTF-8''receipt%2001.pdf"
+        );
+        assert_eq!(
+            content_disposition("a\"b\r\nSet-Cookie: x.png"),
+            "attachment; filename=\"a_b__Set-Cookie: x.png\"; filename*=UTF-8''a%22b%0D%0ASet-Cookie%3A%20x.png"
+        );
+        assert_eq!(content_disposition("Rechnung ü.pdf"), "attachment; filename=\"Rechnung _.pdf\"; filename*=UTF-8''Rechnung%20%C3%BC.pdf");
+    }
+
+    #[test]
+    fn writes_blobs_atomically() {
+        let root = env::temp_dir().join(format!("blobs-{}", Uuid::new_v4()));
+        let store = LocalBlobStore::new(&root);
+        let key = sha256_hex(b"photo");
+
+        store.put(&key, b"photo").unwrap();
+        store.put(&key, b"photo").unwrap();
+        assert_eq!(store.get(&key).unwrap(), b"photo");
+
+        // Only the blob is left behind, no temporary files
+        let files = fs::read_dir(root.join(&key[..2])).unwrap().count();
+        assert_eq!(files, 1);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+
+// Evaluates the `formula_per_day` of a contract type, e.g. "price * 0.01 + 0.5".
+// Supports numbers, the `price` variable, + - * /, unary minus and parentheses.
+
+#[derive(Debug)]
+pub struct FormulaError(String);
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid premium formula: {}", self.0)
+    }
+}
+
+impl std::error::Error for FormulaError {}
+
+pub fn evaluate(formula: &str, price: f32) -> Result<f32, FormulaError> {
+    let tokens = tokenize(formula)?;
+    let mut parser = Parser { tokens, pos: 0, price: price as f64 };
+
+    let value = parser.expression()?;
+    if parser.pos != parser.tokens.len() {
+        return Err(FormulaError(format!("unexpected input in '{}'", formula)));
+    }
+
+    Ok(value as f32)
+}
+
+// Daily premium of a contract type for an item of the given price
+pub fn premium_per_day(formula: &str, price: f32) -> Result<f32, FormulaError> {
+    let premium = evaluate(formula, price)?;
+    if !premium.is_finite() || premium < 0.0 {
+        return Err(FormulaError(format!("'{}' gives a negative premium", formula)));
+    }
+    Ok(premium)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Price,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LParen,
+    RParen,
+}
+
+fn tokenize(formula: &str) -> Result<Vec<Token>, FormulaError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = formula.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' => {}
+            '+' => tokens.push(Token::Plus),
+            '-' => tokens.push(Token::Minus),
+            '*' => tokens.push(Token::Star),
+            '/' => tokens.push(Token::Slash),
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            '0'..='9' | '.' => {
+                let start = i;
+                while i + 1 < chars.len() && (chars[i + 1].is_ascii_digit() || chars[i + 1] == '.') {
+                    i += 1;
+                }
+                let literal: String = chars[start..=i].iter().collect();
+                let number = literal
+                    .parse()
+                    .map_err(|_| FormulaError(format!("bad number '{}'", literal)))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_ascii_alphabetic() => {
+                let start = i;
+                while i + 1 < chars.len() && (chars[i + 1].is_ascii_alphanumeric() || chars[i + 1] == '_') {
+                    i += 1;
+                }
+                let ident: String = chars[start..=i].iter().collect();
+                match ident.to_lowercase().as_str() {
+                    "price" => tokens.push(Token::Price),
+                    _ => return Err(FormulaError(format!("unknown variable '{}'", ident))),
+                }
+            }
+            _ => return Err(FormulaError(format!("unexpected character '{}'", c))),
+        }
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    price: f64,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<f64, FormulaError> {
+        let mut value = self.term()?;
+        while let Some(op) = self.peek().cloned() {
+            match op {
+                Token::Plus => {
+                    self.pos += 1;
+                    value += self.term()?;
+                }
+                Token::Minus => {
+                    self.pos += 1;
+                    value -= self.term()?;
+                }
+                _ => break,
+            }
+        }
+        Ok(value)
+    }
+
+    // term := factor (('*' | '/') factor)*
+    fn term(&mut self) -> Result<f64, FormulaError> {
+        let mut value = self.factor()?;
+        while let Some(op) = self.peek().cloned() {
+            match op {
+                Token::Star => {
+                    self.pos += 1;
+                    value *= self.factor()?;
+                }
+                Token::Slash => {
+                    self.pos += 1;
+                    let divisor = self.factor()?;
+                    if divisor == 0.0 {
+                        return Err(FormulaError("division by zero".to_string()));
+                    }
+                    value /= divisor;
+                }
+                _ => break,
+            }
+        }
+        Ok(value)
+    }
+
+    // factor := number | 'price' | '-' factor | '(' expression ')'
+    fn factor(&mut self) -> Result<f64, FormulaError> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Price) => Ok(self.price),
+            Some(Token::Minus) => Ok(-self.factor()?),
+            Some(Token::LParen) => {
+                let value = self.expression()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(value),
+                    _ => Err(FormulaError("missing closing parenthesis".to_string())),
+                }
+            }
+            other => Err(FormulaError(format!("unexpected token {:?}", other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3", 0.0).unwrap(), 7.0);
+        assert_eq!(evaluate("10 - 4 / 2", 0.0).unwrap(), 8.0);
+        assert_eq!(evaluate("8 - 2 - 1", 0.0).unwrap(), 5.0);
+        assert_eq!(evaluate("-2 * 3 + 1", 0.0).unwrap(), -5.0);
+    }
+
+    #[test]
+    fn test_parentheses() {
+        assert_eq!(evaluate("(1 + 2) * 3", 0.0).unwrap(), 9.0);
+        assert_eq!(evaluate("-(price - 50) / (2 * 5)", 100.0).unwrap(), -5.0);
+        assert!(evaluate("(1 + 2", 0.0).is_err());
+        assert!(evaluate("1 + 2)", 0.0).is_err());
+    }
+
+    #[test]
+    fn test_price_variable() {
+        assert_eq!(evaluate("price * 0.01 + 0.5", 200.0).unwrap(), 2.5);
+        assert_eq!(evaluate("PRICE", 42.0).unwrap(), 42.0);
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let err = evaluate("value * 0.01", 200.0).unwrap_err();
+        assert!(err.to_string().contains("unknown variable 'value'"));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let err = evaluate("price / (1 - 1)", 200.0).unwrap_err();
+        assert!(err.to_string().contains("division by zero"));
+    }
+
+    #[test]
+    fn test_negative_premium() {
+        assert_eq!(premium_per_day("price * 0.01", 300.0).unwrap(), 3.0);
+        assert!(premium_per_day("0.5 - price", 300.0).is_err());
+    }
+}
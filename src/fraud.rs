@@ -0,0 +1,251 @@
+use sqlx::{Error, PgConnection};
+use serde::{Deserialize, Serialize};
+
+use crate::config::env_or;
+use crate::data::{Claim, Contract};
+use crate::storage::Storage;
+
+// Rules-based fraud scoring for newly filed claims. Each triggered rule adds its
+// weight to the score; claims scoring at or above the review threshold are held
+// in `ManualReview` until an adjuster clears them.
+
+const DEFAULT_REVIEW_THRESHOLD: i32 = 50;
+const DEFAULT_MAX_CLAIMS_PER_CONTRACT: i64 = 3;
+const DEFAULT_EARLY_CLAIM_DAYS: i64 = 7;
+const DEFAULT_MAX_THEFT_CLAIMS_PER_USER: i64 = 2;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudRule {
+    ManyClaimsOnContract,
+    EarlyClaim,
+    SerialNoSharedAcrossUsers,
+    RepeatedTheft,
+}
+
+impl FraudRule {
+    pub fn to_str(&self) -> &str {
+        match self {
+            FraudRule::ManyClaimsOnContract => "many_claims_on_contract",
+            FraudRule::EarlyClaim => "early_claim",
+            FraudRule::SerialNoSharedAcrossUsers => "serial_no_shared_across_users",
+            FraudRule::RepeatedTheft => "repeated_theft",
+        }
+    }
+
+    pub fn weight(&self) -> i32 {
+        match self {
+            FraudRule::ManyClaimsOnContract => 30,
+            FraudRule::EarlyClaim => 25,
+            FraudRule::SerialNoSharedAcrossUsers => 40,
+            FraudRule::RepeatedTheft => 30,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FraudAssessment {
+    pub score: i32,
+    pub rules: Vec<String>,
+    pub manual_review: bool,
+}
+
+// History of the claimant and the item, gathered by the storage backend
+#[derive(Debug, Default)]
+pub struct ClaimHistory {
+    pub claims_on_contract: i64,
+    pub other_users_with_serial_no: i64,
+    pub theft_claims_by_user: i64,
+}
+
+pub fn assess(claim: &Claim, contract: &Contract, history: &ClaimHistory) -> FraudAssessment {
+    let mut triggered = Vec::new();
+
+    if history.claims_on_contract >= env_or("FRAUD_MAX_CLAIMS_PER_CONTRACT", DEFAULT_MAX_CLAIMS_PER_CONTRACT) {
+        triggered.push(FraudRule::ManyClaimsOnContract);
+    }
+    if (claim.date - contract.start_date).num_days() < env_or("FRAUD_EARLY_CLAIM_DAYS", DEFAULT_EARLY_CLAIM_DAYS) {
+        triggered.push(FraudRule::EarlyClaim);
+    }
+    if history.other_users_with_serial_no > 0 {
+        triggered.push(FraudRule::SerialNoSharedAcrossUsers);
+    }
+    if claim.is_theft
+        && history.theft_claims_by_user >= env_or("FRAUD_MAX_THEFT_CLAIMS_PER_USER", DEFAULT_MAX_THEFT_CLAIMS_PER_USER)
+    {
+        triggered.push(FraudRule::RepeatedTheft);
+    }
+
+    let score = triggered.iter().map(|rule| rule.weight()).sum();
+
+    FraudAssessment {
+        score,
+        rules: triggered.iter().map(|rule| rule.to_str().to_string()).collect(),
+        manual_review: score >= env_or("FRAUD_REVIEW_THRESHOLD", DEFAULT_REVIEW_THRESHOLD),
+    }
+}
+
+//Index the item serial number so the cross-user lookup stays cheap:
+//CREATE INDEX idx_contracts_item_serial_no ON contracts ((item->>'serial_no'));
+pub async fn claim_history(conn: &mut PgConnection, claim: &Claim, contract: &Contract) -> Result<ClaimHistory, Error> {
+    // Earlier claims on the same contract
+    let claims_on_contract = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM claims
+        WHERE contract_uuid = $1 AND id <> $2
+        "#,
+        contract.id,
+        claim.id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // Other customers insuring an item with the same serial number; items
+    // without a serial number cannot be told apart
+    let other_users_with_serial_no = if contract.item.serial_no.trim().is_empty() {
+        0
+    } else {
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(DISTINCT username) AS "count!"
+            FROM contracts
+            WHERE item->>'serial_no' = $1 AND username <> $2
+            "#,
+            contract.item.serial_no,
+            contract.username
+        )
+        .fetch_one(&mut *conn)
+        .await?
+    };
+
+    // Theft claims filed by the same customer on any contract
+    let theft_claims_by_user = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM claims
+        JOIN contracts ON contracts.id = claims.contract_uuid
+        WHERE contracts.username = $1 AND claims.is_theft = TRUE AND claims.id <> $2
+        "#,
+        contract.username,
+        claim.id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(ClaimHistory {
+        claims_on_contract,
+        other_users_with_serial_no,
+        theft_claims_by_user,
+    })
+}
+
+pub async fn score_claim(storage: &dyn Storage, claim: &Claim, contract: &Contract) -> Result<FraudAssessment, Error> {
+    let history = storage.claim_history(claim, contract).await?;
+    Ok(assess(claim, contract, &history))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDateTime};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn date(day: i64) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap() + Duration::days(day)
+    }
+
+    fn contract() -> Contract {
+        serde_json::from_value(json!({
+            "id": Uuid::nil(),
+            "username": "jdoe",
+            "item": { "id": 1, "brand": "Gazelle", "model": "Ultimate", "price": 1500.0, "description": "E-bike", "serial_no": "GZ-1234" },
+            "start_date": date(0),
+            "end_date": date(365),
+            "void": false,
+            "contract_type_uuid": Uuid::nil(),
+            "claim_index": null,
+        }))
+        .unwrap()
+    }
+
+    fn claim(day: i64, is_theft: bool) -> Claim {
+        serde_json::from_value(json!({
+            "id": Uuid::nil(),
+            "contract_uuid": Uuid::nil(),
+            "date": date(day),
+            "description": "Broken frame",
+            "is_theft": is_theft,
+            "status": "New",
+            "reimbursable": 0.0,
+            "repaired": false,
+            "file_reference": "",
+        }))
+        .unwrap()
+    }
+
+    fn rules(assessment: &FraudAssessment) -> Vec<&str> {
+        assessment.rules.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn test_clean_claim() {
+        let assessment = assess(&claim(100, true), &contract(), &ClaimHistory::default());
+        assert_eq!(assessment.score, 0);
+        assert!(assessment.rules.is_empty());
+        assert!(!assessment.manual_review);
+    }
+
+    #[test]
+    fn test_many_claims_on_contract() {
+        let history = ClaimHistory { claims_on_contract: 3, ..Default::default() };
+        let assessment = assess(&claim(100, false), &contract(), &history);
+        assert_eq!(rules(&assessment), vec!["many_claims_on_contract"]);
+        assert_eq!(assessment.score, 30);
+
+        let history = ClaimHistory { claims_on_contract: 2, ..Default::default() };
+        assert!(assess(&claim(100, false), &contract(), &history).rules.is_empty());
+    }
+
+    #[test]
+    fn test_early_claim() {
+        let assessment = assess(&claim(6, false), &contract(), &ClaimHistory::default());
+        assert_eq!(rules(&assessment), vec!["early_claim"]);
+        assert_eq!(assessment.score, 25);
+
+        assert!(assess(&claim(7, false), &contract(), &ClaimHistory::default()).rules.is_empty());
+    }
+
+    #[test]
+    fn test_serial_no_shared_across_users() {
+        let history = ClaimHistory { other_users_with_serial_no: 1, ..Default::default() };
+        let assessment = assess(&claim(100, false), &contract(), &history);
+        assert_eq!(rules(&assessment), vec!["serial_no_shared_across_users"]);
+        assert_eq!(assessment.score, 40);
+    }
+
+    #[test]
+    fn test_repeated_theft() {
+        let history = ClaimHistory { theft_claims_by_user: 2, ..Default::default() };
+        let assessment = assess(&claim(100, true), &contract(), &history);
+        assert_eq!(rules(&assessment), vec!["repeated_theft"]);
+        assert_eq!(assessment.score, 30);
+
+        // Only theft claims count as repeated thefts
+        assert!(assess(&claim(100, false), &contract(), &history).rules.is_empty());
+    }
+
+    #[test]
+    fn test_review_threshold() {
+        // 40 points stay below the threshold of 50
+        let history = ClaimHistory { other_users_with_serial_no: 1, ..Default::default() };
+        assert!(!assess(&claim(100, false), &contract(), &history).manual_review);
+
+        // 30 + 25 points reach it
+        let history = ClaimHistory { claims_on_contract: 3, ..Default::default() };
+        let assessment = assess(&claim(1, false), &contract(), &history);
+        assert_eq!(assessment.score, 55);
+        assert!(assessment.manual_review);
+    }
+}
@@ -0,0 +1,211 @@
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde_json::Value;
+use sqlx::Error;
+use std::collections::BTreeSet;
+use std::future::{ready, Future, Ready};
+
+use crate::config;
+use crate::documents::sha256_hex;
+use crate::rpc;
+use crate::storage::Storage;
+
+// Idempotency keys let callers retry a state-changing call safely: the first
+// call with a key runs and its result is stored, later calls of the same function
+// with the same key and parameters get the stored result back without running
+// again. Failed calls are not stored, so they can be retried with the same key.
+// Keys are given in the Idempotency-Key header or the `idempotency_key` field of
+// the envelope, are scoped by function and caller and are forgotten after the
+// retention window. A key reserved by a call that never finished, e.g. because the server
+// stopped, is freed once its lease runs out; the lease must outlast the slowest call.
+
+pub const HEADER: &str = "Idempotency-Key";
+
+const DEFAULT_IDEMPOTENCY_KEY_RETENTION_HOURS: i64 = 24;
+const DEFAULT_IDEMPOTENCY_KEY_LEASE_SECS: i64 = 300;
+const MAX_KEY_LENGTH: usize = 255;
+
+// Keys given with functions that change nothing are ignored
+pub fn is_mutating(function: &str) -> bool {
+    rpc::registered(function).map_or(false, |function| function.mutating)
+}
+
+// A call made with an idempotency key, and its result once it succeeded
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    pub request_hash: String, // Function and parameters the key was first used with
+    pub response: Option<String>, // None while the call runs
+    pub created_at: NaiveDateTime,
+}
+
+// Idempotency-Key header of a request, if any
+pub struct IdempotencyKey(pub Option<String>);
+
+impl FromRequest for IdempotencyKey {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(IdempotencyKey(header_key(req))))
+    }
+}
+
+pub fn header_key(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+}
+
+fn retention() -> Duration {
+    Duration::hours(config::env_or("IDEMPOTENCY_KEY_RETENTION_HOURS", DEFAULT_IDEMPOTENCY_KEY_RETENTION_HOURS))
+}
+
+fn lease() -> Duration {
+    Duration::seconds(config::env_or("IDEMPOTENCY_KEY_LEASE_SECS", DEFAULT_IDEMPOTENCY_KEY_LEASE_SECS))
+}
+
+// Who makes a call, from the credentials in its parameters: the usernames given
+// with a password, anywhere in the parameters. Calls without any share a scope.
+pub fn caller(args: &str) -> String {
+    fn usernames(value: &Value, found: &mut BTreeSet<String>) {
+        match value {
+            Value::Object(object) => {
+                if let (Some(Value::String(username)), true) = (object.get("username"), object.contains_key("password")) {
+                    found.insert(username.clone());
+                }
+                object.values().for_each(|value| usernames(value, found));
+            }
+            Value::Array(array) => array.iter().for_each(|value| usernames(value, found)),
+            _ => {}
+        }
+    }
+
+    let mut found = BTreeSet::new();
+    if let Ok(args) = serde_json::from_str::<Value>(args) {
+        usernames(&args, &mut found);
+    }
+    found.into_iter().collect::<Vec<_>>().join("\n")
+}
+
+// Key a call is recorded under; the same key given with another function, or by
+// another caller, is another key
+pub fn scoped_key(function: &str, caller: &str, key: &str) -> String {
+    format!("{}:{}:{}", function, sha256_hex(caller.as_bytes()), key)
+}
+
+// Hash of the function and its parameters, with JSON object fields in a fixed order
+fn request_hash(function: &str, args: &str) -> String {
+    let args = serde_json::from_str::<Value>(args)
+        .map(|args| args.to_string())
+        .unwrap_or_else(|_| args.to_string());
+    sha256_hex(format!("{}\n{}", function, args).as_bytes())
+}
+
+fn error(kind: std::io::ErrorKind, message: &str) -> Error {
+    Error::Decode(Box::new(std::io::Error::new(kind, message)))
+}
+
+// Run a function once per idempotency key; without a key, or for functions that
+// do not change state, it simply runs
+pub async fn once<F, Fut>(
+    storage: &dyn Storage,
+    key: Option<&str>,
+    function: &str,
+    args: String,
+    run: F,
+) -> Result<String, Error>
+where
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = Result<String, Error>>,
+{
+    let key = match key {
+        Some(key) if is_mutating(function) => key,
+        _ => return run(args).await,
+    };
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(error(
+            std::io::ErrorKind::InvalidInput,
+            "Idempotency key must have between 1 and 255 characters.",
+        ));
+    }
+
+    let now = Utc::now().naive_utc();
+    storage.purge_idempotency_keys(now - retention(), now - lease()).await?;
+
+    let key = scoped_key(function, &caller(&args), key);
+    let key = key.as_str();
+    let record = IdempotencyRecord {
+        key: key.to_string(),
+        request_hash: request_hash(function, &args),
+        response: None,
+        created_at: now,
+    };
+    if !storage.reserve_idempotency_key(&record).await? {
+        return match storage.find_idempotency_key(key).await? {
+            Some(stored) if stored.request_hash != record.request_hash => Err(error(
+                std::io::ErrorKind::InvalidInput,
+                "Idempotency key was already used with different parameters.",
+            )),
+            Some(IdempotencyRecord { response: Some(response), .. }) => Ok(response),
+            _ => Err(error(
+                std::io::ErrorKind::AlreadyExists,
+                "A call with this idempotency key is still in progress.",
+            )),
+        };
+    }
+
+    let result = run(args).await;
+
+    // The result stands even when it cannot be recorded
+    let recorded = match result {
+        Ok(ref response) => storage.complete_idempotency_key(key, response).await,
+        Err(_) => storage.release_idempotency_key(key).await,
+    };
+    if let Err(err) = recorded {
+        eprintln!("Failed to record idempotency key {}: {:?}", key, err);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_parameters_independent_of_field_order() {
+        assert_eq!(
+            request_hash("claim_file", r#"{"uuid":"a","date":"b"}"#),
+            request_hash("claim_file", r#"{ "date": "b", "uuid": "a" }"#)
+        );
+        assert_ne!(
+            request_hash("claim_file", r#"{"uuid":"a"}"#),
+            request_hash("claim_process", r#"{"uuid":"a"}"#)
+        );
+    }
+
+    #[test]
+    fn scopes_keys_by_function_and_caller() {
+        assert_ne!(scoped_key("claim_file", "", "retry-1"), scoped_key("claim_process", "", "retry-1"));
+        assert_eq!(scoped_key("claim_file", "", "retry-1"), scoped_key("claim_file", "", "retry-1"));
+        assert_ne!(scoped_key("claim_file", "jane", "retry-1"), scoped_key("claim_file", "john", "retry-1"));
+    }
+
+    #[test]
+    fn finds_the_caller_in_the_credentials() {
+        assert_eq!(caller(r#"{"username":"jane","password":"secret"}"#), "jane");
+        assert_eq!(caller(r#"{"uuid":"a","adjuster":{"username":"sam","password":"secret"}}"#), "sam");
+        assert_eq!(caller(r#"{"username":"jane","shop":{"username":"bikes","password":"secret"}}"#), "bikes");
+        assert_eq!(caller(r#"{"uuid":"a"}"#), "");
+    }
+
+    #[test]
+    fn only_changing_functions_are_mutating() {
+        assert!(is_mutating("claim_file"));
+        assert!(is_mutating("batch"));
+        assert!(!is_mutating("claim_ls"));
+        assert!(!is_mutating("no_such_function"));
+    }
+}
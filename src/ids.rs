@@ -0,0 +1,74 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::Error;
+use uuid::Uuid;
+
+// Ids of new contract types, contracts and claims are generated by the server
+// and returned to the caller. Callers may only choose them within a batch, and
+// only where BATCH_CLIENT_IDS is set, to refer to a record created by an earlier
+// call of the batch or to migrate records from another system; ids already taken
+// are rejected as conflicts.
+
+// Whether the caller may choose the ids of the records it creates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientIds {
+    Refused,
+    Allowed, // Calls of a batch, where BATCH_CLIENT_IDS is set
+}
+
+// Id of a new record, the one requested by the caller if allowed
+pub fn assign_id(requested: Option<Uuid>, client_ids: ClientIds) -> Result<Uuid, Error> {
+    match requested {
+        None => Ok(Uuid::new_v4()),
+        Some(id) if client_ids == ClientIds::Allowed && !id.is_nil() => Ok(id),
+        Some(_) if client_ids == ClientIds::Allowed => Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Nil UUID cannot be used as an id.",
+        )))),
+        Some(_) => Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Ids are assigned by the server, leave out the uuid.",
+        )))),
+    }
+}
+
+// Id a new record was stored under
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CreatedResult {
+    pub uuid: Uuid,
+}
+
+pub fn created(uuid: Uuid) -> Result<String, Error> {
+    serde_json::to_string(&CreatedResult { uuid }).map_err(|err| {
+        eprintln!("Failed to serialize results to JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(result: Result<Uuid, Error>) -> Option<std::io::ErrorKind> {
+        match result {
+            Err(Error::Decode(source)) => source.downcast_ref::<std::io::Error>().map(|err| err.kind()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn generates_ids_by_default() {
+        let first = assign_id(None, ClientIds::Refused).unwrap();
+        let second = assign_id(None, ClientIds::Allowed).unwrap();
+        assert_ne!(first, second);
+        assert!(!first.is_nil());
+    }
+
+    #[test]
+    fn accepts_client_ids_only_when_allowed() {
+        let id = Uuid::new_v4();
+        assert_eq!(assign_id(Some(id), ClientIds::Allowed).unwrap(), id);
+        assert_eq!(kind(assign_id(Some(id), ClientIds::Refused)), Some(std::io::ErrorKind::InvalidInput));
+        assert_eq!(kind(assign_id(Some(Uuid::nil()), ClientIds::Allowed)), Some(std::io::ErrorKind::InvalidInput));
+    }
+}
@@ -1,55 +1,114 @@
 use sqlx::{Error, Pool, Postgres};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use schemars::JsonSchema;
 use bcrypt::{hash, verify, DEFAULT_COST};
 use uuid::Uuid;
-
-use crate::data::{Claim, ClaimStatus, Contract, RepairOrder};
-
-
-#[derive(Debug, Serialize, Deserialize)]
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::fmt;
+
+use crate::conditions::{self, PolicyConditions};
+use crate::config;
+use crate::adjusters;
+use crate::fraud;
+use crate::ids::{self, ClientIds};
+use crate::peers::PeerCredentials;
+use crate::settlements::{self, SettlementDto, SettlementKind};
+use crate::data::{Claim, ClaimStatus, Contract, ContractType, Depreciation, RepairOrder};
+use crate::listing::{self, ListQuery, SortKey};
+use crate::storage::{ClaimRepository, ContractRepository, PgStorage, Storage};
+
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ContractTypeResult {
     pub uuid: String,
     #[serde(flatten)]
     pub contract_type: ContractType,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ContractTypeListDto {
+    pub history: Option<Uuid>, // Every version of the family of this contract type
+    #[serde(flatten)]
+    pub query: ListQuery,
+}
+
+const CONTRACT_TYPE_SORT_KEYS: &[SortKey] = &[
+    SortKey { name: "shop_type", column: "shop_type", cast: "TEXT" },
+    SortKey { name: "version", column: "version", cast: "INTEGER" },
+    SortKey { name: "max_sum_insured", column: "max_sum_insured", cast: "REAL" },
+];
+
 //Add an index to the shop_type column for faster filtering:
 //:CREATE INDEX idx_contract_types_shop_type ON contract_types (shop_type);
 pub async fn list_contract_types(
     pool: &Pool<Postgres>,
-    args: Option<String>, // Optional JSON string for filtering
+    args: Option<String>, // Optional JSON string for filtering and paging
 ) -> Result<String, Error> {
-    // Base query
-    let mut query = String::from(
-        "SELECT id AS uuid, shop_type, formula_per_day, max_sum_insured, theft_insured, 
-        description, conditions, active, min_duration_days, max_duration_days 
-        FROM contract_types",
-    );
-    let mut query_params: Vec<&(dyn sqlx::Encode<'_> + sqlx::Type<Postgres>)> = vec![];
-
-    // Add filtering if called as a merchant
-    if let Some(arg) = args {
-        // Deserialize the input JSON
-        let input: serde_json::Value = serde_json::from_str(&arg).map_err(|err| {
-            eprintln!("Failed to parse input JSON: {:?}", err);
-            Error::Decode(Box::new(err))
-        })?;
+    let mut input: ContractTypeListDto = listing::parse_list(args)?;
+    input.query.allow_filters("contract types", &["status", "shop_type"])?;
 
-        if let Some(shop_type) = input.get("shop_type").and_then(|v| v.as_str()) {
-            query.push_str(" WHERE POSITION(UPPER($1) IN UPPER(shop_type)) > 0 AND active = TRUE");
-            query_params.push(&shop_type);
+    let active = match input.query.status.as_deref() {
+        Some("active") => Some(true),
+        Some("inactive") => Some(false),
+        Some(status) => {
+            return Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown contract type status '{}', expected active or inactive.", status),
+            ))))
         }
+        // Merchants looking up a shop type only see what they can sell
+        None if input.query.shop_type.is_some() => Some(true),
+        None => None,
+    };
+
+    // A version history reads oldest first
+    if input.history.is_some() && input.query.sort.is_none() {
+        input.query.sort = Some("version".to_string());
     }
 
-    // Execute the query and fetch results
-    let rows = sqlx::query_as::<_, ContractTypeResult>(&query)
-        .bind_all(query_params) // Binds all parameters dynamically
-        .fetch_all(pool)
-        .await?;
+    let history = input.history;
+    let shop_type = input.query.shop_type.clone();
+    let window = listing::page_ids(pool, &input.query, CONTRACT_TYPE_SORT_KEYS, "contract_types", "id", |filters| {
+        // Only the current version of each contract type unless history is requested
+        match history {
+            Some(uuid) => {
+                filters
+                    .and()
+                    .push("base_uuid = (SELECT base_uuid FROM contract_types WHERE id = ")
+                    .push_bind(uuid)
+                    .push(")");
+            }
+            None => {
+                filters.and().push("superseded_by IS NULL");
+            }
+        }
+        if let Some(ref shop_type) = shop_type {
+            filters
+                .and()
+                .push("POSITION(UPPER(")
+                .push_bind(shop_type.clone())
+                .push(") IN UPPER(shop_type)) > 0");
+        }
+        if let Some(active) = active {
+            filters.and().push("active = ").push_bind(active);
+        }
+    })
+    .await?;
+
+    // Contract types are few, load the versions of the page one by one
+    let mut results = Vec::new();
+    for uuid in &window.ids {
+        if let Some(contract_type) = ContractType::find(pool, *uuid).await? {
+            results.push(ContractTypeResult {
+                uuid: uuid.to_string(),
+                contract_type,
+            });
+        }
+    }
+    let page = window.page(results, |result| result.contract_type.id);
 
     // Serialize results into JSON
-    serde_json::to_string(&rows).map_err(|err| {
+    serde_json::to_string(&page).map_err(|err| {
         eprintln!("Failed to serialize results to JSON: {:?}", err);
         Error::Decode(Box::new(err))
     })
@@ -59,123 +118,207 @@ pub async fn list_contract_types(
 pub async fn create_contract_type(
     pool: &Pool<Postgres>,
     args: String, // JSON input as a string
-) -> Result<(), Error> {
-    // Parse the input JSON into a partial struct to extract UUID
-    let partial: serde_json::Value = serde_json::from_str(&args).map_err(|err| {
-        eprintln!("Failed to parse partial JSON: {:?}", err);
+) -> Result<String, Error> {
+    let (uuid, ct) = parse_new_contract_type(&args, ClientIds::Refused)?;
+    register_contract_type(&PgStorage::new(pool), uuid, ct).await?;
+    ids::created(uuid)
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NewContractTypeDto {
+    #[serde(default)]
+    pub uuid: Option<Uuid>, // Generated; only chosen by the caller within a batch
+    #[serde(flatten)]
+    pub contract_type: ContractType,
+}
+
+// Read the terms of a new contract type from the input JSON, with the id to register it under
+pub fn parse_new_contract_type(args: &str, client_ids: ClientIds) -> Result<(Uuid, ContractType), Error> {
+    let dto: NewContractTypeDto = serde_json::from_str(args).map_err(|err| {
+        eprintln!("Failed to parse ContractType JSON: {:?}", err);
         Error::Decode(Box::new(err))
     })?;
 
-    let uuid: Uuid = partial    //was let uuid: Uuid = partial
+    if let Some(depreciation) = &dto.contract_type.depreciation {
+        depreciation.validate()?;
+    }
 
-        .get("uuid")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| {
-            Error::Decode(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Missing or invalid UUID.",
-            )))
-        })?
-        .parse()
-        .map_err(|err| {
-            eprintln!("Invalid UUID format: {:?}", err);
-            Error::Decode(Box::new(err))
-        })?;
+    Ok((ids::assign_id(dto.uuid, client_ids)?, dto.contract_type))
+}
 
-    // Deserialize the full ContractType from the input JSON
-    let ct: ContractType = serde_json::from_str(&args).map_err(|err| {
-        eprintln!("Failed to parse ContractType JSON: {:?}", err);
+// Register the first version of a contract type under `uuid`
+pub async fn register_contract_type(storage: &dyn Storage, uuid: Uuid, ct: ContractType) -> Result<(), Error> {
+    let ct = ContractType {
+        id: uuid,
+        version: 1,
+        base_uuid: Some(uuid),
+        superseded_by: None,
+        ..ct
+    };
+
+    storage.insert_contract_type(&ct).await
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UpdateContractTypeDto {
+    pub uuid: Uuid, // Current version being replaced
+    #[serde(default)]
+    pub version_uuid: Option<Uuid>, // Id of the new version, generated
+    pub shop_type: Option<String>,
+    pub formula_per_day: Option<String>,
+    pub max_sum_insured: Option<f32>,
+    pub theft_insured: Option<bool>,
+    pub description: Option<String>,
+    pub conditions: Option<String>,
+    pub active: Option<bool>,
+    pub min_duration_days: Option<i32>,
+    pub max_duration_days: Option<i32>,
+    pub depreciation: Option<Depreciation>,
+    pub policy_conditions: Option<PolicyConditions>,
+}
+
+// Contract types are immutable once sold: an update creates a new version and
+// supersedes the current one, contracts stay pinned to the version they reference.
+pub async fn contract_type_update(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse the input JSON
+    let dto: UpdateContractTypeDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
         Error::Decode(Box::new(err))
     })?;
 
-    // Insert the contract type into the database
+    if let Some(depreciation) = &dto.depreciation {
+        depreciation.validate()?;
+    }
+
+    // Fetch the version being replaced
+    let current = ContractType::find(pool, dto.uuid).await?.ok_or_else(|| {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract Type could not be found.",
+        )))
+    })?;
+
+    if current.superseded_by.is_some() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Only the current version of a Contract Type can be updated.",
+        ))));
+    }
+
+    // Unchanged terms are carried over from the current version
+    let next = ContractType {
+        id: ids::assign_id(dto.version_uuid, ClientIds::Refused)?,
+        shop_type: dto.shop_type.unwrap_or(current.shop_type),
+        formula_per_day: dto.formula_per_day.unwrap_or(current.formula_per_day),
+        max_sum_insured: dto.max_sum_insured.unwrap_or(current.max_sum_insured),
+        theft_insured: dto.theft_insured.unwrap_or(current.theft_insured),
+        description: dto.description.unwrap_or(current.description),
+        conditions: dto.conditions.unwrap_or(current.conditions),
+        active: dto.active.unwrap_or(current.active),
+        min_duration_days: dto.min_duration_days.unwrap_or(current.min_duration_days),
+        max_duration_days: dto.max_duration_days.unwrap_or(current.max_duration_days),
+        depreciation: dto.depreciation.or(current.depreciation),
+        version: current.version + 1,
+        base_uuid: current.base_uuid.or(Some(current.id)),
+        superseded_by: None,
+        policy_conditions: dto.policy_conditions.or(current.policy_conditions),
+    };
+
+    let mut tx = pool.begin().await?;
+
+    // Insert the new version
     sqlx::query!(
         r#"
         INSERT INTO contract_types (id, shop_type, formula_per_day, max_sum_insured, theft_insured, 
-            description, conditions, active, min_duration_days, max_duration_days)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            description, conditions, active, min_duration_days, max_duration_days, depreciation,
+            version, base_uuid, policy_conditions)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
         "#,
-        uuid,
-        ct.shop_type,
-        ct.formula_per_day,
-        ct.max_sum_insured,
-        ct.theft_insured,
-        ct.description,
-        ct.conditions,
-        ct.active,
-        ct.min_duration_days,
-        ct.max_duration_days
+        next.id,
+        next.shop_type,
+        next.formula_per_day,
+        next.max_sum_insured,
+        next.theft_insured,
+        next.description,
+        next.conditions,
+        next.active,
+        next.min_duration_days,
+        next.max_duration_days,
+        next.depreciation
+            .as_ref()
+            .map(|d| serde_json::to_value(d).unwrap()), // Serialize the depreciation rule to JSON
+        next.version,
+        next.base_uuid,
+        next.policy_conditions
+            .as_ref()
+            .map(|c| serde_json::to_value(c).unwrap()) // Serialize the policy conditions to JSON
     )
-    .execute(pool)
+    .execute(&mut tx)
     .await?;
 
-    Ok(())
+    // Retire the previous version so it can no longer be sold
+    sqlx::query!(
+        r#"
+        UPDATE contract_types
+        SET superseded_by = $1, active = FALSE
+        WHERE id = $2 AND superseded_by IS NULL
+        "#,
+        next.id,
+        current.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    serde_json::to_string(&next).map_err(|err| {
+        eprintln!("Failed to serialize contract type to JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
 }
 
 pub async fn set_active_contract_type(
     pool: &Pool<Postgres>,
     args: String, // JSON input as a string
 ) -> Result<(), Error> {
-    // Parse the input JSON
-    let req: serde_json::Value = serde_json::from_str(&args).map_err(|err| {
+    let (uuid, active) = parse_activation(&args)?;
+    activate_contract_type(&PgStorage::new(pool), uuid, active).await
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ActivateContractTypeDto {
+    pub uuid: Uuid,
+    pub active: bool,
+}
+
+// Read the contract type id and active flag from the input JSON
+pub fn parse_activation(args: &str) -> Result<(Uuid, bool), Error> {
+    let dto: ActivateContractTypeDto = serde_json::from_str(args).map_err(|err| {
         eprintln!("Failed to parse input JSON: {:?}", err);
         Error::Decode(Box::new(err))
     })?;
 
-    // Extract UUID and Active fields from input
-    let uuid: Uuid = req
-        .get("uuid")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| {
-            Error::Decode(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Missing or invalid UUID.",
-            )))
-        })?
-        .parse()
-        .map_err(|err| {
-            eprintln!("Invalid UUID format: {:?}", err);
-            Error::Decode(Box::new(err))
-        })?;
-
-    let active: bool = req
-        .get("active")
-        .and_then(|v| v.as_bool())
-        .ok_or_else(|| {
-            Error::Decode(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Missing or invalid active flag.",
-            )))
-        })?;
+    Ok((dto.uuid, dto.active))
+}
 
-    // Check if the contract type exists
-    let contract_exists = sqlx::query!(
-        "SELECT 1 FROM contract_types WHERE id = $1",
-        uuid
-    )
-    .fetch_optional(pool)
-    .await?;
+pub async fn activate_contract_type(storage: &dyn Storage, uuid: Uuid, active: bool) -> Result<(), Error> {
+    // Update the active status if the contract type exists
+    let found = storage.set_contract_type_active(uuid, active).await?;
 
-    if contract_exists.is_none() {
+    if !found {
         return Err(Error::Decode(Box::new(std::io::Error::new(
             std::io::ErrorKind::NotFound,
             "Contract Type could not be found.",
         ))));
     }
 
-    // Update the active status
-    sqlx::query!(
-        "UPDATE contract_types SET active = $1 WHERE id = $2",
-        active,
-        uuid
-    )
-    .execute(pool)
-    .await?;
-
     Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ContractResult {
     pub uuid: String,
     #[serde(flatten)]
@@ -183,143 +326,227 @@ pub struct ContractResult {
     pub claims: Option<Vec<Claim>>,
 }
 
+const CONTRACT_SORT_KEYS: &[SortKey] = &[
+    SortKey { name: "start_date", column: "contracts.start_date", cast: "TIMESTAMP" },
+    SortKey { name: "end_date", column: "contracts.end_date", cast: "TIMESTAMP" },
+    SortKey { name: "username", column: "contracts.username", cast: "TEXT" },
+];
+
 pub async fn list_contracts(
     pool: &Pool<Postgres>,
-    args: Option<String>, // JSON input as a string for filtering
+    args: Option<String>, // JSON input as a string for filtering and paging
 ) -> Result<String, Error> {
-    let mut filter_username: Option<String> = None;
-
-    // Parse the input JSON if provided
-    if let Some(arg) = args {
-        let input: serde_json::Value = serde_json::from_str(&arg).map_err(|err| {
-            eprintln!("Failed to parse input JSON: {:?}", err);
-            Error::Decode(Box::new(err))
-        })?;
-
-        if let Some(username) = input.get("username").and_then(|v| v.as_str()) {
-            filter_username = Some(username.to_string());
+    let query: ListQuery = listing::parse_list(args)?;
+    query.allow_filters(
+        "contracts",
+        &["status", "date_from", "date_to", "username", "shop_type", "contract_type_uuid"],
+    )?;
+
+    // Contract status is derived from the cancellation, expiry and void flags
+    let status_condition = match query.status.as_deref() {
+        None => None,
+        Some("active") => Some("contracts.void = FALSE AND contracts.expired = FALSE AND contracts.cancelled_at IS NULL"),
+        Some("void") => Some("contracts.void = TRUE"),
+        Some("expired") => Some("contracts.expired = TRUE"),
+        Some("cancelled") => Some("contracts.cancelled_at IS NOT NULL"),
+        Some(status) => {
+            return Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown contract status '{}', expected active, void, expired or cancelled.", status),
+            ))))
         }
-    }
-
-    let query = if let Some(ref username) = filter_username {
-        // Filter contracts by username
-        sqlx::query_as!(
-            Contract,
-            r#"
-            SELECT id, username, item, start_date, end_date, void, contract_type_uuid, claim_index
-            FROM contracts
-            WHERE username = $1
-            "#,
-            username
-        )
-    } else {
-        // Fetch all contracts
-        sqlx::query_as!(
-            Contract,
-            r#"
-            SELECT id, username, item, start_date, end_date, void, contract_type_uuid, claim_index
-            FROM contracts
-            "#
-        )
     };
 
-    let contracts: Vec<Contract> = query.fetch_all(pool).await?;
+    let window = listing::page_ids(
+        pool,
+        &query,
+        CONTRACT_SORT_KEYS,
+        "contracts JOIN contract_types ON contract_types.id = contracts.contract_type_uuid",
+        "contracts.id",
+        |filters| {
+            if let Some(condition) = status_condition {
+                filters.and().push(condition);
+            }
+            if let Some(from) = query.date_from {
+                filters.and().push("contracts.start_date >= ").push_bind(from);
+            }
+            if let Some(to) = query.date_to {
+                filters.and().push("contracts.start_date <= ").push_bind(to);
+            }
+            if let Some(ref username) = query.username {
+                filters.and().push("contracts.username = ").push_bind(username.clone());
+            }
+            if let Some(ref shop_type) = query.shop_type {
+                filters
+                    .and()
+                    .push("POSITION(UPPER(")
+                    .push_bind(shop_type.clone())
+                    .push(") IN UPPER(contract_types.shop_type)) > 0");
+            }
+            if let Some(contract_type_uuid) = query.contract_type_uuid {
+                filters.and().push("contracts.contract_type_uuid = ").push_bind(contract_type_uuid);
+            }
+        },
+    )
+    .await?;
 
-    // Construct results
-    let mut results = Vec::new();
+    // Fetch the contracts of the page and their claims in one query each
+    let contracts = Contract::find_many(pool, &window.ids).await?;
+    let mut claims_by_contract = Claim::for_contracts(pool, &window.ids).await?;
 
-    for contract in contracts {
-        let claims = if filter_username.is_some() {
-            // Fetch claims associated with the contract
-            sqlx::query_as!(
-                Claim,
-                r#"
-                SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference
-                FROM claims
-                WHERE contract_id = $1
-                "#,
-                contract.id
-            )
-            .fetch_all(pool)
-            .await?
-        } else {
-            Vec::new()
-        };
-
-        results.push(ContractResult {
-            uuid: contract.uuid.to_string(),
+    // Construct results
+    let results: Vec<ContractResult> = contracts
+        .into_iter()
+        .map(|contract| ContractResult {
+            uuid: contract.id.to_string(),
+            claims: Some(claims_by_contract.remove(&contract.id).unwrap_or_default()),
             contract,
-            claims: Some(claims),
-        });
-    }
+        })
+        .collect();
+    let page = window.page(results, |result| result.contract.id);
 
     // Serialize results into JSON
-    serde_json::to_string(&results).map_err(|err| {
+    serde_json::to_string(&page).map_err(|err| {
+        eprintln!("Failed to serialize results to JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GetContractDto {
+    pub uuid: Uuid,
+}
+
+pub async fn get_contract(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let input: GetContractDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    contract_details(&PgStorage::new(pool), input.uuid).await
+}
+
+// A single contract with its claims
+pub async fn contract_details(storage: &dyn Storage, uuid: Uuid) -> Result<String, Error> {
+    let contract = storage.find_contract(uuid).await?.ok_or_else(|| {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract could not be found.",
+        )))
+    })?;
+    let claims = storage.claims_of_contracts(&[uuid]).await?.remove(&uuid).unwrap_or_default();
+
+    let result = ContractResult {
+        uuid: contract.id.to_string(),
+        contract,
+        claims: Some(claims),
+    };
+
+    serde_json::to_string(&result).map_err(|err| {
         eprintln!("Failed to serialize results to JSON: {:?}", err);
         Error::Decode(Box::new(err))
     })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ClaimResult {
     pub uuid: String,
     #[serde(flatten)]
     pub claim: Claim,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimListDto {
+    pub adjuster: Option<PeerCredentials>, // Required; adjusters see their claims, supervisors all
+    #[serde(flatten)]
+    pub query: ListQuery,
+}
+
+const CLAIM_SORT_KEYS: &[SortKey] = &[
+    SortKey { name: "date", column: "claims.date", cast: "TIMESTAMP" },
+    SortKey { name: "priority", column: "claims.priority", cast: "INTEGER" },
+    SortKey { name: "fraud_score", column: "claims.fraud_score", cast: "INTEGER" },
+    SortKey { name: "reimbursable", column: "claims.reimbursable", cast: "REAL" },
+    SortKey { name: "status", column: "claims.status", cast: "TEXT" },
+];
+
 pub async fn list_claims(
     pool: &Pool<Postgres>,
-    args: Option<String>, // Optional JSON input for filtering
+    args: Option<String>, // Optional JSON input for filtering and paging
 ) -> Result<String, Error> {
-    let mut filter_status: Option<ClaimStatus> = None;
-
-    // Parse input JSON for filtering status if provided
-    if let Some(arg) = args {
-        let input: serde_json::Value = serde_json::from_str(&arg).map_err(|err| {
-            eprintln!("Failed to parse input JSON: {:?}", err);
-            Error::Decode(Box::new(err))
-        })?;
-
-        if let Some(status) = input.get("status").and_then(|v| v.as_str()) {
-            filter_status = Some(ClaimStatus::from_str(status));
-        }
-    }
-
-    // Query to fetch claims
-    let query = if let Some(ref status) = filter_status {
-        if *status != ClaimStatus::Unknown {
-            // Filter claims by status
-            sqlx::query_as!(
-                Claim,
-                r#"
-                SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference
-                FROM claims
-                WHERE status = $1
-                "#,
-                status.to_string()
-            )
-        } else {
-            // Fetch all claims if status is "Unknown"
-            sqlx::query_as!(
-                Claim,
-                r#"
-                SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference
-                FROM claims
-                "#
-            )
+    let input: ClaimListDto = listing::parse_list(args)?;
+    let query = input.query;
+    query.allow_filters(
+        "claims",
+        &["status", "date_from", "date_to", "username", "shop_type", "is_theft", "contract_type_uuid"],
+    )?;
+
+    let filter_status = match query.status.as_deref().map(|status| (status, ClaimStatus::from_str(status))) {
+        None => None,
+        Some((status, ClaimStatus::Unknown)) => {
+            return Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown claim status '{}', expected one of N, J, R, F, P, M or A.", status),
+            ))))
         }
-    } else {
-        // Fetch all claims if no status is provided
-        sqlx::query_as!(
-            Claim,
-            r#"
-            SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference
-            FROM claims
-            "#
-        )
+        Some((_, status)) => Some(status),
     };
 
-    let claims: Vec<Claim> = query.fetch_all(pool).await?;
+    // Adjusters only see the claims assigned to them, supervisors see everything
+    let credentials = input.adjuster.ok_or_else(|| {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Adjuster credentials are required.",
+        )))
+    })?;
+    let adjuster = adjusters::authenticate(pool, &credentials).await?;
+    let filter_adjuster = if adjuster.is_supervisor() { None } else { Some(adjuster.username) };
+
+    let window = listing::page_ids(
+        pool,
+        &query,
+        CLAIM_SORT_KEYS,
+        "claims JOIN contracts ON contracts.id = claims.contract_uuid \
+            JOIN contract_types ON contract_types.id = contracts.contract_type_uuid",
+        "claims.id",
+        |filters| {
+            if let Some(ref adjuster) = filter_adjuster {
+                filters.and().push("claims.assignee = ").push_bind(adjuster.clone());
+            }
+            if let Some(status) = filter_status {
+                filters.and().push("claims.status = ").push_bind(status.stored_name().to_string());
+            }
+            if let Some(from) = query.date_from {
+                filters.and().push("claims.date >= ").push_bind(from);
+            }
+            if let Some(to) = query.date_to {
+                filters.and().push("claims.date <= ").push_bind(to);
+            }
+            if let Some(ref username) = query.username {
+                filters.and().push("contracts.username = ").push_bind(username.clone());
+            }
+            if let Some(ref shop_type) = query.shop_type {
+                filters
+                    .and()
+                    .push("POSITION(UPPER(")
+                    .push_bind(shop_type.clone())
+                    .push(") IN UPPER(contract_types.shop_type)) > 0");
+            }
+            if let Some(is_theft) = query.is_theft {
+                filters.and().push("claims.is_theft = ").push_bind(is_theft);
+            }
+            if let Some(contract_type_uuid) = query.contract_type_uuid {
+                filters.and().push("contracts.contract_type_uuid = ").push_bind(contract_type_uuid);
+            }
+        },
+    )
+    .await?;
+
+    let claims = Claim::find_many(pool, &window.ids).await?;
 
     // Construct results
     let results: Vec<ClaimResult> = claims
@@ -329,66 +556,169 @@ pub async fn list_claims(
             claim,
         })
         .collect();
+    let page = window.page(results, |result| result.claim.id);
 
     // Serialize results into JSON
-    serde_json::to_string(&results).map_err(|err| {
+    serde_json::to_string(&page).map_err(|err| {
         eprintln!("Failed to serialize results to JSON: {:?}", err);
         Error::Decode(Box::new(err))
     })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Number of days after the incident within which a claim must be filed
+const DEFAULT_CLAIM_REPORTING_WINDOW_DAYS: i64 = 30;
+
+fn claim_reporting_window() -> Duration {
+    config::days_or("CLAIM_REPORTING_WINDOW_DAYS", DEFAULT_CLAIM_REPORTING_WINDOW_DAYS)
+}
+
+#[derive(Debug)]
+pub enum ClaimValidationError {
+    ContractVoid,
+    ContractCancelled,
+    OutsideContractPeriod,
+    TheftNotInsured,
+    IncidentInFuture,
+    ReportingWindowExceeded { window_days: i64 },
+}
+
+impl fmt::Display for ClaimValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClaimValidationError::ContractVoid => write!(f, "Contract is void."),
+            ClaimValidationError::ContractCancelled => write!(f, "Contract is cancelled."),
+            ClaimValidationError::OutsideContractPeriod => {
+                write!(f, "Claim date is outside of the contract period.")
+            }
+            ClaimValidationError::TheftNotInsured => {
+                write!(f, "Theft is not insured by this contract type.")
+            }
+            ClaimValidationError::IncidentInFuture => write!(f, "Claim date is in the future."),
+            ClaimValidationError::ReportingWindowExceeded { window_days } => write!(
+                f,
+                "Claim must be filed within {} days of the incident.",
+                window_days
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClaimValidationError {}
+
+// Validate a new claim against its contract; `now` is the filing time
+pub fn validate_claim(
+    claim: &Claim,
+    contract: &Contract,
+    contract_type: &ContractType,
+    now: NaiveDateTime,
+) -> Result<(), ClaimValidationError> {
+    if contract.void {
+        return Err(ClaimValidationError::ContractVoid);
+    }
+    if contract.cancelled_at.is_some() {
+        return Err(ClaimValidationError::ContractCancelled);
+    }
+    if claim.date < contract.start_date || claim.date > contract.end_date {
+        return Err(ClaimValidationError::OutsideContractPeriod);
+    }
+    if claim.is_theft && !contract_type.theft_insured {
+        return Err(ClaimValidationError::TheftNotInsured);
+    }
+    if claim.date > now {
+        return Err(ClaimValidationError::IncidentInFuture);
+    }
+
+    let window = claim_reporting_window();
+    if now - claim.date > window {
+        return Err(ClaimValidationError::ReportingWindowExceeded {
+            window_days: window.num_days(),
+        });
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct FileClaimDto {
-    pub uuid: Uuid,
+    #[serde(default)]
+    pub uuid: Option<Uuid>, // Generated; only chosen by the caller within a batch
     pub contract_uuid: Uuid,
     pub date: NaiveDateTime,
     pub description: String,
     pub is_theft: bool,
+    pub damage_category: Option<String>,
+    pub region: Option<String>,
 }
 
 pub async fn file_claim(
     pool: &Pool<Postgres>,
     args: String, // JSON input as a string
-) -> Result<(), Error> {
+) -> Result<String, Error> {
     // Parse input JSON into DTO
     let dto: FileClaimDto = serde_json::from_str(&args).map_err(|err| {
         eprintln!("Failed to parse input JSON: {:?}", err);
         Error::Decode(Box::new(err))
     })?;
 
+    submit_claim(&PgStorage::new(pool), dto, ClientIds::Refused).await
+}
+
+// File a claim on any backend: validated against its contract and policy
+// conditions, then scored for fraud before it is stored
+pub async fn submit_claim(storage: &dyn Storage, dto: FileClaimDto, client_ids: ClientIds) -> Result<String, Error> {
+    let (mut claim, contract) = prepare_claim(storage, dto, Utc::now().naive_utc(), client_ids).await?;
+
+    // Evaluate the policy conditions of the contract type
+    conditions::check_claim(storage, &claim, &contract).await?;
+
+    // Score the claim and hold suspicious ones for manual review
+    let assessment = fraud::score_claim(storage, &claim, &contract).await?;
+    claim.fraud_score = assessment.score;
+    claim.fraud_rules = assessment.rules;
+    if assessment.manual_review {
+        claim.status = "ManualReview".to_string();
+    }
+
+    // Insert the claim into the database
+    storage.insert_claim(&claim).await?;
+    ids::created(claim.id)
+}
+
+// Build a new claim and validate it against its contract; `now` is the filing time
+pub async fn prepare_claim(
+    storage: &dyn Storage,
+    dto: FileClaimDto,
+    now: NaiveDateTime,
+    client_ids: ClientIds,
+) -> Result<(Claim, Contract), Error> {
     // Create the claim
-    let claim_id = dto.uuid;
     let claim = Claim {
-        id: claim_id,
+        id: ids::assign_id(dto.uuid, client_ids)?,
         contract_uuid: dto.contract_uuid,
         date: dto.date,
         description: dto.description,
         is_theft: dto.is_theft,
         status: "New".to_string(), // ClaimStatusNew
-        //reimbursable: 0.0,
-        //repaired: false,
-        //file_reference: String::new(),
+        reimbursable: 0.0,
+        repaired: false,
+        file_reference: String::new(),
+        damage_category: dto.damage_category,
+        region: dto.region,
+        fraud_score: 0,
+        fraud_rules: Vec::new(),
+        assignee: None,
+        assigned_at: None,
+        priority: 0,
+        status_changed_at: Some(now),
+        sla_breached_at: None,
+        rejected_from: None,
     };
 
     // Check if the contract exists
-   let contract = claim.Contract(pool);
-
-   /* let contract = sqlx::query_as!(
-        Contract,
-        r#"
-        SELECT id, username, claim_index
-        FROM contracts
-        WHERE id = $1
-        "#,
-        dto.contract_uuid
-    )
-    .fetch_optional(pool)
-    .await?;*/
-
-    let mut contract = match contract {
+    let contract = match storage.find_contract(claim.contract_uuid).await? {
         Some(c) => c,
         None => {
-            eprintln!("Contract with UUID {} not found.", dto.contract_uuid);
+            eprintln!("Contract with UUID {} not found.", claim.contract_uuid);
             return Err(Error::Decode(Box::new(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
                 "Contract could not be found.",
@@ -396,55 +726,32 @@ pub async fn file_claim(
         }
     };
 
-    // Insert the claim into the database
-    //removed reimbursable, repaired, file_reference & values $7, $8, $9
-    sqlx::query!(
-        r#"
-        INSERT INTO claims (id, contract_uuid, date, description, is_theft, status)  
-        VALUES ($1, $2, $3, $4, $5, $6)
-        "#,
-        claim.id,
-        claim.contract_id,
-        claim.date,
-        claim.description,
-        claim.is_theft,
-        claim.status,
-        //claim.reimbursable,
-        //claim.repaired,
-        //claim.file_reference
-    )
-    .execute(pool)
-    .await?;
-
-    // Update the claim index in the contract
-    contract.claim_index.push(claim.id);
+    // Check the claim against the contract it is filed under
+    let contract_type = storage.find_contract_type(contract.contract_type_uuid).await?.ok_or_else(|| {
+        eprintln!("Contract type with UUID {} not found.", contract.contract_type_uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract Type could not be found.",
+        )))
+    })?;
 
-    // Update the contract in the database
-    sqlx::query!(
-        r#"
-        UPDATE contracts
-        SET claim_index = $1
-        WHERE id = $2
-        "#,
-        serde_json::to_value(contract.claim_index).map_err(|err| {
-            eprintln!("Failed to serialize claim_index to JSON: {:?}", err);
-            Error::Decode(Box::new(err))
-        })?,
-        contract.id
-    )
-    .execute(pool)
-    .await?;
+    validate_claim(&claim, &contract, &contract_type, now).map_err(|err| {
+        eprintln!("Claim {} rejected at filing: {}", claim.id, err);
+        Error::Decode(Box::new(err))
+    })?;
 
-    Ok(())
+    Ok((claim, contract))
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ProcessClaimDto {
     pub uuid: Uuid,
     pub contract_uuid: Uuid,
     pub status: ClaimStatus,
-    pub reimbursable: f32,
+    pub reimbursable: Option<f32>, // Defaults to the claim valuation when omitted
+    pub adjuster: PeerCredentials, // Must be the assigned adjuster or a supervisor
+    pub settlements: Option<Vec<SettlementDto>>,
 }
 
 pub async fn process_claim(
@@ -457,35 +764,139 @@ pub async fn process_claim(
         Error::Decode(Box::new(err))
     })?;
 
+    // The decision, its repair order and its settlements are stored together
+    let storage = PgStorage::new(pool).begin().await?;
+    match decide_and_settle(&storage, input).await {
+        Ok(()) => storage.commit().await,
+        Err(err) => {
+            storage.rollback().await?;
+            Err(err)
+        }
+    }
+}
+
+async fn decide_and_settle(storage: &PgStorage, input: ProcessClaimDto) -> Result<(), Error> {
     // Fetch the claim
-    let mut claim = sqlx::query_as!(
-        Claim,
-        r#"
-        SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference
-        FROM claims
-        WHERE id = $1 AND contract_uuid = $2
-        "#,
-        input.uuid,
-        input.contract_uuid
-    )
-    .fetch_optional(pool)
-    .await?
-    .ok_or_else(|| {
-        eprintln!("Claim not found for UUID {}.", input.uuid);
-        Error::Decode(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "Claim cannot be found.",
-        )))
-    })?;
+    let mut claim = storage
+        .find_claim(input.uuid)
+        .await?
+        .filter(|claim| claim.contract_uuid == input.contract_uuid)
+        .ok_or_else(|| {
+            eprintln!("Claim not found for UUID {}.", input.uuid);
+            Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Claim cannot be found.",
+            )))
+        })?;
+
+    let adjuster = adjusters::authorize_processing(storage.pool(), &claim, &input.adjuster).await?;
 
-    // Validate the status transition
-    if !claim.is_theft && claim.status != "New" && input.status != ClaimStatus::Rejected {
+    // Appeals are decided through claim_appeal_decide
+    if claim.status == "UnderAppeal" {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Claim is under appeal.",
+        ))));
+    }
+
+    // Claims held for fraud review must be cleared back to New or rejected first
+    if claim.status == "ManualReview" {
+        if input.status == ClaimStatus::New {
+            claim.status = "New".to_string();
+            claim.status_changed_at = Some(Utc::now().naive_utc());
+            claim.sla_breached_at = None;
+            return storage.update_claim(&claim).await;
+        }
+        if input.status != ClaimStatus::Rejected {
+            return Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Claim is held for manual fraud review.",
+            ))));
+        }
+    }
+
+    // Approved claims are settled in installments by repeating their current status
+    let settling = (claim.status == "Repair" && input.status == ClaimStatus::Repair)
+        || (claim.status == "Reimbursement" && input.status == ClaimStatus::Reimbursement);
+    if settling {
+        let lines = input.settlements.clone().unwrap_or_default();
+        if lines.is_empty() {
+            return Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "No settlements to add.",
+            ))));
+        }
+        let insured_value = claim_value(storage, &claim).await?.insured_value;
+        claim.reimbursable = settlements::add_settlements(storage, claim.id, &lines, &adjuster.username, insured_value).await?;
+        return storage.update_claim(&claim).await;
+    }
+
+    // Re-evaluate the policy conditions before approving the claim
+    if input.status != ClaimStatus::Rejected {
+        let contract = storage.find_contract(claim.contract_uuid).await?.ok_or_else(|| {
+            eprintln!("Contract with UUID {} not found.", input.contract_uuid);
+            Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Contract could not be found.",
+            )))
+        })?;
+        conditions::check_claim(storage, &claim, &contract).await?;
+    }
+
+    let previous_status = claim.status.clone();
+    decide_claim(storage, &mut claim, input.status).await?;
+
+    // Settle the approved claim, up to the insured value of the item
+    match input.status {
+        ClaimStatus::Repair => {
+            // Record any repair cost or deductible known up front
+            if let Some(lines) = input.settlements.as_ref().filter(|lines| !lines.is_empty()) {
+                let insured_value = claim_value(storage, &claim).await?.insured_value;
+                claim.reimbursable = settlements::add_settlements(storage, claim.id, lines, &adjuster.username, insured_value).await?;
+            }
+        }
+
+        ClaimStatus::Reimbursement => {
+            // Without explicit line items the claim is settled in cash
+            let insured_value = claim_value(storage, &claim).await?.insured_value;
+            let lines = match input.settlements.clone() {
+                Some(lines) if !lines.is_empty() => lines,
+                _ => vec![SettlementDto {
+                    kind: SettlementKind::Cash,
+                    amount: match input.reimbursable {
+                        Some(amount) => amount,
+                        None => insured_value,
+                    },
+                    note: None,
+                }],
+            };
+            claim.reimbursable = settlements::add_settlements(storage, claim.id, &lines, &adjuster.username, insured_value).await?;
+        }
+
+        _ => {}
+    }
+
+    close_decision(storage, &claim, &previous_status, Utc::now().naive_utc()).await
+}
+
+// Apply the insurer's decision to a claim: validates the status transition and
+// opens a repair order for repairs. The caller settles the amounts and then
+// persists the claim with `close_decision`.
+pub async fn decide_claim(storage: &dyn Storage, claim: &mut Claim, status: ClaimStatus) -> Result<(), Error> {
+    // Validate the status transition; rejections are only reopened by an appeal
+    if claim.status == "Rejected" && status != ClaimStatus::Rejected {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Rejected claims can only be reopened by an appeal.",
+        ))));
+    }
+    if !claim.is_theft && claim.status != "New" && status != ClaimStatus::Rejected {
         return Err(Error::Decode(Box::new(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
             "Cannot change the status of a non-new claim.",
         ))));
     }
-    if claim.is_theft && claim.status == "New" && input.status != ClaimStatus::Rejected {
+    if claim.is_theft && claim.status == "New" && status != ClaimStatus::Rejected {
         return Err(Error::Decode(Box::new(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
             "Theft must first be confirmed by authorities.",
@@ -493,7 +904,8 @@ pub async fn process_claim(
     }
 
     // Update the claim status
-    claim.status = match input.status {
+    let previous_status = claim.status.clone();
+    claim.status = match status {
         ClaimStatus::Repair => "Repair".to_string(),
         ClaimStatus::Reimbursement => "Reimbursement".to_string(),
         ClaimStatus::Rejected => "Rejected".to_string(),
@@ -503,8 +915,7 @@ pub async fn process_claim(
         )))),
     };
 
-    // Process based on the new status
-    match input.status {
+    match status {
         ClaimStatus::Repair => {
             if claim.is_theft {
                 return Err(Error::Decode(Box::new(std::io::Error::new(
@@ -514,13 +925,10 @@ pub async fn process_claim(
             }
 
             //get the contract
-            //let contract = fetch_contract(pool, claim.contract_uuid).await?;
-            let contract = claim.Contract(pool);
-
-            let mut contract = match contract {
+            let contract = match storage.find_contract(claim.contract_uuid).await? {
                 Some(c) => c,
                 None => {
-                    eprintln!("Contract with UUID {} not found.", input.contract_uuid);
+                    eprintln!("Contract with UUID {} not found.", claim.contract_uuid);
                     return Err(Error::Decode(Box::new(std::io::Error::new(
                         std::io::ErrorKind::NotFound,
                         "Contract could not be found.",
@@ -530,69 +938,133 @@ pub async fn process_claim(
 
             // Create a repair order
             let repair_order = RepairOrder {
+                id: Uuid::new_v4(),
                 claim_uuid: claim.id,
-                contract_id: claim.contract_uuid,
-                item_id: contract.item,
+                contract_uuid: claim.contract_uuid,
+                item: serde_json::to_value(&contract.item).unwrap(), // Serialize the item to JSON
                 ready: false,
             };
 
-            // Insert the repair order
-            sqlx::query!(
-                r#"
-                INSERT INTO repair_orders (claim_uuid, contract_uuid, item, ready)
-                VALUES ($1, $2, $3, $4)
-                "#,
-                repair_order.claim_uuid,
-                repair_order.contract_uuid,
-                repair_order.item,
-                repair_order.ready
-            )
-            .execute(pool)
-            .await?;
-        }
-
-        ClaimStatus::Reimbursement => {
-            claim.reimbursable = input.reimbursable;
-
-            // If theft was involved, mark the contract as void
-            if claim.is_theft {
-                sqlx::query!(
-                    r#"
-                    UPDATE contracts
-                    SET void = TRUE
-                    WHERE id = $1
-                    "#,
-                    claim.contract_uuid
-                )
-                .execute(pool)
-                .await?;
-            }
+            storage.insert_repair_order(&repair_order).await?;
         }
 
         ClaimStatus::Rejected => {
             claim.reimbursable = 0.0;
+            if previous_status != "Rejected" {
+                claim.rejected_from = Some(previous_status);
+            }
         }
 
         _ => {}
     }
 
-    // Persist the claim
-    sqlx::query!(
-        r#"
-        UPDATE claims
-        SET status = $1, reimbursable = $2
-        WHERE id = $3
-        "#,
-        claim.status,
-        claim.reimbursable,
-        claim.id
-    )
-    .execute(pool)
-    .await?;
-
     Ok(())
 }
 
+// Persist a decided claim that had `previous_status` before the decision;
+// reimbursed thefts void the contract
+pub async fn close_decision(
+    storage: &dyn Storage,
+    claim: &Claim,
+    previous_status: &str,
+    now: NaiveDateTime,
+) -> Result<(), Error> {
+    // If theft was involved, mark the contract as void
+    if claim.is_theft && claim.status == "Reimbursement" {
+        storage.void_contract(claim.contract_uuid).await?;
+    }
+
+    // Deadlines and the appeal window run from the last change of status
+    let mut claim = claim.clone();
+    if claim.status != previous_status {
+        claim.status_changed_at = Some(now);
+        claim.sla_breached_at = None;
+    }
+
+    storage.update_claim(&claim).await
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimValuationDto {
+    pub uuid: Uuid,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimValuationResult {
+    pub claim_uuid: String,
+    pub contract_uuid: String,
+    pub price: f32,
+    pub age_days: i64,
+    pub insured_value: f32,
+}
+
+// Compute the insured value of the claimed item on the claim date
+pub async fn claim_value(storage: &dyn Storage, claim: &Claim) -> Result<ClaimValuationResult, Error> {
+    let contract = storage.find_contract(claim.contract_uuid).await?.ok_or_else(|| {
+        eprintln!("Contract with UUID {} not found.", claim.contract_uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract could not be found.",
+        )))
+    })?;
+
+    let contract_type = storage.find_contract_type(contract.contract_type_uuid).await?.ok_or_else(|| {
+        eprintln!("Contract type with UUID {} not found.", contract.contract_type_uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract Type could not be found.",
+        )))
+    })?;
+
+    let price = contract.item.price;
+
+    // Without a depreciation rule the item is insured at its purchase price
+    let value = match &contract_type.depreciation {
+        Some(depreciation) => depreciation.value_at(price, contract.start_date, claim.date),
+        None => price,
+    };
+
+    Ok(ClaimValuationResult {
+        claim_uuid: claim.id.to_string(),
+        contract_uuid: contract.id.to_string(),
+        price,
+        age_days: (claim.date - contract.start_date).num_days().max(0),
+        insured_value: value.min(contract_type.max_sum_insured),
+    })
+}
+
+pub async fn claim_valuation(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let input: ClaimValuationDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    value_claim(&PgStorage::new(pool), input).await
+}
+
+pub async fn value_claim(storage: &dyn Storage, input: ClaimValuationDto) -> Result<String, Error> {
+    // Fetch the claim
+    let claim = storage.find_claim(input.uuid).await?.ok_or_else(|| {
+        eprintln!("Claim not found for UUID {}.", input.uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Claim cannot be found.",
+        )))
+    })?;
+
+    let valuation = claim_value(storage, &claim).await?;
+
+    // Serialize the valuation into JSON
+    serde_json::to_string(&valuation).map_err(|err| {
+        eprintln!("Failed to serialize valuation to JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
 /*// Fetch a contract by ID
 async fn fetch_contract(pool: &Pool<Postgres>, contract_uuid: Uuid) -> Result<Contract, Error> {
     sqlx::query_as!(
@@ -608,7 +1080,7 @@ async fn fetch_contract(pool: &Pool<Postgres>, contract_uuid: Uuid) -> Result<Co
     .await
 }*/
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AuthUserDto {
     pub username: String,
     pub password: String,
@@ -628,7 +1100,8 @@ pub async fn auth_user(
     let user = sqlx::query_as!(
         User,
         r#"
-        SELECT username, password, first_name, last_name
+        SELECT username, password, first_name, last_name,
+            ARRAY(SELECT id::TEXT FROM contracts WHERE contracts.username = users.username ORDER BY start_date) AS "contract_index!"
         FROM users
         WHERE username = $1
         "#,
@@ -650,7 +1123,7 @@ pub async fn auth_user(
     })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct UpdatePasswordDto {
     pub username: String,
     pub new_password: String,
@@ -707,7 +1180,7 @@ pub async fn update_password(
     Ok(format!("Password for user '{}' updated successfully.", input.username))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AuthMagicDto {
     pub username: String,
     //pub password: String,
@@ -749,19 +1222,24 @@ pub async fn auth_magic(
     })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct UserResponse {
     pub username: String,
     pub first_name: String,
     pub last_name: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GetUserDto {
+    pub username: String,
+}
+
 pub async fn get_user(
     pool: &Pool<Postgres>,
     args: String, // JSON input as a string
 ) -> Result<Option<String>, Error> {
     // Parse input JSON
-    let input: UserResponse = serde_json::from_str(&args).map_err(|err| {
+    let input: GetUserDto = serde_json::from_str(&args).map_err(|err| {
         eprintln!("Failed to parse input JSON: {:?}", err);
         Error::Decode(Box::new(err))
     })?;
@@ -791,4 +1269,121 @@ pub async fn get_user(
         // Return None if the user does not exist
         Ok(None)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn date(day: i64) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap() + Duration::days(day)
+    }
+
+    // Contract running from day 0 to day 365
+    fn contract(void: bool) -> Contract {
+        serde_json::from_value(json!({
+            "id": Uuid::nil(),
+            "username": "jdoe",
+            "item": { "id": 1, "brand": "Gazelle", "model": "Ultimate", "price": 1500.0, "description": "E-bike", "serial_no": "GZ-1234" },
+            "start_date": date(0),
+            "end_date": date(365),
+            "void": void,
+            "contract_type_uuid": Uuid::nil(),
+            "claim_index": null,
+        }))
+        .unwrap()
+    }
+
+    fn contract_type(theft_insured: bool) -> ContractType {
+        serde_json::from_value(json!({
+            "id": Uuid::nil(),
+            "shop_type": "Bicycle",
+            "formula_per_day": "price * 0.001",
+            "max_sum_insured": 2000.0,
+            "theft_insured": theft_insured,
+            "description": "Bicycle insurance",
+            "conditions": "",
+            "active": true,
+            "min_duration_days": 30,
+            "max_duration_days": 730,
+        }))
+        .unwrap()
+    }
+
+    fn claim(day: i64, is_theft: bool) -> Claim {
+        serde_json::from_value(json!({
+            "id": Uuid::nil(),
+            "contract_uuid": Uuid::nil(),
+            "date": date(day),
+            "description": "Broken frame",
+            "is_theft": is_theft,
+            "status": "New",
+            "reimbursable": 0.0,
+            "repaired": false,
+            "file_reference": "",
+        }))
+        .unwrap()
+    }
+
+    fn validate(claim: &Claim, contract: &Contract, contract_type: &ContractType, now: i64) -> Result<(), ClaimValidationError> {
+        validate_claim(claim, contract, contract_type, date(now))
+    }
+
+    #[test]
+    fn accepts_claims_within_the_contract_and_reporting_window() {
+        assert!(validate(&claim(10, false), &contract(false), &contract_type(false), 12).is_ok());
+        assert!(validate(&claim(10, true), &contract(false), &contract_type(true), 40).is_ok()); // 30 days after
+    }
+
+    #[test]
+    fn rejects_claims_on_void_contracts() {
+        let err = validate(&claim(10, false), &contract(true), &contract_type(false), 12).unwrap_err();
+        assert!(matches!(err, ClaimValidationError::ContractVoid));
+    }
+
+    #[test]
+    fn rejects_claims_on_cancelled_contracts() {
+        let mut cancelled = contract(false);
+        cancelled.cancelled_at = Some(date(5));
+        let err = validate(&claim(10, false), &cancelled, &contract_type(false), 12).unwrap_err();
+        assert!(matches!(err, ClaimValidationError::ContractCancelled));
+    }
+
+    #[test]
+    fn rejects_claims_outside_the_contract_period() {
+        let err = validate(&claim(-1, false), &contract(false), &contract_type(false), 1).unwrap_err();
+        assert!(matches!(err, ClaimValidationError::OutsideContractPeriod));
+        let err = validate(&claim(366, false), &contract(false), &contract_type(false), 367).unwrap_err();
+        assert!(matches!(err, ClaimValidationError::OutsideContractPeriod));
+    }
+
+    #[test]
+    fn rejects_uninsured_thefts() {
+        let err = validate(&claim(10, true), &contract(false), &contract_type(false), 12).unwrap_err();
+        assert!(matches!(err, ClaimValidationError::TheftNotInsured));
+    }
+
+    #[test]
+    fn rejects_incidents_in_the_future() {
+        let err = validate(&claim(10, false), &contract(false), &contract_type(false), 9).unwrap_err();
+        assert!(matches!(err, ClaimValidationError::IncidentInFuture));
+    }
+
+    #[test]
+    fn rejects_late_reports() {
+        let err = validate(&claim(10, false), &contract(false), &contract_type(false), 41).unwrap_err();
+        assert!(matches!(err, ClaimValidationError::ReportingWindowExceeded { window_days: 30 }));
+    }
+
+    #[test]
+    fn refuses_unsorted_depreciation_tables() {
+        let mut args = serde_json::to_value(contract_type(false)).unwrap();
+        args["depreciation"] = json!({
+            "method": "fixed_table",
+            "steps": [{ "max_age_days": 365, "pct": 0.5 }, { "max_age_days": 30, "pct": 1.0 }],
+        });
+        let err = parse_new_contract_type(&args.to_string(), ClientIds::Refused).unwrap_err();
+        assert!(matches!(err, Error::Decode(ref e) if e.to_string().contains("strictly increasing")));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,335 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use chrono::NaiveDateTime;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{Error, Pool, Postgres, QueryBuilder};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::data::Contract;
+use crate::storage::Storage;
+
+// Paging, sorting and filtering shared by the list functions. A page is addressed
+// by an opaque cursor holding the sort value and id of the last entry of the
+// previous page, so deep pages cost the same as the first one.
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ListQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub sort: Option<String>,
+    pub order: Option<SortOrder>,
+    // Filters, each list accepts the ones that apply to its records
+    pub status: Option<String>,
+    pub date_from: Option<NaiveDateTime>,
+    pub date_to: Option<NaiveDateTime>,
+    pub username: Option<String>,
+    pub shop_type: Option<String>,
+    pub is_theft: Option<bool>,
+    pub contract_type_uuid: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64, // Entries matching the filters across all pages
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+// Sortable column of a list; the first key of a list is its default
+pub struct SortKey {
+    pub name: &'static str,
+    pub column: &'static str,
+    pub cast: &'static str, // SQL type the cursor value is cast back to
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    key: String,
+    id: Uuid,
+}
+
+fn invalid_input(message: String) -> Error {
+    Error::Decode(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, message)))
+}
+
+fn encode_cursor(key: String, id: Uuid) -> String {
+    BASE64.encode(serde_json::to_vec(&Cursor { key, id }).unwrap())
+}
+
+fn decode_cursor(cursor: &str) -> Result<Cursor, Error> {
+    BASE64
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| invalid_input("Invalid cursor.".to_string()))
+}
+
+// Parse the arguments of a list function; no arguments list everything
+pub fn parse_list<T: DeserializeOwned + Default>(args: Option<String>) -> Result<T, Error> {
+    match args {
+        Some(arg) if !arg.trim().is_empty() && arg.trim() != "null" => serde_json::from_str(&arg).map_err(|err| {
+            eprintln!("Failed to parse input JSON: {:?}", err);
+            Error::Decode(Box::new(err))
+        }),
+        _ => Ok(T::default()),
+    }
+}
+
+impl ListQuery {
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    fn order(&self) -> SortOrder {
+        self.order.unwrap_or_default()
+    }
+
+    // Index of the requested sort key among the keys of the list
+    fn sort_index(&self, names: &[&str]) -> Result<usize, Error> {
+        match self.sort.as_deref() {
+            None => Ok(0),
+            Some(sort) => names.iter().position(|name| *name == sort).ok_or_else(|| {
+                invalid_input(format!("Cannot sort by '{}', expected one of: {}.", sort, names.join(", ")))
+            }),
+        }
+    }
+
+    // Refuse filters that do not apply to the records of the list
+    pub fn allow_filters(&self, list: &str, supported: &[&str]) -> Result<(), Error> {
+        let given = [
+            ("status", self.status.is_some()),
+            ("date_from", self.date_from.is_some()),
+            ("date_to", self.date_to.is_some()),
+            ("username", self.username.is_some()),
+            ("shop_type", self.shop_type.is_some()),
+            ("is_theft", self.is_theft.is_some()),
+            ("contract_type_uuid", self.contract_type_uuid.is_some()),
+        ];
+
+        match given.iter().find(|(name, set)| *set && !supported.contains(name)) {
+            Some((name, _)) => Err(invalid_input(format!("Filter '{}' does not apply to {}.", name, list))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn in_date_range(&self, date: NaiveDateTime) -> bool {
+        self.date_from.map_or(true, |from| date >= from) && self.date_to.map_or(true, |to| date <= to)
+    }
+
+    // Contracts passing the username, contract type and shop type filters, by id;
+    // each contract type is loaded once
+    pub async fn matching_contracts(
+        &self,
+        storage: &dyn Storage,
+        contracts: Vec<Contract>,
+    ) -> Result<HashMap<Uuid, Contract>, Error> {
+        let mut contracts: Vec<Contract> = contracts
+            .into_iter()
+            .filter(|contract| self.username.as_ref().map_or(true, |username| *username == contract.username))
+            .filter(|contract| self.contract_type_uuid.map_or(true, |uuid| uuid == contract.contract_type_uuid))
+            .collect();
+
+        if let Some(ref shop_type) = self.shop_type {
+            let mut matching = HashSet::new();
+            let contract_types: HashSet<Uuid> = contracts.iter().map(|contract| contract.contract_type_uuid).collect();
+            for uuid in contract_types {
+                if let Some(contract_type) = storage.find_contract_type(uuid).await? {
+                    if matches_shop_type(&contract_type.shop_type, shop_type) {
+                        matching.insert(uuid);
+                    }
+                }
+            }
+            contracts.retain(|contract| matching.contains(&contract.contract_type_uuid));
+        }
+
+        Ok(contracts.into_iter().map(|contract| (contract.id, contract)).collect())
+    }
+}
+
+// Case-insensitive substring match, as the SQL lists filter shop types
+pub fn matches_shop_type(shop_type: &str, filter: &str) -> bool {
+    shop_type.to_uppercase().contains(&filter.to_uppercase())
+}
+
+// Conditions of a list query, joined with AND
+pub struct Filters {
+    builder: QueryBuilder<'static, Postgres>,
+    has_where: bool,
+}
+
+impl Filters {
+    fn new(sql: String) -> Self {
+        Filters {
+            builder: QueryBuilder::new(sql),
+            has_where: false,
+        }
+    }
+
+    // Start a new condition
+    pub fn and(&mut self) -> &mut QueryBuilder<'static, Postgres> {
+        self.builder.push(if self.has_where { " AND " } else { " WHERE " });
+        self.has_where = true;
+        &mut self.builder
+    }
+
+    // Date range on `date_column` and the username, shop type and contract type
+    // filters, for lists joined with contracts and contract_types
+    pub fn contract_filters(&mut self, query: &ListQuery, date_column: &str) {
+        if let Some(from) = query.date_from {
+            self.and().push(format!("{} >= ", date_column)).push_bind(from);
+        }
+        if let Some(to) = query.date_to {
+            self.and().push(format!("{} <= ", date_column)).push_bind(to);
+        }
+        if let Some(ref username) = query.username {
+            self.and().push("contracts.username = ").push_bind(username.clone());
+        }
+        if let Some(ref shop_type) = query.shop_type {
+            self.and()
+                .push("POSITION(UPPER(")
+                .push_bind(shop_type.clone())
+                .push(") IN UPPER(contract_types.shop_type)) > 0");
+        }
+        if let Some(contract_type_uuid) = query.contract_type_uuid {
+            self.and().push("contracts.contract_type_uuid = ").push_bind(contract_type_uuid);
+        }
+    }
+}
+
+// Ids of one page of a list, in page order
+pub struct Window {
+    pub ids: Vec<Uuid>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+impl Window {
+    // Arrange the records loaded for the ids in page order
+    pub fn page<T>(self, records: Vec<T>, id: impl Fn(&T) -> Uuid) -> Page<T> {
+        let mut by_id: HashMap<Uuid, T> = records.into_iter().map(|record| (id(&record), record)).collect();
+
+        Page {
+            items: self.ids.iter().filter_map(|id| by_id.remove(id)).collect(),
+            total: self.total,
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+// Select the ids of one page from `from` (table and joins) with the conditions
+// pushed by `filter`, and count the entries of all pages
+pub async fn page_ids(
+    pool: &Pool<Postgres>,
+    query: &ListQuery,
+    sort_keys: &[SortKey],
+    from: &str,
+    id_column: &str,
+    filter: impl Fn(&mut Filters),
+) -> Result<Window, Error> {
+    let names: Vec<&str> = sort_keys.iter().map(|key| key.name).collect();
+    let sort = &sort_keys[query.sort_index(&names)?];
+    let limit = query.limit();
+    let cursor = query.cursor.as_deref().map(decode_cursor).transpose()?;
+
+    let (direction, comparison) = match query.order() {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+
+    let mut count = Filters::new(format!("SELECT COUNT(*) FROM {}", from));
+    filter(&mut count);
+    let (total,): (i64,) = count.builder.build_query_as().fetch_one(pool).await?;
+
+    let mut page = Filters::new(format!("SELECT {}, ({})::TEXT FROM {}", id_column, sort.column, from));
+    filter(&mut page);
+    if let Some(cursor) = cursor {
+        // Continue after the last entry of the previous page
+        page.and()
+            .push(format!("({}, {}) {} (CAST(", sort.column, id_column, comparison))
+            .push_bind(cursor.key)
+            .push(format!(" AS {}), ", sort.cast))
+            .push_bind(cursor.id)
+            .push(")");
+    }
+    page.builder
+        .push(format!(" ORDER BY {} {2}, {} {2} LIMIT ", sort.column, id_column, direction))
+        .push_bind(limit + 1); // One more entry tells whether a next page exists
+
+    let mut rows: Vec<(Uuid, String)> = page.builder.build_query_as().fetch_all(pool).await?;
+
+    let mut next_cursor = None;
+    if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        next_cursor = rows.last().map(|(id, key)| encode_cursor(key.clone(), *id));
+    }
+
+    Ok(Window {
+        ids: rows.into_iter().map(|(id, _)| id).collect(),
+        total,
+        next_cursor,
+    })
+}
+
+// Page a list assembled from storage records, already filtered. Each entry
+// carries its value for every sort key, as text that sorts like the value.
+pub fn paginate<T>(query: &ListQuery, sort_keys: &[&str], entries: Vec<(Vec<String>, Uuid, T)>) -> Result<Page<T>, Error> {
+    let index = query.sort_index(sort_keys)?;
+    let limit = query.limit() as usize;
+    let cursor = query.cursor.as_deref().map(decode_cursor).transpose()?;
+    let order = query.order();
+
+    let total = entries.len() as i64;
+
+    let mut entries: Vec<(String, Uuid, T)> = entries
+        .into_iter()
+        .map(|(mut keys, id, item)| (keys.swap_remove(index), id, item))
+        .collect();
+    entries.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+    if order == SortOrder::Desc {
+        entries.reverse();
+    }
+
+    if let Some(cursor) = cursor {
+        let after = (cursor.key, cursor.id);
+        entries.retain(|(key, id, _)| match order {
+            SortOrder::Asc => (key.clone(), *id) > after,
+            SortOrder::Desc => (key.clone(), *id) < after,
+        });
+    }
+
+    let has_more = entries.len() > limit;
+    entries.truncate(limit);
+    let next_cursor = if has_more {
+        entries.last().map(|(key, id, _)| encode_cursor(key.clone(), *id))
+    } else {
+        None
+    };
+
+    Ok(Page {
+        items: entries.into_iter().map(|(_, _, item)| item).collect(),
+        total,
+        next_cursor,
+    })
+}
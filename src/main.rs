@@ -1,73 +1,150 @@
-use actix_web::{web, App, HttpServer, Responder};
+use actix_web::{http::{header, StatusCode}, web, App, HttpRequest, HttpResponse, HttpServer};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, Pool, Postgres};
-use std::{collections::HashMap, env};
+use sqlx::{Pool, Postgres};
+use std::env;
+use uuid::Uuid;
 //use actix_cors::Cors;
 
 
 
+mod adjusters;
+mod appeals;
+mod batch;
+mod conditions;
+mod config;
 mod data;
+mod documents;
+mod formula;
+mod fraud;
+mod idempotency;
+mod ids;
 mod shop;
 mod insurance;
+mod listing;
+mod messages;
+mod openapi;
+mod peers;
 mod repairs; // Assume all the previously implemented functions are in this module
 mod police;
+mod rest;
+mod rpc;
+mod scheduler;
+mod search;
+mod settlements;
+mod sla;
+mod storage;
+mod workflow;
+
+use data::PeerRole;
+use messages::Caller;
+use storage::Backend;
+
+// Largest request body accepted, large enough for base64 encoded photos
+const DEFAULT_MAX_REQUEST_BYTES: usize = 16 * 1024 * 1024;
 
 // Define the structure of incoming requests
 #[derive(Debug, Serialize, Deserialize)]
 struct Request {
     function: String,
     parameters: serde_json::Value, // Generic JSON object for parameters
-}
-
-// Map of function handlers
-pub fn get_bc_functions() -> HashMap<&'static str, fn(&Pool<Postgres>, String) -> Result<(), sqlx::Error>> {
-    let mut bc_functions = HashMap::new();
-
-// Insurance Peer
-bc_functions.insert("contract_type_ls", handlers::list_contract_types);
-bc_functions.insert("contract_type_create", handlers::create_contract_type);
-bc_functions.insert("contract_type_set_active", handlers::set_active_contract_type);
-bc_functions.insert("contract_ls", handlers::list_contracts);
-bc_functions.insert("claim_ls", handlers::list_claims);
-bc_functions.insert("claim_file", handlers::file_claim);
-bc_functions.insert("claim_process", handlers::process_claim);
-bc_functions.insert("user_authenticate", handlers::auth_user);
-bc_functions.insert("password_update", handlers::update_password);
-bc_functions.insert("magic_authenticate", handlers::auth_magic);
-bc_functions.insert("user_get_info", handlers::get_user);
-
-// Shop Peer
-bc_functions.insert("contract_create", handlers::create_contract);
-bc_functions.insert("user_create", handlers::create_user);
-
-// Repair Shop Peer
-bc_functions.insert("repair_order_ls", handlers::list_repair_orders);
-bc_functions.insert("repair_order_complete", handlers::complete_repair_order);
-
-// Police Peer
-bc_functions.insert("theft_claim_ls", handlers::list_theft_claims);
-bc_functions.insert("theft_claim_process", handlers::process_theft_claim);
-
-bc_functions
+    #[serde(default)]
+    idempotency_key: Option<String>, // Takes precedence over the Idempotency-Key header
 }
 
 async fn invoke_function(
-    pool: web::Data<PgPool>,
-    request: web::Json<Request>,
-) -> impl Responder {
+    backend: web::Data<Backend>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> HttpResponse {
+    let key = idempotency::header_key(&req);
+
+    let body: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(_) => return rpc::parse_error(),
+    };
+
+    // JSON-RPC 2.0 calls and batches
+    if rpc::is_rpc(&body) {
+        return rpc::handle(&backend, body, key).await;
+    }
+
+    let request: Request = match serde_json::from_value(body) {
+        Ok(request) => request,
+        Err(err) => return HttpResponse::BadRequest().body(format!("Error: Invalid request: {}", err)),
+    };
     let function = request.function.as_str();
     let parameters = request.parameters.to_string();
+    let key = request.idempotency_key.or(key);
 
-    let mut bc_functions = handlers::get_bc_functions();
-
-    match bc_functions.get(function) {
-        Some(handler) => {
-            if let Err(err) = handler(&pool, parameters).await {
-                return format!("Error executing function '{}': {:?}", function, err);
-            }
-            format!("Function '{}' executed successfully.", function)
+    let message = match rpc::invoke_once(&backend, key.as_deref(), function, parameters).await {
+        Some(Ok(_)) => format!("Function '{}' executed successfully.", function),
+        Some(Err(err)) if rest::error_status(&err) == StatusCode::NOT_IMPLEMENTED => {
+            format!("Error: Function '{}' needs the Postgres backend", function)
         }
+        Some(Err(err)) => format!("Error executing function '{}': {:?}", function, err),
         None => format!("Error: Invalid invoke function '{}'", function),
+    };
+    HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(message)
+}
+
+#[derive(Debug, Deserialize)]
+struct DownloadQuery {
+    role: PeerRole,
+}
+
+// Download the raw content of a claim document. The caller signs in with HTTP
+// Basic credentials and gives its role in the query string.
+async fn download_document(
+    backend: web::Data<Backend>,
+    req: HttpRequest,
+    path: web::Path<(Uuid, Uuid)>,
+    query: web::Query<DownloadQuery>,
+) -> HttpResponse {
+    let (claim_uuid, document_uuid) = path.into_inner();
+
+    let caller = match rest::basic_credentials(&req) {
+        Some((username, password)) => Caller {
+            username,
+            role: query.role,
+            password: Some(password),
+        },
+        None => {
+            return HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"claims\""))
+                .body("Credentials are required.")
+        }
+    };
+
+    let pool = match backend.get_ref() {
+        Backend::Postgres(storage) => storage.pool(),
+        Backend::Sqlite(_) => {
+            return HttpResponse::NotImplemented().body("Claim documents are not available on the SQLite backend.")
+        }
+    };
+
+    match documents::load_document(pool, &caller, document_uuid).await {
+        Ok((document, bytes)) if document.claim_uuid == claim_uuid => HttpResponse::Ok()
+            .content_type(documents::served_content_type(&document.content_type))
+            .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+            .insert_header(("X-Content-SHA256", document.sha256.as_str()))
+            .insert_header((header::CONTENT_DISPOSITION, documents::content_disposition(&document.file_name)))
+            .body(bytes),
+        Ok(_) => HttpResponse::NotFound().body("Document cannot be found."),
+        Err(err) => HttpResponse::build(rest::error_status(&err)).body(rest::error_message(&err)),
+    }
+}
+
+fn read_password() -> std::io::Result<String> {
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// Accounts of peers and adjusters are only kept in Postgres
+fn accounts_pool(backend: &Backend) -> &Pool<Postgres> {
+    match backend {
+        Backend::Postgres(storage) => storage.pool(),
+        Backend::Sqlite(_) => panic!("Accounts require the Postgres backend"),
     }
 }
 
@@ -79,13 +156,78 @@ async fn main() -> std::io::Result<()> {
     let database_url = env::var("DATABASE_URL")
         .expect("DATABASE_URL must be set in the environment variables");
 
-    let pool = PgPool::connect(&database_url).await.unwrap();
+    // postgres:// and postgresql:// URLs select Postgres, sqlite: URLs select SQLite
+    let backend = Backend::connect(&database_url).await.unwrap();
     println!("Connected to the database.");
 
+    // `migrate` applies pending migrations and exits
+    if env::args().nth(1).as_deref() == Some("migrate") {
+        backend.migrate().await.expect("Failed to apply migrations");
+        println!("Migrations applied.");
+        return Ok(());
+    }
+
+    // Apply pending migrations on startup unless disabled
+    let migrate_on_startup = env::var("MIGRATE_ON_STARTUP")
+        .map(|v| v != "false")
+        .unwrap_or(true);
+    if migrate_on_startup {
+        backend.migrate().await.expect("Failed to apply migrations");
+        println!("Migrations applied.");
+    }
+
+    // `peer-create <role> <username>` creates a shop, police or repair shop
+    // account with the password read from standard input
+    if env::args().nth(1).as_deref() == Some("peer-create") {
+        let role = env::args().nth(2).as_deref().and_then(peers::PeerAccountRole::parse)
+            .expect("Role must be shop, police or repair_shop");
+        let username = env::args().nth(3).expect("Username must be given");
+        let password = read_password()?;
+
+        peers::create_account(accounts_pool(&backend), role, &username, &password)
+            .await
+            .expect("Failed to create peer account");
+        println!("Peer account {} created.", username);
+        return Ok(());
+    }
+
+    // `supervisor-create <username>` creates a supervisor, e.g. the first one, with
+    // the password read from standard input
+    if env::args().nth(1).as_deref() == Some("supervisor-create") {
+        let username = env::args().nth(2).expect("Username must be given");
+        let password = read_password()?;
+
+        adjusters::create(accounts_pool(&backend), &username, adjusters::AdjusterRole::Supervisor, &password)
+            .await
+            .expect("Failed to create supervisor");
+        println!("Supervisor {} created.", username);
+        return Ok(());
+    }
+
+    // Expire contracts, send renewal reminders and escalate late claims in the background
+    match &backend {
+        Backend::Postgres(storage) => {
+            tokio::spawn(scheduler::run(storage.pool().clone()));
+        }
+        Backend::Sqlite(_) => {
+            println!("SQLite serves the core claim workflow only; the scheduler and the other functions need Postgres.");
+        }
+    }
+
+    let max_request_bytes = config::env_or("MAX_REQUEST_BYTES", DEFAULT_MAX_REQUEST_BYTES);
+
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(backend.clone()))
+            .app_data(web::PayloadConfig::new(max_request_bytes))
+            .app_data(web::JsonConfig::default().limit(max_request_bytes))
             .route("/invoke", web::post().to(invoke_function))
+            .configure(rest::configure)
+            .route("/openapi.json", web::get().to(openapi::serve))
+            .route(
+                "/claims/{claim_uuid}/documents/{document_uuid}",
+                web::get().to(download_document),
+            )
     })
     .bind("127.0.0.1:8080")? // Listen on localhost:8080
     .run()
@@ -0,0 +1,242 @@
+use sqlx::{Error, Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use bcrypt::verify;
+use chrono::{NaiveDateTime, Utc};
+use uuid::Uuid;
+
+use crate::adjusters;
+use crate::data::{Contract, PeerRole};
+use crate::peers::{self, PeerAccountRole, PeerCredentials};
+use crate::storage::{PgStorage, UserRepository};
+
+// Per-claim message thread shared between the insurer, police, repair shop and
+// claimant. Internal messages are only visible to the insurer.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    Internal,
+    Shared,
+}
+
+impl Visibility {
+    pub fn to_str(&self) -> &str {
+        match self {
+            Visibility::Internal => "internal",
+            Visibility::Shared => "shared",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimMessage {
+    pub id: Uuid,
+    pub claim_uuid: Uuid,
+    pub author: String,
+    pub role: String,
+    pub visibility: String,
+    pub body: String,
+    pub created_at: NaiveDateTime,
+}
+
+// Caller of the claim functions, signed in with the account of their role:
+// claimants with their customer account, the insurer with an adjuster account,
+// police and repair shops with their peer account
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Caller {
+    pub username: String,
+    pub role: PeerRole,
+    pub password: Option<String>,
+}
+
+impl Caller {
+    fn credentials(&self) -> PeerCredentials {
+        PeerCredentials {
+            username: self.username.clone(),
+            password: self.password.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Check the caller's password against the account of their role
+pub async fn authenticate(pool: &Pool<Postgres>, caller: &Caller) -> Result<(), Error> {
+    let credentials = caller.credentials();
+    match caller.role {
+        PeerRole::Insurer => adjusters::authenticate(pool, &credentials).await.map(|_| ()),
+        PeerRole::Police => peers::authenticate(pool, PeerAccountRole::Police, Some(&credentials)).await,
+        PeerRole::RepairShop => peers::authenticate(pool, PeerAccountRole::RepairShop, Some(&credentials)).await,
+        PeerRole::Claimant => {
+            let authenticated = match PgStorage::new(pool).find_user(&caller.username).await? {
+                Some(user) => verify(&credentials.password, &user.password).unwrap_or(false),
+                None => false,
+            };
+            if !authenticated {
+                return Err(Error::Decode(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Invalid credentials.",
+                ))));
+            }
+            Ok(())
+        }
+    }
+}
+
+// Check that the signed in caller can see the claim: the insurer sees every claim,
+// police only theft claims, repair shops claims with a repair order and claimants their own.
+pub async fn authorize_claim_access(pool: &Pool<Postgres>, caller: &Caller, claim_uuid: Uuid) -> Result<(), Error> {
+    authenticate(pool, caller).await?;
+
+    let claim = sqlx::query!(
+        r#"
+        SELECT contract_uuid, is_theft
+        FROM claims
+        WHERE id = $1
+        "#,
+        claim_uuid
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Claim cannot be found.",
+        )))
+    })?;
+
+    let allowed = match caller.role {
+        PeerRole::Insurer => true,
+        PeerRole::Police => claim.is_theft,
+        PeerRole::RepairShop => sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM repair_orders WHERE claim_uuid = $1
+            ) AS "exists!"
+            "#,
+            claim_uuid
+        )
+        .fetch_one(pool)
+        .await?,
+        PeerRole::Claimant => Contract::find(pool, claim.contract_uuid)
+            .await?
+            .map_or(false, |contract| contract.username == caller.username),
+    };
+
+    if !allowed {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Claim is not accessible to this caller.",
+        ))));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PostClaimMessageDto {
+    pub claim_uuid: Uuid,
+    #[serde(flatten)]
+    pub caller: Caller,
+    pub visibility: Visibility,
+    pub body: String,
+}
+
+pub async fn claim_message_post(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let dto: PostClaimMessageDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    authorize_claim_access(pool, &dto.caller, dto.claim_uuid).await?;
+
+    if dto.visibility == Visibility::Internal && dto.caller.role != PeerRole::Insurer {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Only the insurer can post internal messages.",
+        ))));
+    }
+
+    if dto.body.trim().is_empty() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Message cannot be empty.",
+        ))));
+    }
+
+    let message = ClaimMessage {
+        id: Uuid::new_v4(),
+        claim_uuid: dto.claim_uuid,
+        author: dto.caller.username,
+        role: dto.caller.role.to_str().to_string(),
+        visibility: dto.visibility.to_str().to_string(),
+        body: dto.body,
+        created_at: Utc::now().naive_utc(),
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO claim_messages (id, claim_uuid, author, role, visibility, body, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        message.id,
+        message.claim_uuid,
+        message.author,
+        message.role,
+        message.visibility,
+        message.body,
+        message.created_at
+    )
+    .execute(pool)
+    .await?;
+
+    serde_json::to_string(&message).map_err(|err| {
+        eprintln!("Failed to serialize message: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ListClaimMessagesDto {
+    pub claim_uuid: Uuid,
+    #[serde(flatten)]
+    pub caller: Caller,
+}
+
+pub async fn claim_message_ls(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let input: ListClaimMessagesDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    authorize_claim_access(pool, &input.caller, input.claim_uuid).await?;
+
+    // Internal messages stay within the insurer
+    let include_internal = input.caller.role == PeerRole::Insurer;
+
+    let messages = sqlx::query_as!(
+        ClaimMessage,
+        r#"
+        SELECT id, claim_uuid, author, role, visibility, body, created_at
+        FROM claim_messages
+        WHERE claim_uuid = $1 AND ($2 OR visibility = 'shared')
+        ORDER BY created_at
+        "#,
+        input.claim_uuid,
+        include_internal
+    )
+    .fetch_all(pool)
+    .await?;
+
+    serde_json::to_string(&messages).map_err(|err| {
+        eprintln!("Failed to serialize messages: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
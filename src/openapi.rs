@@ -0,0 +1,411 @@
+use actix_web::HttpResponse;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+
+use crate::idempotency;
+use crate::rest::{Input, ROUTES};
+use crate::rpc::FUNCTIONS;
+
+// OpenAPI document of the /invoke functions and the REST resources, generated
+// from rpc::FUNCTIONS and rest::ROUTES. Served at /openapi.json.
+
+const SCHEMA_PATH: &str = "#/components/schemas/";
+
+// Generates the schema of a type
+pub type Schema = fn(&mut SchemaGenerator) -> Value;
+
+// What a function returns on success
+pub enum Output<S = Schema> {
+    Nothing, // No content over REST
+    Message, // Plain text, wrapped as {"message": ...} over REST
+    Json(S), // Schema of the JSON result
+}
+
+// A registered function with the schemas of its parameters and result
+struct Function {
+    name: &'static str,
+    summary: &'static str,
+    input: Value, // Schema of the parameters
+    output: Output<Value>,
+}
+
+pub fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(gen.subschema_for::<T>()).unwrap()
+}
+
+// Every function of rpc::FUNCTIONS, in the same order
+fn functions(gen: &mut SchemaGenerator) -> Vec<Function> {
+    FUNCTIONS
+        .iter()
+        .map(|function| Function {
+            name: function.name,
+            summary: function.summary,
+            input: (function.input)(gen),
+            output: match function.output {
+                Output::Nothing => Output::Nothing,
+                Output::Message => Output::Message,
+                Output::Json(schema) => Output::Json(schema(gen)),
+            },
+        })
+        .collect()
+}
+
+fn message_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": { "message": { "type": "string" } },
+        "required": ["message"],
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": {
+                    "type": "object",
+                    "properties": { "error": { "type": "string" } },
+                    "required": ["error"],
+                },
+            },
+        },
+    })
+}
+
+// Schema that a reference into the components points to
+fn resolve<'a>(schema: &'a Value, schemas: &'a Map<String, Value>) -> &'a Value {
+    match schema.get("$ref").and_then(Value::as_str) {
+        Some(reference) => schemas.get(reference.trim_start_matches(SCHEMA_PATH)).unwrap_or(schema),
+        None => schema,
+    }
+}
+
+// Copy of an object schema without the fields taken from the path
+fn without_fields(schema: &Value, fields: &[&str]) -> Value {
+    let mut schema = schema.clone();
+    if let Some(properties) = schema.get_mut("properties").and_then(Value::as_object_mut) {
+        for field in fields {
+            properties.remove(*field);
+        }
+    }
+    if let Some(required) = schema.get_mut("required").and_then(Value::as_array_mut) {
+        required.retain(|field| !fields.contains(&field.as_str().unwrap_or_default()));
+    }
+    schema
+}
+
+// Idempotency-Key header, honored by the functions that change state
+fn idempotency_key_parameter() -> Value {
+    json!({
+        "name": idempotency::HEADER,
+        "in": "header",
+        "required": false,
+        "description": "Retried calls with the same key get the stored result of the first successful call",
+        "schema": { "type": "string", "maxLength": 255 },
+    })
+}
+
+fn idempotency_key_field() -> Value {
+    json!({ "type": "string", "maxLength": 255, "description": "Overrides the Idempotency-Key header" })
+}
+
+fn path_fields(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')))
+        .collect()
+}
+
+fn invoke_operation(functions: &[Function]) -> Value {
+    let requests = functions.iter().map(|function| {
+        let mut request = json!({
+            "title": function.name,
+            "description": function.summary,
+            "type": "object",
+            "properties": {
+                "function": { "type": "string", "enum": [function.name] },
+                "parameters": function.input,
+            },
+            "required": ["function", "parameters"],
+        });
+        if idempotency::is_mutating(function.name) {
+            request["properties"]["idempotency_key"] = idempotency_key_field();
+        }
+        request
+    });
+
+    // JSON-RPC 2.0 calls, alone or in a batch
+    let calls: Vec<Value> = functions
+        .iter()
+        .map(|function| {
+            let mut call = json!({
+                "title": format!("{} (JSON-RPC)", function.name),
+                "description": function.summary,
+                "type": "object",
+                "properties": {
+                    "jsonrpc": { "type": "string", "enum": ["2.0"] },
+                    "method": { "type": "string", "enum": [function.name] },
+                    "params": function.input,
+                    "id": { "oneOf": [{ "type": "string" }, { "type": "integer" }], "nullable": true },
+                },
+                "required": ["jsonrpc", "method"],
+            });
+            if idempotency::is_mutating(function.name) {
+                call["properties"]["idempotency_key"] = idempotency_key_field();
+            }
+            call
+        })
+        .collect();
+    let batch = json!({ "type": "array", "minItems": 1, "items": { "oneOf": calls } });
+    let rpc_response = json!({
+        "type": "object",
+        "properties": {
+            "jsonrpc": { "type": "string", "enum": ["2.0"] },
+            "result": { "description": "Result of the function, null when it returns nothing" },
+            "error": {
+                "type": "object",
+                "properties": {
+                    "code": { "type": "integer" },
+                    "message": { "type": "string" },
+                    "data": { "type": "object" },
+                },
+                "required": ["code", "message"],
+            },
+            "id": { "oneOf": [{ "type": "string" }, { "type": "integer" }], "nullable": true },
+        },
+        "required": ["jsonrpc", "id"],
+    });
+
+    let mut schemas: Vec<Value> = requests.chain(calls.iter().cloned()).collect();
+    schemas.push(batch);
+
+    json!({
+        "post": {
+            "operationId": "invoke",
+            "summary": "Invoke a function by name, or call functions with JSON-RPC 2.0",
+            "parameters": [idempotency_key_parameter()],
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": { "oneOf": schemas } } },
+            },
+            "responses": {
+                "200": {
+                    "description": "Outcome of the function, or the JSON-RPC responses of the calls",
+                    "content": {
+                        "text/plain": { "schema": { "type": "string" } },
+                        "application/json": {
+                            "schema": { "oneOf": [rpc_response, { "type": "array", "items": rpc_response }] },
+                        },
+                    },
+                },
+                "204": { "description": "Only notifications were sent" },
+            },
+        },
+    })
+}
+
+// Credentials that REST routes read from the Authorization header, by function
+const BASIC_AUTH_FIELDS: &[(&str, &str)] = &[("claim_ls", "adjuster")];
+
+fn rest_operation(function: &Function, path: &str, input: Input, status: u16, schemas: &Map<String, Value>) -> Value {
+    let fields = path_fields(path);
+    let basic_auth = BASIC_AUTH_FIELDS
+        .iter()
+        .find(|(name, _)| *name == function.name)
+        .map(|(_, field)| *field);
+    let mut parameters: Vec<Value> = fields
+        .iter()
+        .map(|field| {
+            let schema = match *field {
+                "uuid" => json!({ "type": "string", "format": "uuid" }),
+                _ => json!({ "type": "string" }),
+            };
+            json!({ "name": field, "in": "path", "required": true, "schema": schema })
+        })
+        .collect();
+
+    let mut operation = json!({
+        "operationId": function.name,
+        "summary": function.summary,
+    });
+
+    let input_schema = without_fields(resolve(&function.input, schemas), &fields);
+    match input {
+        Input::Query => {
+            // Filters and paging, each one optional
+            if let Some(properties) = input_schema.get("properties").and_then(Value::as_object) {
+                for (name, schema) in properties.iter().filter(|(name, _)| Some(name.as_str()) != basic_auth) {
+                    parameters.push(json!({ "name": name, "in": "query", "required": false, "schema": schema }));
+                }
+            }
+        }
+        Input::Body => {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": input_schema } },
+            });
+        }
+        Input::Path => {}
+    }
+    if idempotency::is_mutating(function.name) {
+        parameters.push(idempotency_key_parameter());
+    }
+    if basic_auth.is_some() {
+        operation["security"] = json!([{ "basic": [] }]);
+    }
+    if !parameters.is_empty() {
+        operation["parameters"] = Value::Array(parameters);
+    }
+
+    let success = match &function.output {
+        Output::Nothing => (if status == 200 { 204 } else { status }, None),
+        Output::Message => (status, Some(message_schema())),
+        Output::Json(schema) => (status, Some(schema.clone())),
+    };
+    let mut responses = Map::new();
+    responses.insert(
+        success.0.to_string(),
+        match success.1 {
+            Some(schema) => json!({
+                "description": "Success",
+                "content": { "application/json": { "schema": schema } },
+            }),
+            None => json!({ "description": "Success" }),
+        },
+    );
+    responses.insert("400".to_string(), error_response("Invalid parameters"));
+    responses.insert("403".to_string(), error_response("Not allowed for the caller"));
+    responses.insert("404".to_string(), error_response("Record not found"));
+    responses.insert("409".to_string(), error_response("Conflicts with an existing record"));
+    responses.insert("501".to_string(), error_response("Not available on the configured backend"));
+    operation["responses"] = Value::Object(responses);
+
+    operation
+}
+
+fn generate() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let functions = functions(&mut gen);
+    let schemas: Map<String, Value> = gen
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap()))
+        .collect();
+
+    let mut paths = Map::new();
+    paths.insert("/invoke".to_string(), invoke_operation(&functions));
+
+    for (method, path, name, input, status) in ROUTES {
+        let function = functions.iter().find(|function| function.name == *name).unwrap();
+        let operations = paths.entry(path.to_string()).or_insert_with(|| json!({}));
+        operations[*method] = rest_operation(function, path, *input, *status, &schemas);
+    }
+
+    paths.insert(
+        "/claims/{claim_uuid}/documents/{document_uuid}".to_string(),
+        json!({
+            "get": {
+                "operationId": "claim_document_download",
+                "summary": "Download the raw content of a claim document",
+                "security": [{ "basic": [] }],
+                "parameters": [
+                    { "name": "claim_uuid", "in": "path", "required": true, "schema": { "type": "string", "format": "uuid" } },
+                    { "name": "document_uuid", "in": "path", "required": true, "schema": { "type": "string", "format": "uuid" } },
+                    { "name": "role", "in": "query", "required": true, "schema": { "type": "string", "enum": ["claimant", "police", "repair_shop", "insurer"] } },
+                ],
+                "responses": {
+                    "200": {
+                        "description": "Document content",
+                        "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } },
+                    },
+                    "401": { "description": "Credentials missing" },
+                    "403": { "description": "Claim not accessible to the caller" },
+                    "404": { "description": "Document not found" },
+                    "501": { "description": "Not available on the configured backend" },
+                },
+            },
+        }),
+    );
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Insurance API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": { "basic": { "type": "http", "scheme": "basic" } },
+        },
+    })
+}
+
+// The document only depends on the types, generate it once
+pub fn document() -> &'static Value {
+    static DOCUMENT: OnceLock<Value> = OnceLock::new();
+    DOCUMENT.get_or_init(generate)
+}
+
+pub async fn serve() -> HttpResponse {
+    HttpResponse::Ok().json(document())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn references(value: &Value, found: &mut BTreeSet<String>) {
+        match value {
+            Value::Object(object) => {
+                if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+                    found.insert(reference.to_string());
+                }
+                object.values().for_each(|value| references(value, found));
+            }
+            Value::Array(array) => array.iter().for_each(|value| references(value, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn invoke_accepts_every_documented_function() {
+        let requests = document()["paths"]["/invoke"]["post"]["requestBody"]["content"]["application/json"]["schema"]["oneOf"]
+            .as_array()
+            .unwrap();
+        let names = |field: &str| -> BTreeSet<String> {
+            requests
+                .iter()
+                .filter_map(|request| request["properties"][field]["enum"][0].as_str())
+                .map(str::to_string)
+                .collect()
+        };
+        let registered: BTreeSet<String> = FUNCTIONS.iter().map(|function| function.name.to_string()).collect();
+
+        assert_eq!(names("function"), registered);
+        assert_eq!(names("method"), registered);
+    }
+
+    #[test]
+    fn rest_routes_use_registered_functions() {
+        for (method, path, name, _, _) in ROUTES {
+            assert!(crate::rpc::is_registered(name), "{} {} uses unknown function {}", method, path, name);
+            assert_eq!(document()["paths"][*path][*method]["operationId"], json!(name));
+        }
+    }
+
+    #[test]
+    fn references_resolve() {
+        let document = document();
+        let mut found = BTreeSet::new();
+        references(document, &mut found);
+
+        for reference in found {
+            let name = reference.trim_start_matches(SCHEMA_PATH);
+            assert!(document["components"]["schemas"].get(name).is_some(), "Unresolved reference {}", reference);
+        }
+    }
+}
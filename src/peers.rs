@@ -0,0 +1,95 @@
+use bcrypt::{hash, verify, DEFAULT_COST};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Pool, Postgres};
+
+use crate::storage::or_duplicate;
+
+// Shops, police and repair shops act on behalf of customers and claimants, so
+// they sign in with their own account. Accounts are created with the
+// `peer-create` command and checked with bcrypt like customer passwords.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerAccountRole {
+    Shop,
+    Police,
+    RepairShop,
+}
+
+impl PeerAccountRole {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            PeerAccountRole::Shop => "shop",
+            PeerAccountRole::Police => "police",
+            PeerAccountRole::RepairShop => "repair_shop",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<PeerAccountRole> {
+        match role {
+            "shop" => Some(PeerAccountRole::Shop),
+            "police" => Some(PeerAccountRole::Police),
+            "repair_shop" => Some(PeerAccountRole::RepairShop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PeerCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+// Check the credentials of an active account with the given role
+pub async fn authenticate(
+    pool: &Pool<Postgres>,
+    role: PeerAccountRole,
+    credentials: Option<&PeerCredentials>,
+) -> Result<(), Error> {
+    let denied = || {
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Invalid credentials.",
+        )))
+    };
+    let credentials = credentials.ok_or_else(denied)?;
+
+    let password = sqlx::query_scalar!(
+        "SELECT password FROM peer_accounts WHERE username = $1 AND role = $2 AND active = TRUE",
+        credentials.username,
+        role.to_str()
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(denied)?;
+
+    if !verify(&credentials.password, &password).unwrap_or(false) {
+        return Err(denied());
+    }
+    Ok(())
+}
+
+pub async fn create_account(
+    pool: &Pool<Postgres>,
+    role: PeerAccountRole,
+    username: &str,
+    password: &str,
+) -> Result<(), Error> {
+    let password = hash(password, DEFAULT_COST).map_err(|err| {
+        eprintln!("Failed to hash password: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    sqlx::query!(
+        "INSERT INTO peer_accounts (username, role, password) VALUES ($1, $2, $3)",
+        username,
+        role.to_str(),
+        password
+    )
+    .execute(pool)
+    .await
+    .map_err(|err| or_duplicate(err, "Peer account"))?;
+    Ok(())
+}
@@ -1,12 +1,19 @@
 use sqlx::{Error, Pool, Postgres};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use chrono::Utc;
 use uuid::Uuid;
+use std::collections::{HashMap, HashSet};
+
+use crate::data::{Claim, Item, User};
+use crate::listing::{self, ListQuery, SortKey};
+use crate::storage::{PgStorage, Storage};
 
 //Add indexes to is_theft and status columns in the claims table for efficient filtering:
 //CREATE INDEX idx_claims_is_theft_status ON claims (is_theft, status);
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct TheftClaimResult {
     pub uuid: String,
     pub contract_uuid: String,
@@ -15,79 +22,142 @@ pub struct TheftClaimResult {
     pub name: String,
 }
 
-pub async fn list_theft_claims(pool: &Pool<Postgres>) -> Result<String, Error> {
-    // Query to fetch claims flagged as theft and with a status of "New"
-    let claims: Vec<Claim> = sqlx::query_as!(
-        Claim,
+const THEFT_CLAIM_SORT_KEYS: &[SortKey] = &[
+    SortKey { name: "date", column: "claims.date", cast: "TIMESTAMP" },
+    SortKey { name: "username", column: "contracts.username", cast: "TEXT" },
+];
+
+const THEFT_CLAIM_FILTERS: &[&str] = &["date_from", "date_to", "username", "shop_type", "contract_type_uuid"];
+
+pub async fn list_theft_claims(
+    pool: &Pool<Postgres>,
+    args: Option<String>, // Optional JSON input for filtering and paging
+) -> Result<String, Error> {
+    let query: ListQuery = listing::parse_list(args)?;
+    query.allow_filters("theft claims", THEFT_CLAIM_FILTERS)?;
+
+    let window = listing::page_ids(
+        pool,
+        &query,
+        THEFT_CLAIM_SORT_KEYS,
+        "claims JOIN contracts ON contracts.id = claims.contract_uuid \
+            JOIN contract_types ON contract_types.id = contracts.contract_type_uuid",
+        "claims.id",
+        |filters| {
+            filters.and().push("claims.is_theft = TRUE AND claims.status = 'New'");
+            filters.contract_filters(&query, "claims.date");
+        },
+    )
+    .await?;
+
+    // The claims of the page with their contract and customer, in one query
+    let rows = sqlx::query!(
         r#"
-        SELECT id, contract_uuid, description, is_theft, status
+        SELECT claims.id, claims.contract_uuid, claims.description, contracts.item, users.first_name, users.last_name
         FROM claims
-        WHERE is_theft = TRUE AND status = 'New'
-        "#
+        JOIN contracts ON contracts.id = claims.contract_uuid
+        JOIN users ON users.username = contracts.username
+        WHERE claims.id = ANY($1)
+        "#,
+        &window.ids
     )
     .fetch_all(pool)
     .await?;
 
-    let mut results = Vec::new();
+    let mut entries = Vec::new();
+    for row in rows {
+        let item: Item = serde_json::from_value(row.item).map_err(|err| {
+            eprintln!("Failed to parse item of contract {}: {:?}", row.contract_uuid, err);
+            Error::Decode(Box::new(err))
+        })?;
+        entries.push((
+            row.id,
+            TheftClaimResult {
+                uuid: row.id.to_string(),
+                contract_uuid: row.contract_uuid.to_string(),
+                item,
+                description: row.description,
+                name: format!("{} {}", row.first_name, row.last_name),
+            },
+        ));
+    }
+    let page = window.page(entries, |(id, _)| *id).map(|(_, result)| result);
 
-    for claim in claims {
-        // Fetch the associated contract
-        //get the contract
-        contract = claim.Contract(pool);
-        let mut contract = match contract {
-            Some(c) => c,
-            None => {
-                eprintln!("Contract with UUID {} not found.", claim.contract_uuid);
-                return Err(Error::Decode(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "Contract could not be found.",
-                ))));
-            }
-        };
+    // Serialize results into JSON
+    serde_json::to_string(&page).map_err(|err| {
+        eprintln!("Failed to serialize results: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+// Theft reports only wait here until the police answer, so on the other backends
+// they are filtered and paged after loading, with their contracts and customers
+// loaded at once
+pub async fn theft_claims_to_confirm(storage: &dyn Storage, query: ListQuery) -> Result<String, Error> {
+    query.allow_filters("theft claims", THEFT_CLAIM_FILTERS)?;
+
+    // Fetch claims flagged as theft and with a status of "New"
+    let claims: Vec<Claim> = storage
+        .theft_claims_to_confirm()
+        .await?
+        .into_iter()
+        .filter(|claim| query.in_date_range(claim.date))
+        .collect();
+
+    // Load the contracts and customers of all claims at once
+    let contract_uuids: HashSet<Uuid> = claims.iter().map(|claim| claim.contract_uuid).collect();
+    let contract_uuids: Vec<Uuid> = contract_uuids.into_iter().collect();
+    let contracts = query.matching_contracts(storage, storage.find_contracts(&contract_uuids).await?).await?;
+    let usernames: HashSet<String> = contracts.values().map(|contract| contract.username.clone()).collect();
+    let usernames: Vec<String> = usernames.into_iter().collect();
+    let users: HashMap<String, User> = storage
+        .find_users(&usernames)
+        .await?
+        .into_iter()
+        .map(|user| (user.username.clone(), user))
+        .collect();
+
+    let mut entries = Vec::new();
 
-        // Fetch the associated user
-        user = contract.User(pool);
-        let mut user = match user {
-            Some(u) => u,
-            None => {
-                eprintln!("User not found.");
-                return Err(Error::Decode(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "User could not be found.",
-                ))));
-            }
+    for claim in claims {
+        let contract = match contracts.get(&claim.contract_uuid) {
+            Some(contract) => contract,
+            None => continue,
         };
-       /* let user = sqlx::query_as!(
-            User,
-            r#"
-            SELECT username, first_name, last_name
-            FROM users
-            WHERE username = $1
-            "#,
-            contract.username
-        )
-        .fetch_one(pool)
-        .await?;*/
+        let user = users.get(&contract.username).ok_or_else(|| {
+            eprintln!("User {} not found.", contract.username);
+            Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "User could not be found.",
+            )))
+        })?;
 
         // Construct the result
-        results.push(TheftClaimResult {
-            uuid: claim.id.to_string(),
-            contract_uuid: contract.id.to_string(),
-            item: serde_json::from_value(contract.item).unwrap(), // Deserialize JSON item field
-            description: claim.description,
-            name: format!("{} {}", user.first_name, user.last_name),
-        });
+        let keys = vec![claim.date.format("%Y-%m-%dT%H:%M:%S%.f").to_string(), contract.username.clone()];
+        entries.push((
+            keys,
+            claim.id,
+            TheftClaimResult {
+                uuid: claim.id.to_string(),
+                contract_uuid: contract.id.to_string(),
+                item: contract.item.clone(),
+                description: claim.description,
+                name: format!("{} {}", user.first_name, user.last_name),
+            },
+        ));
     }
 
+    let sort_keys: Vec<&str> = THEFT_CLAIM_SORT_KEYS.iter().map(|key| key.name).collect();
+    let page = listing::paginate(&query, &sort_keys, entries)?;
+
     // Serialize results into JSON
-    serde_json::to_string(&results).map_err(|err| {
+    serde_json::to_string(&page).map_err(|err| {
         eprintln!("Failed to serialize results: {:?}", err);
         Error::Decode(Box::new(err))
     })
 }
 
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ProcessTheftClaimDto {
     pub uuid: Uuid,
     pub contract_uuid: Uuid,
@@ -105,26 +175,23 @@ pub async fn process_theft_claim(
         Error::Decode(Box::new(err))
     })?;
 
+    confirm_theft(&PgStorage::new(pool), dto).await
+}
+
+// Record the police outcome of a theft report
+pub async fn confirm_theft(storage: &dyn Storage, dto: ProcessTheftClaimDto) -> Result<(), Error> {
     // Fetch the claim
-    let mut claim = sqlx::query_as!(
-        Claim,
-        r#"
-        SELECT id, contract_uuid, is_theft, status, file_reference
-        FROM claims
-        WHERE id = $1 AND contract_uuid = $2
-        "#,
-        dto.uuid,
-        dto.contract_uuid
-    )
-    .fetch_optional(pool)
-    .await?
-    .ok_or_else(|| {
-        eprintln!("Claim with UUID {} not found.", dto.uuid);
-        Error::Decode(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "Claim cannot be found.",
-        )))
-    })?;
+    let mut claim = storage
+        .find_claim(dto.uuid)
+        .await?
+        .filter(|claim| claim.contract_uuid == dto.contract_uuid)
+        .ok_or_else(|| {
+            eprintln!("Claim with UUID {} not found.", dto.uuid);
+            Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Claim cannot be found.",
+            )))
+        })?;
 
     // Validate claim status and type
     if !claim.is_theft || claim.status != "New" {
@@ -138,24 +205,13 @@ pub async fn process_theft_claim(
     claim.status = if dto.is_theft {
         "TheftConfirmed".to_string() // Status for confirmed theft
     } else {
+        claim.rejected_from = Some(claim.status.clone());
         "Rejected".to_string() // Status for rejected claims
     };
-    claim.file_reference = Some(dto.file_reference);
+    claim.file_reference = dto.file_reference;
+    claim.status_changed_at = Some(Utc::now().naive_utc());
+    claim.sla_breached_at = None;
 
     // Persist the updated claim
-    sqlx::query!(
-        r#"
-        UPDATE claims
-        SET status = $1, file_reference = $2
-        WHERE id = $3
-        "#,
-        claim.status,
-        claim.file_reference,
-        claim.id
-    )
-    .execute(pool)
-    .await?;
-
-    Ok(())
+    storage.update_claim(&claim).await
 }
-
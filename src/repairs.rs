@@ -1,11 +1,17 @@
 use sqlx::{Error, Pool, Postgres};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use uuid::Uuid;
+use std::collections::{HashMap, HashSet};
+
+use crate::data::{Claim, Item, RepairOrder};
+use crate::listing::{self, ListQuery, SortKey};
+use crate::storage::{PgStorage, Storage};
 
 //Add an index to the ready column in the repair_orders table for efficient filtering:
 //CREATE INDEX idx_repair_orders_ready ON repair_orders (ready);
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct RepairOrderResult {
     pub uuid: String,
     pub claim_uuid: String,
@@ -13,39 +19,124 @@ pub struct RepairOrderResult {
     pub item: Item,
 }
 
-pub async fn list_repair_orders(pool: &Pool<Postgres>) -> Result<String, Error> {  //replaced id AS uuid with id AS claim_uuid
-    // Query to fetch all repair orders where `ready` is false
-    let repair_orders: Vec<RepairOrder> = sqlx::query_as!(
+const REPAIR_ORDER_SORT_KEYS: &[SortKey] = &[
+    SortKey { name: "date", column: "claims.date", cast: "TIMESTAMP" },
+    SortKey { name: "username", column: "contracts.username", cast: "TEXT" },
+];
+
+const REPAIR_ORDER_FILTERS: &[&str] = &["date_from", "date_to", "username", "shop_type", "contract_type_uuid"];
+
+// Item of a repair order, stored as JSON
+fn order_item(order: &RepairOrder) -> Result<Item, Error> {
+    serde_json::from_value(order.item.clone()).map_err(|err| {
+        eprintln!("Failed to parse item of repair order {}: {:?}", order.id, err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+fn order_result(order: &RepairOrder) -> Result<RepairOrderResult, Error> {
+    Ok(RepairOrderResult {
+        uuid: order.id.to_string(),
+        claim_uuid: order.claim_uuid.to_string(),
+        contract_uuid: order.contract_uuid.to_string(),
+        item: order_item(order)?,
+    })
+}
+
+pub async fn list_repair_orders(
+    pool: &Pool<Postgres>,
+    args: Option<String>, // Optional JSON input for filtering and paging
+) -> Result<String, Error> {
+    let query: ListQuery = listing::parse_list(args)?;
+    query.allow_filters("repair orders", REPAIR_ORDER_FILTERS)?;
+
+    let window = listing::page_ids(
+        pool,
+        &query,
+        REPAIR_ORDER_SORT_KEYS,
+        "repair_orders JOIN claims ON claims.id = repair_orders.claim_uuid \
+            JOIN contracts ON contracts.id = repair_orders.contract_uuid \
+            JOIN contract_types ON contract_types.id = contracts.contract_type_uuid",
+        "repair_orders.id",
+        |filters| {
+            filters.and().push("repair_orders.ready = FALSE");
+            filters.contract_filters(&query, "claims.date");
+        },
+    )
+    .await?;
+
+    let orders = sqlx::query_as!(
         RepairOrder,
         r#"
-        SELECT id AS claim_uuid, contract_uuid, item, ready
+        SELECT id, claim_uuid, contract_uuid, item, ready
         FROM repair_orders
-        WHERE ready = FALSE
-        "#
+        WHERE id = ANY($1)
+        "#,
+        &window.ids
     )
     .fetch_all(pool)
     .await?;
 
-    // Map the repair orders to the result structure
-    let results: Vec<RepairOrderResult> = repair_orders
+    let mut entries = Vec::new();
+    for order in &orders {
+        entries.push((order.id, order_result(order)?));
+    }
+    let page = window.page(entries, |(id, _)| *id).map(|(_, result)| result);
+
+    // Serialize results into JSON
+    serde_json::to_string(&page).map_err(|err| {
+        eprintln!("Failed to serialize repair order results: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+// Open repair orders are only the ones in progress, so on the other backends
+// they are filtered and paged after loading, with their claims and contracts
+// loaded at once
+pub async fn open_repair_orders(storage: &dyn Storage, query: ListQuery) -> Result<String, Error> {
+    query.allow_filters("repair orders", REPAIR_ORDER_FILTERS)?;
+
+    // Fetch all repair orders where `ready` is false
+    let repair_orders: Vec<RepairOrder> = storage.open_repair_orders().await?;
+
+    let claim_uuids: Vec<Uuid> = repair_orders.iter().map(|order| order.claim_uuid).collect();
+    let claims: HashMap<Uuid, Claim> = storage
+        .find_claims(&claim_uuids)
+        .await?
         .into_iter()
-        .map(|order| RepairOrderResult {
-            uuid: order.id.to_string(),
-            claim_uuid: order.claim_id.to_string(),
-            contract_uuid: order.contract_id.to_string(),
-            item: serde_json::from_value(order.item).unwrap(), // Deserialize JSON item
-        })
+        .filter(|claim| query.in_date_range(claim.date))
+        .map(|claim| (claim.id, claim))
         .collect();
+    let contract_uuids: HashSet<Uuid> = repair_orders.iter().map(|order| order.contract_uuid).collect();
+    let contract_uuids: Vec<Uuid> = contract_uuids.into_iter().collect();
+    let contracts = query.matching_contracts(storage, storage.find_contracts(&contract_uuids).await?).await?;
+
+    let mut entries = Vec::new();
+
+    for order in repair_orders {
+        // Filter on the claim date and the contract
+        let (claim, contract) = match (claims.get(&order.claim_uuid), contracts.get(&order.contract_uuid)) {
+            (Some(claim), Some(contract)) => (claim, contract),
+            _ => continue,
+        };
+
+        // Map the repair order to the result structure
+        let keys = vec![claim.date.format("%Y-%m-%dT%H:%M:%S%.f").to_string(), contract.username.clone()];
+        entries.push((keys, order.id, order_result(&order)?));
+    }
+
+    let sort_keys: Vec<&str> = REPAIR_ORDER_SORT_KEYS.iter().map(|key| key.name).collect();
+    let page = listing::paginate(&query, &sort_keys, entries)?;
 
     // Serialize results into JSON
-    serde_json::to_string(&results).map_err(|err| {
+    serde_json::to_string(&page).map_err(|err| {
         eprintln!("Failed to serialize repair order results: {:?}", err);
         Error::Decode(Box::new(err))
     })
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct CompleteRepairOrderDto {
     pub uuid: Uuid,
 }
@@ -60,70 +151,20 @@ pub async fn complete_repair_order(
         Error::Decode(Box::new(err))
     })?;
 
+    finish_repair_order(&PgStorage::new(pool), input.uuid).await
+}
+
+// Mark a repair order ready and its claim repaired
+pub async fn finish_repair_order(storage: &dyn Storage, repair_order_uuid: Uuid) -> Result<(), Error> {
     // Fetch the repair order
-    let mut repair_order = sqlx::query_as!(
-        RepairOrder,
-        r#"
-        SELECT claim_uuid, contract_uuid, ready
-        FROM repair_orders
-        WHERE id = $1
-        "#,
-        input.uuid
-    )
-    .fetch_optional(pool)
-    .await?
-    .ok_or_else(|| {
-        eprintln!("Repair order with UUID {} not found.", input.uuid);
+    let repair_order = storage.find_repair_order(repair_order_uuid).await?.ok_or_else(|| {
+        eprintln!("Repair order with UUID {} not found.", repair_order_uuid);
         Error::Decode(Box::new(std::io::Error::new(
             std::io::ErrorKind::NotFound,
             "Could not find the repair order.",
         )))
     })?;
 
-    // Mark the repair order as ready
-    repair_order.ready = true;
-
-    // Update the repair order in the database
-    sqlx::query!(
-        r#"
-        UPDATE repair_orders
-        SET ready = TRUE
-        WHERE id = $1
-        "#,
-        repair_order.claim_uuid
-    )
-    .execute(pool)
-    .await?;
-
-    // Update the corresponding claim
-    let claim = sqlx::query_as!(
-        Claim,
-        r#"
-        SELECT id, repaired
-        FROM claims
-        WHERE id = $1 AND contract_uuid = $2
-        "#,
-        repair_order.claim_uuid,
-        repair_order.contract_uuid
-    )
-    .fetch_optional(pool)
-    .await?;
-
-    if let Some(mut claim) = claim {
-        claim.repaired = true;
-
-        sqlx::query!(
-            r#"
-            UPDATE claims
-            SET repaired = TRUE
-            WHERE id = $1 AND contract_uuid = $2
-            "#,
-            repair_order.claim_uuid,
-            repair_order.contract_uuid
-        )
-        .execute(pool)
-        .await?;
-    }
-
-    Ok(())
+    // Mark the repair order as ready and the corresponding claim as repaired
+    storage.complete_repair_order(&repair_order).await
 }
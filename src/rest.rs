@@ -0,0 +1,471 @@
+use actix_web::{http::{header, StatusCode}, web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::{Error, PgPool};
+use std::future::Future;
+use uuid::Uuid;
+
+use crate::batch::BatchError;
+use crate::idempotency::{self, IdempotencyKey};
+use crate::ids::ClientIds;
+use crate::insurance;
+use crate::listing::ListQuery;
+use crate::police;
+use crate::repairs;
+use crate::shop;
+use crate::storage::Backend;
+use crate::workflow;
+
+// REST resources over the same handlers as the /invoke functions. Path ids are
+// merged into the JSON body, list filters and paging come from the query string.
+// Functions without a resource here stay available through /invoke.
+
+// Where a route reads the parameters of its function from, besides the path
+#[derive(Clone, Copy, PartialEq)]
+pub enum Input {
+    Query,
+    Body,
+    Path,
+}
+
+// Routes: method, path, function, input and status on success. The OpenAPI
+// document is generated from the same table.
+pub const ROUTES: &[(&str, &str, &str, Input, u16)] = &[
+    ("get", "/contract-types", "contract_type_ls", Input::Query, 200),
+    ("post", "/contract-types", "contract_type_create", Input::Body, 201),
+    ("put", "/contract-types/{uuid}", "contract_type_update", Input::Body, 200),
+    ("put", "/contract-types/{uuid}/active", "contract_type_set_active", Input::Body, 200),
+    ("get", "/contracts", "contract_ls", Input::Query, 200),
+    ("post", "/contracts", "contract_create", Input::Body, 201),
+    ("get", "/contracts/{uuid}", "contract_get", Input::Path, 200),
+    ("post", "/contracts/{uuid}/cancel", "contract_cancel", Input::Body, 200),
+    ("post", "/contracts/{uuid}/renew", "contract_renew", Input::Body, 201),
+    ("get", "/claims", "claim_ls", Input::Query, 200),
+    ("post", "/claims", "claim_file", Input::Body, 201),
+    ("post", "/claims/{uuid}/process", "claim_process", Input::Body, 200),
+    ("get", "/claims/{uuid}/valuation", "claim_valuation", Input::Path, 200),
+    ("get", "/repair-orders", "repair_order_ls", Input::Query, 200),
+    ("post", "/repair-orders/{uuid}/complete", "repair_order_complete", Input::Path, 200),
+    ("get", "/theft-claims", "theft_claim_ls", Input::Query, 200),
+    ("post", "/theft-claims/{uuid}/process", "theft_claim_process", Input::Body, 200),
+    ("get", "/users/{username}", "user_get_info", Input::Path, 200),
+    ("put", "/users/{username}/password", "password_update", Input::Body, 200),
+    ("post", "/users/{username}/authenticate", "user_authenticate", Input::Body, 200),
+];
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    for (method, path, function, _, _) in ROUTES {
+        let route = match *method {
+            "get" => web::get(),
+            "post" => web::post(),
+            "put" => web::put(),
+            _ => unreachable!("Unknown method {}", method),
+        };
+        let route = match *function {
+            "contract_type_ls" => route.to(list_contract_types),
+            "contract_type_create" => route.to(create_contract_type),
+            "contract_type_update" => route.to(update_contract_type),
+            "contract_type_set_active" => route.to(set_contract_type_active),
+            "contract_ls" => route.to(list_contracts),
+            "contract_create" => route.to(create_contract),
+            "contract_get" => route.to(get_contract),
+            "contract_cancel" => route.to(cancel_contract),
+            "contract_renew" => route.to(renew_contract),
+            "claim_ls" => route.to(list_claims),
+            "claim_file" => route.to(file_claim),
+            "claim_process" => route.to(process_claim),
+            "claim_valuation" => route.to(claim_valuation),
+            "repair_order_ls" => route.to(list_repair_orders),
+            "repair_order_complete" => route.to(complete_repair_order),
+            "theft_claim_ls" => route.to(list_theft_claims),
+            "theft_claim_process" => route.to(process_theft_claim),
+            "user_get_info" => route.to(get_user),
+            "password_update" => route.to(update_password),
+            "user_authenticate" => route.to(authenticate_user),
+            _ => unreachable!("No resource handler for {}", function),
+        };
+        cfg.route(path, route);
+    }
+}
+
+// Run a function: Postgres calls the handler, SQLite the storage-backed workflow
+async fn run<F, Fut>(backend: &Backend, function: &str, args: String, handler: F) -> Result<String, Error>
+where
+    F: FnOnce(PgPool, String) -> Fut,
+    Fut: Future<Output = Result<String, Error>>,
+{
+    match backend {
+        Backend::Postgres(storage) => handler(storage.pool().clone(), args).await,
+        Backend::Sqlite(storage) => workflow::invoke(storage, function, args, ClientIds::Refused).await.unwrap_or_else(|| {
+            Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("Function '{}' needs the Postgres backend.", function),
+            ))))
+        }),
+    }
+}
+
+// Run a state-changing function, at most once per Idempotency-Key header
+async fn run_once<F, Fut>(
+    backend: &Backend,
+    key: IdempotencyKey,
+    function: &str,
+    args: String,
+    handler: F,
+) -> Result<String, Error>
+where
+    F: FnOnce(PgPool, String) -> Fut,
+    Fut: Future<Output = Result<String, Error>>,
+{
+    idempotency::once(backend.storage(), key.0.as_deref(), function, args, |args| {
+        run(backend, function, args, handler)
+    })
+    .await
+}
+
+// Status code of a handler error, from the error kind the handlers report
+pub fn error_status(err: &Error) -> StatusCode {
+    match err {
+        // A failed batch reports the status of the call that failed
+        Error::Decode(source) if source.is::<BatchError>() => {
+            error_status(&source.downcast_ref::<BatchError>().unwrap().source)
+        }
+        Error::Decode(source) => match source.downcast_ref::<std::io::Error>() {
+            Some(io) => match io.kind() {
+                std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                std::io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+                std::io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                std::io::ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+                std::io::ErrorKind::Unsupported => StatusCode::NOT_IMPLEMENTED,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            // Malformed input JSON
+            None if source.is::<serde_json::Error>() => StatusCode::BAD_REQUEST,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        Error::RowNotFound => StatusCode::NOT_FOUND,
+        Error::Database(db) if db.code().as_deref() == Some("23505") => StatusCode::CONFLICT, // Unique violation
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+pub fn error_message(err: &Error) -> String {
+    match err {
+        Error::Decode(source) => source.to_string(),
+        _ => err.to_string(),
+    }
+}
+
+// JSON results are returned as they are, plain messages wrapped in an object
+fn respond(status: StatusCode, result: Result<String, Error>) -> HttpResponse {
+    match result {
+        Ok(body) if body.is_empty() => match status {
+            StatusCode::OK => HttpResponse::NoContent().finish(),
+            _ => HttpResponse::build(status).finish(),
+        },
+        Ok(body) if serde_json::from_str::<Value>(&body).is_ok() => {
+            HttpResponse::build(status).content_type("application/json").body(body)
+        }
+        Ok(body) => HttpResponse::build(status).json(json!({ "message": body })),
+        Err(err) => HttpResponse::build(error_status(&err)).json(json!({ "error": error_message(&err) })),
+    }
+}
+
+// Request body with a path parameter added
+fn with_field(body: Option<web::Json<Value>>, field: &str, value: Value) -> String {
+    let mut body = body.map(|body| body.into_inner()).unwrap_or_else(|| json!({}));
+    if let Some(object) = body.as_object_mut() {
+        object.insert(field.to_string(), value);
+    }
+    body.to_string()
+}
+
+// List arguments from the query string, with the list's own parameters
+fn list_args(query: ListQuery, extra: &[(&str, Option<String>)]) -> String {
+    let mut args = serde_json::to_value(query).unwrap();
+    for (field, value) in extra {
+        args[*field] = json!(value);
+    }
+    args.to_string()
+}
+
+// Username and password of an `Authorization: Basic` header
+pub fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let header = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(BASE64.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+fn body_args(body: web::Json<Value>) -> String {
+    body.into_inner().to_string()
+}
+
+// Contract types
+
+#[derive(Deserialize)]
+struct ContractTypeListParams {
+    history: Option<String>,
+}
+
+async fn list_contract_types(
+    backend: web::Data<Backend>,
+    query: web::Query<ListQuery>,
+    params: web::Query<ContractTypeListParams>,
+) -> HttpResponse {
+    let args = list_args(query.into_inner(), &[("history", params.into_inner().history)]);
+    let result = run(&backend, "contract_type_ls", args, |pool, args| async move {
+        insurance::list_contract_types(&pool, Some(args)).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn create_contract_type(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    body: web::Json<Value>,
+) -> HttpResponse {
+    let result = run_once(&backend, key, "contract_type_create", body_args(body), |pool, args| async move {
+        insurance::create_contract_type(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::CREATED, result)
+}
+
+async fn update_contract_type(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    path: web::Path<Uuid>,
+    body: Option<web::Json<Value>>,
+) -> HttpResponse {
+    let args = with_field(body, "uuid", json!(path.into_inner()));
+    let result = run_once(&backend, key, "contract_type_update", args, |pool, args| async move {
+        insurance::contract_type_update(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn set_contract_type_active(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    path: web::Path<Uuid>,
+    body: Option<web::Json<Value>>,
+) -> HttpResponse {
+    let args = with_field(body, "uuid", json!(path.into_inner()));
+    let result = run_once(&backend, key, "contract_type_set_active", args, |pool, args| async move {
+        insurance::set_active_contract_type(&pool, args).await.map(|_| String::new())
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+// Contracts
+
+async fn list_contracts(backend: web::Data<Backend>, query: web::Query<ListQuery>) -> HttpResponse {
+    let args = list_args(query.into_inner(), &[]);
+    let result = run(&backend, "contract_ls", args, |pool, args| async move {
+        insurance::list_contracts(&pool, Some(args)).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn create_contract(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    body: web::Json<Value>,
+) -> HttpResponse {
+    let result = run_once(&backend, key, "contract_create", body_args(body), |pool, args| async move {
+        shop::create_contract(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::CREATED, result)
+}
+
+async fn get_contract(backend: web::Data<Backend>, path: web::Path<Uuid>) -> HttpResponse {
+    let args = json!({ "uuid": path.into_inner() }).to_string();
+    let result = run(&backend, "contract_get", args, |pool, args| async move {
+        insurance::get_contract(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn cancel_contract(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    path: web::Path<Uuid>,
+    body: Option<web::Json<Value>>,
+) -> HttpResponse {
+    let args = with_field(body, "uuid", json!(path.into_inner()));
+    let result = run_once(&backend, key, "contract_cancel", args, |pool, args| async move {
+        shop::contract_cancel(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn renew_contract(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    path: web::Path<Uuid>,
+    body: Option<web::Json<Value>>,
+) -> HttpResponse {
+    let args = with_field(body, "uuid", json!(path.into_inner()));
+    let result = run_once(&backend, key, "contract_renew", args, |pool, args| async move {
+        shop::contract_renew(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::CREATED, result)
+}
+
+// Claims
+
+// The adjuster signs in with HTTP Basic credentials
+async fn list_claims(
+    backend: web::Data<Backend>,
+    req: HttpRequest,
+    query: web::Query<ListQuery>,
+) -> HttpResponse {
+    let mut args = serde_json::to_value(query.into_inner()).unwrap();
+    if let Some((username, password)) = basic_credentials(&req) {
+        args["adjuster"] = json!({ "username": username, "password": password });
+    }
+    let args = args.to_string();
+    let result = run(&backend, "claim_ls", args, |pool, args| async move {
+        insurance::list_claims(&pool, Some(args)).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn file_claim(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    body: web::Json<Value>,
+) -> HttpResponse {
+    let result = run_once(&backend, key, "claim_file", body_args(body), |pool, args| async move {
+        insurance::file_claim(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::CREATED, result)
+}
+
+async fn process_claim(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    path: web::Path<Uuid>,
+    body: Option<web::Json<Value>>,
+) -> HttpResponse {
+    let args = with_field(body, "uuid", json!(path.into_inner()));
+    let result = run_once(&backend, key, "claim_process", args, |pool, args| async move {
+        insurance::process_claim(&pool, args).await.map(|_| String::new())
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn claim_valuation(backend: web::Data<Backend>, path: web::Path<Uuid>) -> HttpResponse {
+    let args = json!({ "uuid": path.into_inner() }).to_string();
+    let result = run(&backend, "claim_valuation", args, |pool, args| async move {
+        insurance::claim_valuation(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+// Repair orders
+
+async fn list_repair_orders(backend: web::Data<Backend>, query: web::Query<ListQuery>) -> HttpResponse {
+    let args = list_args(query.into_inner(), &[]);
+    let result = run(&backend, "repair_order_ls", args, |pool, args| async move {
+        repairs::list_repair_orders(&pool, Some(args)).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn complete_repair_order(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    path: web::Path<Uuid>,
+) -> HttpResponse {
+    let args = json!({ "uuid": path.into_inner() }).to_string();
+    let result = run_once(&backend, key, "repair_order_complete", args, |pool, args| async move {
+        repairs::complete_repair_order(&pool, args).await.map(|_| String::new())
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+// Theft claims
+
+async fn list_theft_claims(backend: web::Data<Backend>, query: web::Query<ListQuery>) -> HttpResponse {
+    let args = list_args(query.into_inner(), &[]);
+    let result = run(&backend, "theft_claim_ls", args, |pool, args| async move {
+        police::list_theft_claims(&pool, Some(args)).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn process_theft_claim(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    path: web::Path<Uuid>,
+    body: Option<web::Json<Value>>,
+) -> HttpResponse {
+    let args = with_field(body, "uuid", json!(path.into_inner()));
+    let result = run_once(&backend, key, "theft_claim_process", args, |pool, args| async move {
+        police::process_theft_claim(&pool, args).await.map(|_| String::new())
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+// Users
+
+async fn get_user(backend: web::Data<Backend>, path: web::Path<String>) -> HttpResponse {
+    let args = json!({ "username": path.into_inner() }).to_string();
+    let result = run(&backend, "user_get_info", args, |pool, args| async move {
+        insurance::get_user(&pool, args).await?.ok_or_else(|| {
+            Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "User could not be found.",
+            )))
+        })
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn update_password(
+    backend: web::Data<Backend>,
+    key: IdempotencyKey,
+    path: web::Path<String>,
+    body: Option<web::Json<Value>>,
+) -> HttpResponse {
+    let args = with_field(body, "username", json!(path.into_inner()));
+    let result = run_once(&backend, key, "password_update", args, |pool, args| async move {
+        insurance::update_password(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+async fn authenticate_user(
+    backend: web::Data<Backend>,
+    path: web::Path<String>,
+    body: Option<web::Json<Value>>,
+) -> HttpResponse {
+    let args = with_field(body, "username", json!(path.into_inner()));
+    let result = run(&backend, "user_authenticate", args, |pool, args| async move {
+        insurance::auth_user(&pool, args).await
+    })
+    .await;
+    respond(StatusCode::OK, result)
+}
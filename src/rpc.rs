@@ -0,0 +1,412 @@
+use actix_web::{http::StatusCode, HttpResponse};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{Error, Pool, Postgres};
+use std::collections::BTreeMap;
+
+use crate::adjusters::{AssignClaimDto, ClaimQueueDto, CreateAdjusterDto};
+use crate::appeals::{ClaimAppeal, DecideAppealDto, FileAppealDto};
+use crate::batch::{self, BatchDto, BatchError, BatchResult};
+use crate::data::{Claim, ContractType};
+use crate::documents::{ClaimDocument, ClaimDocumentContent, GetClaimDocumentDto, ListClaimDocumentsDto, UploadClaimDocumentDto};
+use crate::idempotency;
+use crate::ids::{ClientIds, CreatedResult};
+use crate::insurance::{
+    ActivateContractTypeDto, AuthMagicDto, AuthUserDto, ClaimListDto, ClaimResult, ClaimValuationDto,
+    ClaimValuationResult, ContractResult, ContractTypeListDto, ContractTypeResult, FileClaimDto, GetContractDto,
+    GetUserDto, NewContractTypeDto, ProcessClaimDto, UpdateContractTypeDto, UpdatePasswordDto, UserResponse,
+};
+use crate::listing::{ListQuery, Page};
+use crate::messages::{ClaimMessage, ListClaimMessagesDto, PostClaimMessageDto};
+use crate::openapi::{schema, Output::{self, Json, Message, Nothing}, Schema};
+use crate::police::{ProcessTheftClaimDto, TheftClaimResult};
+use crate::repairs::{CompleteRepairOrderDto, RepairOrderResult};
+use crate::rest;
+use crate::search::{SearchDto, SearchHit};
+use crate::settlements::{ListSettlementsDto, SettlementsResult};
+use crate::shop::{
+    CancelContractDto, CancelContractResult, CreateContractDto, CreateUserDto, NewContractResult, RenewContractDto,
+    RenewContractResult,
+};
+use crate::sla::{OverdueClaim, SlaReportDto};
+use crate::storage::Backend;
+use crate::workflow;
+use crate::{adjusters, appeals, documents, insurance, messages, police, repairs, search, settlements, shop, sla};
+
+// JSON-RPC 2.0 over /invoke, alongside the { function, parameters } envelope.
+// The calls of a batch run one after the other in the given order; calls
+// without an id are notifications, they run but get no response. Calls carry
+// their idempotency key in an `idempotency_key` member; the Idempotency-Key
+// header is used for a single call without one.
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const SERVER_ERROR: i64 = -32000; // Handler errors, the HTTP status of the REST API in `data`
+
+#[derive(Debug, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcResponse {
+    pub jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+struct Call {
+    method: String,
+    params: Value,
+    id: Option<Value>, // None for notifications
+    idempotency_key: Option<String>,
+}
+
+impl RpcResponse {
+    fn success(id: Value, result: Value) -> Self {
+        RpcResponse { jsonrpc: "2.0", result: Some(result), error: None, id }
+    }
+
+    fn failure(id: Value, error: RpcError) -> Self {
+        RpcResponse { jsonrpc: "2.0", result: None, error: Some(error), id }
+    }
+}
+
+fn rpc_error(code: i64, message: impl Into<String>) -> RpcError {
+    RpcError { code, message: message.into(), data: None }
+}
+
+// JSON-RPC requests carry the protocol version, batches are arrays
+pub fn is_rpc(body: &Value) -> bool {
+    body.is_array() || body.get("jsonrpc").is_some()
+}
+
+// Response to a body that is not valid JSON
+pub fn parse_error() -> HttpResponse {
+    HttpResponse::Ok().json(RpcResponse::failure(Value::Null, rpc_error(PARSE_ERROR, "Parse error.")))
+}
+
+pub async fn handle(backend: &Backend, body: Value, key: Option<String>) -> HttpResponse {
+    match respond_to(backend, body, key).await {
+        Some(response) => HttpResponse::Ok().json(response),
+        None => HttpResponse::NoContent().finish(),
+    }
+}
+
+// Response to a call or a batch; None when only notifications were sent. The
+// key of the header only applies to a single call.
+pub async fn respond_to(backend: &Backend, body: Value, key: Option<String>) -> Option<Value> {
+    match body {
+        Value::Array(calls) if calls.is_empty() => {
+            Some(json!(RpcResponse::failure(Value::Null, rpc_error(INVALID_REQUEST, "Empty batch."))))
+        }
+        Value::Array(calls) => {
+            let mut responses = Vec::new();
+            for call in calls {
+                if let Some(response) = respond(backend, call, None).await {
+                    responses.push(response);
+                }
+            }
+
+            // A batch of notifications gets no response at all
+            if responses.is_empty() {
+                None
+            } else {
+                Some(json!(responses))
+            }
+        }
+        call => respond(backend, call, key).await.map(|response| json!(response)),
+    }
+}
+
+fn parse_call(value: Value) -> Result<Call, RpcError> {
+    let invalid = |message: &str| rpc_error(INVALID_REQUEST, message);
+
+    let mut object = match value {
+        Value::Object(object) => object,
+        _ => return Err(invalid("Request must be an object.")),
+    };
+    if object.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err(invalid("Only JSON-RPC 2.0 is supported."));
+    }
+    let method = match object.remove("method") {
+        Some(Value::String(method)) => method,
+        _ => return Err(invalid("Missing or invalid method.")),
+    };
+    let params = object.remove("params").unwrap_or(Value::Null);
+    if !(params.is_object() || params.is_array() || params.is_null()) {
+        return Err(invalid("Params must be an object or an array."));
+    }
+    let id = object.remove("id");
+    if let Some(ref id) = id {
+        if !(id.is_string() || id.is_number() || id.is_null()) {
+            return Err(invalid("Id must be a string, a number or null."));
+        }
+    }
+
+    let idempotency_key = match object.remove("idempotency_key") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(key)) => Some(key),
+        Some(_) => return Err(invalid("Idempotency key must be a string.")),
+    };
+
+    Ok(Call { method, params, id, idempotency_key })
+}
+
+// Run one call; None for notifications
+async fn respond(backend: &Backend, value: Value, key: Option<String>) -> Option<RpcResponse> {
+    let call = match parse_call(value) {
+        Ok(call) => call,
+        Err(error) => return Some(RpcResponse::failure(Value::Null, error)),
+    };
+    let key = call.idempotency_key.or(key);
+
+    let outcome = match invoke_once(backend, key.as_deref(), &call.method, call.params.to_string()).await {
+        Some(Ok(body)) => Ok(result_value(body)),
+        Some(Err(err)) => Err(error_of(&err)),
+        None => Err(rpc_error(METHOD_NOT_FOUND, format!("Method '{}' not found.", call.method))),
+    };
+
+    let id = call.id?;
+    Some(match outcome {
+        Ok(result) => RpcResponse::success(id, result),
+        Err(error) => RpcResponse::failure(id, error),
+    })
+}
+
+// JSON results as they are, plain messages as strings, nothing as null
+pub fn result_value(body: String) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_str(&body).unwrap_or(Value::String(body))
+}
+
+fn error_of(err: &Error) -> RpcError {
+    let status = rest::error_status(err);
+    let message = rest::error_message(err);
+    let mut error = match status {
+        StatusCode::BAD_REQUEST => rpc_error(INVALID_PARAMS, message),
+        StatusCode::NOT_IMPLEMENTED => rpc_error(METHOD_NOT_FOUND, message),
+        StatusCode::INTERNAL_SERVER_ERROR => rpc_error(INTERNAL_ERROR, message),
+        _ => RpcError {
+            code: SERVER_ERROR,
+            message,
+            data: Some(json!({ "status": status.as_u16() })),
+        },
+    };
+
+    // Failed batches tell which call failed
+    if let Error::Decode(ref source) = err {
+        if let Some(batch) = source.downcast_ref::<BatchError>() {
+            let data = error.data.get_or_insert_with(|| json!({}));
+            data["index"] = json!(batch.index);
+            data["function"] = json!(batch.function);
+        }
+    }
+
+    error
+}
+
+// Run a registered function by name and return its result; None when no such
+// function exists. Functions the backend does not serve fail as unsupported.
+pub async fn invoke(backend: &Backend, function: &str, args: String) -> Option<Result<String, Error>> {
+    // Batches run on every backend, in a transaction of their own
+    if function == "batch" {
+        return Some(batch::batch(backend, args, batch::client_ids()).await);
+    }
+
+    if !is_registered(function) {
+        return None;
+    }
+
+    match backend {
+        Backend::Postgres(storage) => invoke_postgres(storage.pool(), function, args).await,
+        Backend::Sqlite(storage) => Some(workflow::invoke(storage, function, args, ClientIds::Refused).await.unwrap_or_else(|| {
+            Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("Function '{}' needs the Postgres backend.", function),
+            ))))
+        })),
+    }
+}
+
+// Run a function, at most once per idempotency key when it changes state
+pub async fn invoke_once(
+    backend: &Backend,
+    key: Option<&str>,
+    function: &str,
+    args: String,
+) -> Option<Result<String, Error>> {
+    if key.is_none() || !idempotency::is_mutating(function) {
+        return invoke(backend, function, args).await;
+    }
+
+    let result = idempotency::once(backend.storage(), key, function, args, |args| async move {
+        invoke(backend, function, args).await.unwrap_or_else(|| {
+            Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("Function '{}' is not available.", function),
+            ))))
+        })
+    })
+    .await;
+    Some(result)
+}
+
+// A function served over /invoke, with what the OpenAPI document tells of it
+pub struct Function {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub mutating: bool, // Changes state, so calls honor idempotency keys
+    pub input: Schema, // Parameters
+    pub output: Output,
+}
+
+const fn reads(name: &'static str, summary: &'static str, input: Schema, output: Output) -> Function {
+    Function { name, summary, mutating: false, input, output }
+}
+
+const fn writes(name: &'static str, summary: &'static str, input: Schema, output: Output) -> Function {
+    Function { name, summary, mutating: true, input, output }
+}
+
+// Every function served over /invoke, in the order of invoke_postgres. The
+// dispatch, the OpenAPI document and the idempotency keys all go by it.
+pub const FUNCTIONS: &[Function] = &[
+    // Insurance Peer
+    reads("contract_type_ls", "List contract types", schema::<ContractTypeListDto>, Json(schema::<Page<ContractTypeResult>>)),
+    writes("contract_type_create", "Create a contract type", schema::<NewContractTypeDto>, Json(schema::<CreatedResult>)),
+    writes("contract_type_set_active", "Activate or deactivate a contract type", schema::<ActivateContractTypeDto>, Nothing),
+    writes("contract_type_update", "Publish a new version of a contract type", schema::<UpdateContractTypeDto>, Json(schema::<ContractType>)),
+    reads("contract_ls", "List contracts", schema::<ListQuery>, Json(schema::<Page<ContractResult>>)),
+    reads("contract_get", "Get a contract with its claims", schema::<GetContractDto>, Json(schema::<ContractResult>)),
+    reads("claim_ls", "List claims", schema::<ClaimListDto>, Json(schema::<Page<ClaimResult>>)),
+    writes("claim_file", "File a claim", schema::<FileClaimDto>, Json(schema::<CreatedResult>)),
+    writes("claim_process", "Decide on a claim", schema::<ProcessClaimDto>, Nothing),
+    reads("claim_valuation", "Value the insured item of a claim", schema::<ClaimValuationDto>, Json(schema::<ClaimValuationResult>)),
+    reads("claim_settlement_ls", "List the settlements of a claim", schema::<ListSettlementsDto>, Json(schema::<SettlementsResult>)),
+    writes("adjuster_create", "Create an adjuster", schema::<CreateAdjusterDto>, Nothing),
+    writes("claim_assign", "Assign a claim to an adjuster", schema::<AssignClaimDto>, Nothing),
+    reads("claim_queue", "List the open claims of an adjuster", schema::<ClaimQueueDto>, Json(schema::<Vec<Claim>>)),
+    reads("sla_report", "Report overdue claims by peer", schema::<SlaReportDto>, Json(schema::<BTreeMap<String, Vec<OverdueClaim>>>)),
+    writes("claim_appeal", "Appeal a rejected claim", schema::<FileAppealDto>, Json(schema::<ClaimAppeal>)),
+    writes("claim_appeal_decide", "Decide on a claim appeal", schema::<DecideAppealDto>, Nothing),
+    reads("user_authenticate", "Check the password of a user", schema::<AuthUserDto>, Json(schema::<bool>)),
+    writes("password_update", "Change the password of a user", schema::<UpdatePasswordDto>, Message),
+    reads("magic_authenticate", "Check that a user exists", schema::<AuthMagicDto>, Json(schema::<bool>)),
+    reads("user_get_info", "Get the details of a user", schema::<GetUserDto>, Json(schema::<Option<UserResponse>>)),
+
+    // Shop Peer
+    writes("contract_create", "Sell a contract, registering the customer if new", schema::<CreateContractDto>, Json(schema::<NewContractResult>)),
+    writes("user_create", "Register a customer", schema::<CreateUserDto>, Nothing),
+    writes("contract_cancel", "Cancel a contract", schema::<CancelContractDto>, Json(schema::<CancelContractResult>)),
+    writes("contract_renew", "Renew a contract", schema::<RenewContractDto>, Json(schema::<RenewContractResult>)),
+
+    // Repair Shop Peer
+    reads("repair_order_ls", "List open repair orders", schema::<ListQuery>, Json(schema::<Page<RepairOrderResult>>)),
+    writes("repair_order_complete", "Complete a repair order", schema::<CompleteRepairOrderDto>, Nothing),
+
+    // Police Peer
+    reads("theft_claim_ls", "List theft claims to confirm", schema::<ListQuery>, Json(schema::<Page<TheftClaimResult>>)),
+    writes("theft_claim_process", "Confirm or reject a theft", schema::<ProcessTheftClaimDto>, Nothing),
+
+    // Claim documents (all peers)
+    writes("claim_document_upload", "Attach a document to a claim", schema::<UploadClaimDocumentDto>, Json(schema::<ClaimDocument>)),
+    reads("claim_document_ls", "List the documents of a claim", schema::<ListClaimDocumentsDto>, Json(schema::<Vec<ClaimDocument>>)),
+    reads("claim_document_get", "Get a claim document with its content", schema::<GetClaimDocumentDto>, Json(schema::<ClaimDocumentContent>)),
+
+    // Claim messages (all peers)
+    writes("claim_message_post", "Post a message on a claim", schema::<PostClaimMessageDto>, Json(schema::<ClaimMessage>)),
+    reads("claim_message_ls", "List the messages of a claim", schema::<ListClaimMessagesDto>, Json(schema::<Vec<ClaimMessage>>)),
+
+    // Search (all peers)
+    reads("search", "Search claims, contracts and customers", schema::<SearchDto>, Json(schema::<Vec<SearchHit>>)),
+
+    // Batches (all peers)
+    writes("batch", "Run several functions in one transaction", schema::<BatchDto>, Json(schema::<BatchResult>)),
+];
+
+pub fn registered(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+pub fn is_registered(name: &str) -> bool {
+    registered(name).is_some()
+}
+
+// No arguments for optional inputs given as null
+fn optional(args: String) -> Option<String> {
+    match args.trim() {
+        "" | "null" => None,
+        _ => Some(args),
+    }
+}
+
+async fn invoke_postgres(pool: &Pool<Postgres>, function: &str, args: String) -> Option<Result<String, Error>> {
+    let done = |result: Result<(), Error>| result.map(|_| String::new());
+
+    let result = match function {
+        // Insurance Peer
+        "contract_type_ls" => insurance::list_contract_types(pool, optional(args)).await,
+        "contract_type_create" => insurance::create_contract_type(pool, args).await,
+        "contract_type_set_active" => done(insurance::set_active_contract_type(pool, args).await),
+        "contract_type_update" => insurance::contract_type_update(pool, args).await,
+        "contract_ls" => insurance::list_contracts(pool, optional(args)).await,
+        "contract_get" => insurance::get_contract(pool, args).await,
+        "claim_ls" => insurance::list_claims(pool, optional(args)).await,
+        "claim_file" => insurance::file_claim(pool, args).await,
+        "claim_process" => done(insurance::process_claim(pool, args).await),
+        "claim_valuation" => insurance::claim_valuation(pool, args).await,
+        "claim_settlement_ls" => settlements::claim_settlement_ls(pool, args).await,
+        "adjuster_create" => done(adjusters::adjuster_create(pool, args).await),
+        "claim_assign" => done(adjusters::claim_assign(pool, args).await),
+        "claim_queue" => adjusters::claim_queue(pool, args).await,
+        "sla_report" => sla::sla_report(pool, optional(args)).await,
+        "claim_appeal" => appeals::claim_appeal(pool, args).await,
+        "claim_appeal_decide" => done(appeals::claim_appeal_decide(pool, args).await),
+        "user_authenticate" => insurance::auth_user(pool, args).await,
+        "password_update" => insurance::update_password(pool, args).await,
+        "magic_authenticate" => insurance::auth_magic(pool, args).await,
+        "user_get_info" => insurance::get_user(pool, args).await.map(|user| user.unwrap_or_else(|| "null".to_string())),
+
+        // Shop Peer
+        "contract_create" => shop::create_contract(pool, args).await,
+        "user_create" => done(shop::create_user(pool, args).await),
+        "contract_cancel" => shop::contract_cancel(pool, args).await,
+        "contract_renew" => shop::contract_renew(pool, args).await,
+
+        // Repair Shop Peer
+        "repair_order_ls" => repairs::list_repair_orders(pool, optional(args)).await,
+        "repair_order_complete" => done(repairs::complete_repair_order(pool, args).await),
+
+        // Police Peer
+        "theft_claim_ls" => police::list_theft_claims(pool, optional(args)).await,
+        "theft_claim_process" => done(police::process_theft_claim(pool, args).await),
+
+        // Claim documents (all peers)
+        "claim_document_upload" => documents::claim_document_upload(pool, args).await,
+        "claim_document_ls" => documents::claim_document_ls(pool, args).await,
+        "claim_document_get" => documents::claim_document_get(pool, args).await,
+
+        // Claim messages (all peers)
+        "claim_message_post" => messages::claim_message_post(pool, args).await,
+        "claim_message_ls" => messages::claim_message_ls(pool, args).await,
+
+        // Search (all peers)
+        "search" => search::search(pool, args).await,
+
+        _ => return None,
+    };
+
+    Some(result)
+}
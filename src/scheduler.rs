@@ -0,0 +1,121 @@
+use sqlx::{Error, Executor, PgPool, Pool, Postgres};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::config::{days_or, env_or};
+use crate::sla;
+
+// Background jobs run periodically next to the HTTP server
+
+const DEFAULT_SCHEDULER_INTERVAL_SECS: u64 = 3600;
+const DEFAULT_CONTRACT_REMINDER_DAYS: i64 = 30;
+
+pub async fn run(pool: PgPool) {
+    let interval_secs = env_or("SCHEDULER_INTERVAL_SECS", DEFAULT_SCHEDULER_INTERVAL_SECS);
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = expire_contracts(&pool).await {
+            eprintln!("Failed to expire contracts: {:?}", err);
+        }
+        if let Err(err) = send_expiry_reminders(&pool).await {
+            eprintln!("Failed to send expiry reminders: {:?}", err);
+        }
+        if let Err(err) = sla::check_breaches(&pool).await {
+            eprintln!("Failed to check claim SLAs: {:?}", err);
+        }
+    }
+}
+
+// Record an event against a contract, e.g. an expiry reminder
+pub async fn record_contract_event<'e, E>(
+    executor: E,
+    contract_uuid: Uuid,
+    kind: &str,
+    payload: serde_json::Value,
+) -> Result<(), Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query!(
+        r#"
+        INSERT INTO contract_events (id, contract_uuid, kind, created_at, payload)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        contract_uuid,
+        kind,
+        Utc::now().naive_utc(),
+        payload
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn expire_contracts(pool: &Pool<Postgres>) -> Result<u64, Error> {
+    let now = Utc::now().naive_utc();
+    let mut tx = pool.begin().await?;
+
+    // Mark every contract past its end date as expired, together with its events
+    let expired = sqlx::query!(
+        r#"
+        UPDATE contracts
+        SET expired = TRUE
+        WHERE expired = FALSE AND end_date < $1
+        RETURNING id
+        "#,
+        now
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    for row in &expired {
+        record_contract_event(&mut tx, row.id, "expired", json!({ "expired_at": now })).await?;
+    }
+    tx.commit().await?;
+
+    Ok(expired.len() as u64)
+}
+
+pub async fn send_expiry_reminders(pool: &Pool<Postgres>) -> Result<u64, Error> {
+    let now = Utc::now().naive_utc();
+    let reminder_window = days_or("CONTRACT_REMINDER_DAYS", DEFAULT_CONTRACT_REMINDER_DAYS);
+    let mut tx = pool.begin().await?;
+
+    // Contracts ending within the reminder window that were not reminded yet,
+    // marked in the same transaction as their reminder events
+    let due = sqlx::query!(
+        r#"
+        UPDATE contracts
+        SET reminder_sent_at = $1
+        WHERE void = FALSE
+            AND expired = FALSE
+            AND cancelled_at IS NULL
+            AND reminder_sent_at IS NULL
+            AND end_date BETWEEN $1 AND $2
+        RETURNING id, username, end_date
+        "#,
+        now,
+        now + reminder_window
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    for row in &due {
+        record_contract_event(
+            &mut tx,
+            row.id,
+            "expiry_reminder",
+            json!({ "username": row.username, "end_date": row.end_date }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(due.len() as u64)
+}
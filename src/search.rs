@@ -0,0 +1,148 @@
+use sqlx::{Error, Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use bcrypt::verify;
+
+use crate::data::PeerRole;
+use crate::messages::Caller;
+use crate::storage::{PgStorage, Storage};
+
+// Search for claims by description, contracts by item and customers by name.
+// Postgres ranks with full-text search, other backends by the share of search
+// terms found in the text.
+
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+    Claim,
+    Contract,
+    Customer,
+}
+
+// Records a caller may find, following the claim access rules of the message
+// threads: the insurer finds everything, police theft claims, repair shops
+// claims with a repair order, claimants their own records.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchScope {
+    Everything,
+    TheftClaims,
+    RepairedClaims,
+    Customer(String),
+}
+
+impl SearchScope {
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            SearchScope::Customer(username) => Some(username),
+            _ => None,
+        }
+    }
+
+    pub fn theft_only(&self) -> bool {
+        *self == SearchScope::TheftClaims
+    }
+
+    pub fn repaired_only(&self) -> bool {
+        *self == SearchScope::RepairedClaims
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SearchHit {
+    pub kind: SearchKind,
+    pub uuid: String, // Username for customers
+    pub title: String,
+    pub snippet: String,
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SearchDto {
+    pub query: String,
+    pub kinds: Option<Vec<SearchKind>>, // All kinds if omitted
+    pub limit: Option<i64>,
+    #[serde(flatten)]
+    pub caller: Caller,
+}
+
+pub async fn search(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let dto: SearchDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    search_records(&PgStorage::new(pool), dto).await
+}
+
+pub async fn search_records(storage: &dyn Storage, dto: SearchDto) -> Result<String, Error> {
+    let terms = dto.query.trim();
+    if terms.is_empty() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Search query is empty.",
+        ))));
+    }
+
+    let scope = caller_scope(storage, &dto.caller).await?;
+    let kinds = dto
+        .kinds
+        .unwrap_or_else(|| vec![SearchKind::Claim, SearchKind::Contract, SearchKind::Customer]);
+    let limit = dto.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+
+    // Best matches of all kinds first
+    let hits = best_hits(storage.search(terms, &kinds, &scope, limit).await?, limit);
+
+    serde_json::to_string(&hits).map_err(|err| {
+        eprintln!("Failed to serialize search results: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+async fn caller_scope(storage: &dyn Storage, caller: &Caller) -> Result<SearchScope, Error> {
+    match caller.role {
+        PeerRole::Insurer => Ok(SearchScope::Everything),
+        PeerRole::Police => Ok(SearchScope::TheftClaims),
+        PeerRole::RepairShop => Ok(SearchScope::RepairedClaims),
+        PeerRole::Claimant => {
+            // Claimants authenticate with their password
+            let authenticated = match storage.find_user(&caller.username).await? {
+                Some(user) => verify(caller.password.as_deref().unwrap_or_default(), &user.password).unwrap_or(false),
+                None => false,
+            };
+            if !authenticated {
+                return Err(Error::Decode(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Invalid username or password.",
+                ))));
+            }
+            Ok(SearchScope::Customer(caller.username.clone()))
+        }
+    }
+}
+
+// Best `limit` hits, highest rank first
+pub fn best_hits(mut hits: Vec<SearchHit>, limit: i64) -> Vec<SearchHit> {
+    hits.sort_by(|a, b| b.rank.partial_cmp(&a.rank).unwrap_or(std::cmp::Ordering::Equal));
+    hits.truncate(limit as usize);
+    hits
+}
+
+// Share of the search terms found in the text, case-insensitive; ranks the
+// search on backends without full-text search
+pub fn term_rank(terms: &str, text: &str) -> f32 {
+    let text = text.to_lowercase();
+    let terms: Vec<String> = terms.split_whitespace().map(str::to_lowercase).collect();
+    if terms.is_empty() {
+        return 0.0;
+    }
+
+    let found = terms.iter().filter(|term| text.contains(term.as_str())).count();
+    found as f32 / terms.len() as f32
+}
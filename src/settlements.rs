@@ -0,0 +1,221 @@
+use sqlx::{Error, Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use chrono::{NaiveDateTime, Utc};
+use uuid::Uuid;
+
+use crate::data::PeerRole;
+use crate::messages::{self, Caller};
+use crate::storage::{PgStorage, SettlementRepository, Storage};
+
+// Settlement line items of a claim. Repair costs and cash reimbursements are paid
+// out, deductibles and salvage are subtracted; `claims.reimbursable` holds the total.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SettlementKind {
+    Repair,
+    Cash,
+    Deductible,
+    Salvage,
+}
+
+impl SettlementKind {
+    pub fn to_str(&self) -> &str {
+        match self {
+            SettlementKind::Repair => "repair",
+            SettlementKind::Cash => "cash",
+            SettlementKind::Deductible => "deductible",
+            SettlementKind::Salvage => "salvage",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "repair" => Some(SettlementKind::Repair),
+            "cash" => Some(SettlementKind::Cash),
+            "deductible" => Some(SettlementKind::Deductible),
+            "salvage" => Some(SettlementKind::Salvage),
+            _ => None,
+        }
+    }
+
+    // Direction in which the line counts towards the claim total
+    pub fn sign(&self) -> f32 {
+        match self {
+            SettlementKind::Repair | SettlementKind::Cash => 1.0,
+            SettlementKind::Deductible | SettlementKind::Salvage => -1.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct SettlementDto {
+    pub kind: SettlementKind,
+    pub amount: f32, // Never negative, the kind decides the direction
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ClaimSettlement {
+    pub id: Uuid,
+    pub claim_uuid: Uuid,
+    pub kind: String,
+    pub amount: f32,
+    pub note: Option<String>,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+}
+
+// Claim total of settlement lines: payouts less deductibles and salvage
+pub fn total(settlements: &[ClaimSettlement]) -> f32 {
+    settlements
+        .iter()
+        .map(|line| SettlementKind::from_str(&line.kind).map_or(0.0, |kind| kind.sign()) * line.amount)
+        .sum()
+}
+
+// Add settlement lines to a claim and return the new claim total, which must stay
+// between zero and `insured_value`; the caller stores the total on the claim.
+pub async fn add_settlements(
+    storage: &dyn Storage,
+    claim_uuid: Uuid,
+    lines: &[SettlementDto],
+    created_by: &str,
+    insured_value: f32,
+) -> Result<f32, Error> {
+    if lines.iter().any(|line| !line.amount.is_finite() || line.amount < 0.0) {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Settlement amounts cannot be negative.",
+        ))));
+    }
+
+    let now = Utc::now().naive_utc();
+    let added: Vec<ClaimSettlement> = lines
+        .iter()
+        .map(|line| ClaimSettlement {
+            id: Uuid::new_v4(),
+            claim_uuid,
+            kind: line.kind.to_str().to_string(),
+            amount: line.amount,
+            note: line.note.clone(),
+            created_by: created_by.to_string(),
+            created_at: now,
+        })
+        .collect();
+
+    // The total covers every line of the claim, the recorded ones and the new ones
+    let mut settlements = storage.settlements_of_claim(claim_uuid).await?;
+    settlements.extend(added.iter().cloned());
+    let total = total(&settlements);
+
+    if total < 0.0 {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Settlements cannot bring the claim total below zero.",
+        ))));
+    }
+    // Allowing for rounding to the cent
+    if total - insured_value > 0.005 {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Settlements cannot exceed the insured value of {:.2}.", insured_value),
+        ))));
+    }
+
+    for settlement in &added {
+        storage.insert_settlement(settlement).await?;
+    }
+
+    Ok(total)
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ListSettlementsDto {
+    pub uuid: Uuid,
+    #[serde(flatten)]
+    pub caller: Caller,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SettlementsResult {
+    pub claim_uuid: String,
+    pub settlements: Vec<ClaimSettlement>,
+    pub total: f32,
+}
+
+pub async fn claim_settlement_ls(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse input JSON
+    let input: ListSettlementsDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    // Amounts are between the insurer and the claimant
+    if !matches!(input.caller.role, PeerRole::Insurer | PeerRole::Claimant) {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Only the insurer and the claimant can see the settlements of a claim.",
+        ))));
+    }
+    messages::authorize_claim_access(pool, &input.caller, input.uuid).await?;
+
+    let settlements = PgStorage::new(pool).settlements_of_claim(input.uuid).await?;
+
+    let result = SettlementsResult {
+        claim_uuid: input.uuid.to_string(),
+        total: total(&settlements),
+        settlements,
+    };
+
+    serde_json::to_string(&result).map_err(|err| {
+        eprintln!("Failed to serialize settlements: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(kind: SettlementKind, amount: f32) -> ClaimSettlement {
+        ClaimSettlement {
+            id: Uuid::new_v4(),
+            claim_uuid: Uuid::nil(),
+            kind: kind.to_str().to_string(),
+            amount,
+            note: None,
+            created_by: "adjuster".to_string(),
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_total_subtracts_deductibles_and_salvage() {
+        let lines = vec![
+            line(SettlementKind::Repair, 400.0),
+            line(SettlementKind::Cash, 100.0),
+            line(SettlementKind::Deductible, 50.0),
+            line(SettlementKind::Salvage, 25.0),
+        ];
+        assert_eq!(total(&lines), 425.0);
+    }
+
+    #[test]
+    fn test_total_of_zero_amounts() {
+        assert_eq!(total(&[]), 0.0);
+        assert_eq!(total(&[line(SettlementKind::Cash, 0.0)]), 0.0);
+    }
+
+    #[test]
+    fn test_kinds_round_trip() {
+        for kind in [SettlementKind::Repair, SettlementKind::Cash, SettlementKind::Deductible, SettlementKind::Salvage] {
+            assert_eq!(SettlementKind::from_str(kind.to_str()), Some(kind));
+        }
+        assert_eq!(SettlementKind::from_str("refund"), None);
+    }
+}
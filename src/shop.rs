@@ -1,14 +1,23 @@
 use sqlx::{Error, Pool, Postgres};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use bcrypt::{hash, verify, DEFAULT_COST};
 use uuid::Uuid;
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDateTime, Utc};
 
+use crate::data::{Contract, ContractType, Item, User};
+use crate::formula;
+use crate::ids::{self, ClientIds};
+use crate::peers::{self, PeerAccountRole, PeerCredentials};
+use crate::scheduler::record_contract_event;
+use crate::storage::{PgStorage, Storage};
 
 
-#[derive(Debug, Serialize, Deserialize)]
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct CreateContractDto {
-    pub uuid: Uuid,
+    #[serde(default)]
+    pub uuid: Option<Uuid>, // Generated; only chosen by the caller within a batch
     pub contract_type_uuid: Uuid,
     pub username: String,
     pub password: String,
@@ -19,6 +28,20 @@ pub struct CreateContractDto {
     pub end_date: NaiveDateTime,
 }
 
+// Credentials of a customer registered along with their first contract
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NewCustomerResult {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NewContractResult {
+    pub uuid: Uuid,
+    #[serde(flatten)]
+    pub customer: Option<NewCustomerResult>, // Only for customers registered with this contract
+}
+
 pub async fn create_contract(
     pool: &Pool<Postgres>,
     args: String, // JSON input as a string
@@ -29,22 +52,21 @@ pub async fn create_contract(
         Error::Decode(Box::new(err))
     })?;
 
-    // Check if the user exists
-    let user = sqlx::query_as!(
-        User,
-        r#"
-        SELECT username, password, first_name, last_name
-        FROM users
-        WHERE username = $1
-        "#,
-        dto.username
-    )
-    .fetch_optional(pool)
-    .await?;
+    open_contract(&PgStorage::new(pool), dto, ClientIds::Refused).await
+}
+
+// Sell a contract, registering the customer on their first purchase
+pub async fn open_contract(
+    storage: &dyn Storage,
+    dto: CreateContractDto,
+    client_ids: ClientIds,
+) -> Result<String, Error> {
+    let id = ids::assign_id(dto.uuid, client_ids)?;
 
-    let mut user_password_hashed = String::new();
+    // Check if the user exists
+    let user = storage.find_user(&dto.username).await?;
 
-    if let Some(existing_user) = user {
+    if let Some(existing_user) = &user {
         // Verify password for existing user
         if !verify(&dto.password, &existing_user.password).unwrap_or(false) {
             return Err(Error::Decode(Box::new(std::io::Error::new(
@@ -52,56 +74,456 @@ pub async fn create_contract(
                 "Invalid credentials.",
             ))));
         }
-        user_password_hashed = existing_user.password;
     } else {
         // Hash the password for a new user
-        user_password_hashed = hash(&dto.password, DEFAULT_COST).map_err(|err| {
+        let user_password_hashed = hash(&dto.password, DEFAULT_COST).map_err(|err| {
             eprintln!("Password hashing failed: {:?}", err);
             Error::Decode(Box::new(err))
         })?;
 
-        // Insert the new user into the database
-        sqlx::query!(
-            r#"
-            INSERT INTO users (username, password, first_name, last_name)
-            VALUES ($1, $2, $3, $4)
-            "#,
-            dto.username,
-            user_password_hashed,
-            dto.first_name,
-            dto.last_name
-        )
-        .execute(pool)
-        .await?;
+        // Insert the new user
+        storage
+            .insert_user(&User {
+                username: dto.username.clone(),
+                password: user_password_hashed,
+                first_name: dto.first_name.clone(),
+                last_name: dto.last_name.clone(),
+                contract_index: Vec::new(),
+            })
+            .await?;
+    }
+
+    // New contracts are sold under the current version of the contract type
+    let contract_type = storage.find_contract_type(dto.contract_type_uuid).await?;
+    match contract_type {
+        Some(ct) if ct.superseded_by.is_none() && ct.active => {}
+        Some(_) => {
+            return Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Contract Type version is no longer offered.",
+            ))));
+        }
+        None => {
+            return Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Contract Type could not be found.",
+            ))));
+        }
     }
 
     // Create the contract
-    let contract_id = dto.uuid;
+    storage
+        .insert_contract(&Contract {
+            id,
+            username: dto.username.clone(),
+            item: dto.item,
+            start_date: dto.start_date,
+            end_date: dto.end_date,
+            void: false, // Contract is not void
+            contract_type_uuid: dto.contract_type_uuid,
+            claim_index: None,
+            cancelled_at: None,
+            cancel_reason: None,
+            refund: None,
+            expired: false,
+            renewed_from: None,
+        })
+        .await?;
+
+    // Respond with the contract id, and the created user details if a new user was created
+    let response = NewContractResult {
+        uuid: id,
+        customer: user.is_none().then(|| NewCustomerResult {
+            username: dto.username,
+            password: dto.password, // Return the original password
+        }),
+    };
+    serde_json::to_string(&response).map_err(|err| {
+        eprintln!("Failed to serialize results to JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CreateUserDto {
+    pub username: String,
+    pub password: String,
+    pub first_name: String,
+    pub last_name: String,
+}
+
+pub async fn create_user(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<(), Error> {
+    // Parse the input JSON
+    let dto: CreateUserDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    register_user(&PgStorage::new(pool), dto).await
+}
+
+// Register a customer ahead of their first contract
+pub async fn register_user(storage: &dyn Storage, dto: CreateUserDto) -> Result<(), Error> {
+    if storage.find_user(&dto.username).await?.is_some() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "User already exists.",
+        ))));
+    }
+
+    let password = hash(&dto.password, DEFAULT_COST).map_err(|err| {
+        eprintln!("Password hashing failed: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    storage
+        .insert_user(&User {
+            username: dto.username,
+            password,
+            first_name: dto.first_name,
+            last_name: dto.last_name,
+            contract_index: Vec::new(),
+        })
+        .await
+}
+
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CancellationRequester {
+    Customer,
+    Shop,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CancelContractDto {
+    pub uuid: Uuid,
+    pub requested_by: CancellationRequester,
+    pub username: String,
+    pub password: Option<String>, // Required when the customer cancels
+    pub shop: Option<PeerCredentials>, // Required when the shop cancels
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CancelContractResult {
+    pub uuid: String,
+    pub cancelled_at: NaiveDateTime,
+    pub unused_days: i64,
+    pub refund: f32,
+}
+
+pub async fn contract_cancel(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse the input JSON
+    let dto: CancelContractDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    // Fetch the contract
+    let contract = Contract::find(pool, dto.uuid).await?.ok_or_else(|| {
+        eprintln!("Contract with UUID {} not found.", dto.uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract could not be found.",
+        )))
+    })?;
+
+    if contract.username != dto.username {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Contract does not belong to this user.",
+        ))));
+    }
+
+    // Customers prove their identity, shops sign in with their own account
+    match dto.requested_by {
+        CancellationRequester::Customer => {
+            let user = contract.user(pool).await?;
+            let password = dto.password.as_deref().unwrap_or_default();
+            if !verify(password, &user.password).unwrap_or(false) {
+                return Err(Error::Decode(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Invalid credentials.",
+                ))));
+            }
+        }
+        CancellationRequester::Shop => {
+            peers::authenticate(pool, PeerAccountRole::Shop, dto.shop.as_ref()).await?;
+        }
+    }
+
+    // Lock the contract so no claim can be filed and no other cancellation can
+    // run between the checks below and the update
+    let mut tx = pool.begin().await?;
+
+    let locked = sqlx::query!(
+        r#"
+        SELECT void, expired, cancelled_at
+        FROM contracts
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        contract.id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    if locked.void || locked.cancelled_at.is_some() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Contract is already void or cancelled.",
+        ))));
+    }
+
+    if locked.expired {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Expired contracts cannot be cancelled.",
+        ))));
+    }
+
+    // Refuse cancellation while a claim is still being handled
+    let open_claims = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM claims
+        WHERE contract_uuid = $1
+            AND (status IN ('New', 'TheftConfirmed', 'ManualReview', 'UnderAppeal') OR (status = 'Repair' AND repaired = FALSE))
+        "#,
+        contract.id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    if open_claims > 0 {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Contract has open claims and cannot be cancelled.",
+        ))));
+    }
+
+    let contract_type = contract.contract_type(pool).await?.ok_or_else(|| {
+        eprintln!("Contract type with UUID {} not found.", contract.contract_type_uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract Type could not be found.",
+        )))
+    })?;
+
+    // Refund the premium for every day left between now and the end date
+    let cancelled_at = Utc::now().naive_utc();
+    let refund_from = cancelled_at.max(contract.start_date);
+    let unused_days = (contract.end_date - refund_from).num_days().max(0);
+
+    let per_day = formula::premium_per_day(&contract_type.formula_per_day, contract.item.price)
+        .map_err(|err| {
+            eprintln!("Failed to price contract {}: {}", contract.id, err);
+            Error::Decode(Box::new(err))
+        })?;
+    let refund = per_day * unused_days as f32;
+
+    // Persist the cancellation; a cancelled contract is told apart by its cancelled_at
     sqlx::query!(
         r#"
-        INSERT INTO contracts (id, username, contract_type_uuid, item, start_date, end_date, void, claim_index)
+        UPDATE contracts
+        SET cancelled_at = $1, cancel_reason = $2, refund = $3
+        WHERE id = $4
+        "#,
+        cancelled_at,
+        dto.reason,
+        refund,
+        contract.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let result = CancelContractResult {
+        uuid: contract.id.to_string(),
+        cancelled_at,
+        unused_days,
+        refund,
+    };
+
+    serde_json::to_string(&result).map_err(|err| {
+        eprintln!("Failed to serialize cancellation result: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RenewContractDto {
+    pub uuid: Uuid, // Contract being renewed
+    #[serde(default)]
+    pub renewal_uuid: Option<Uuid>, // Successor contract, generated
+    pub username: String,
+    pub password: String,
+    pub duration_days: Option<i64>, // Defaults to the duration of the original contract
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RenewContractResult {
+    pub uuid: String,
+    pub renewed_from: String,
+    pub start_date: NaiveDateTime,
+    pub end_date: NaiveDateTime,
+    pub premium_per_day: f32,
+    pub premium: f32,
+}
+
+pub async fn contract_renew(
+    pool: &Pool<Postgres>,
+    args: String, // JSON input as a string
+) -> Result<String, Error> {
+    // Parse the input JSON
+    let dto: RenewContractDto = serde_json::from_str(&args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })?;
+
+    // Fetch the contract being renewed
+    let contract = Contract::find(pool, dto.uuid).await?.ok_or_else(|| {
+        eprintln!("Contract with UUID {} not found.", dto.uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract could not be found.",
+        )))
+    })?;
+
+    // Verify the customer owns the contract
+    let user = contract.user(pool).await?;
+    if user.username != dto.username || !verify(&dto.password, &user.password).unwrap_or(false) {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Invalid credentials.",
+        ))));
+    }
+
+    if contract.void || contract.cancelled_at.is_some() {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Void or cancelled contracts cannot be renewed.",
+        ))));
+    }
+
+    // Only one successor per contract
+    let already_renewed = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM contracts WHERE renewed_from = $1
+        ) AS "exists!"
+        "#,
+        contract.id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if already_renewed {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "Contract has already been renewed.",
+        ))));
+    }
+
+    // Re-quote against the current version of the contract type
+    let sold_under = contract.contract_type(pool).await?.ok_or_else(|| {
+        eprintln!("Contract type with UUID {} not found.", contract.contract_type_uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract Type could not be found.",
+        )))
+    })?;
+    let base_uuid = sold_under.base_uuid.unwrap_or(sold_under.id);
+    let contract_type = ContractType::current(pool, base_uuid).await?.ok_or_else(|| {
+        eprintln!("No current version of contract type {}.", base_uuid);
+        Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Contract Type could not be found.",
+        )))
+    })?;
+
+    if !contract_type.active {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Contract Type is no longer offered.",
+        ))));
+    }
+
+    let duration_days = dto
+        .duration_days
+        .unwrap_or_else(|| (contract.end_date - contract.start_date).num_days());
+
+    if duration_days < contract_type.min_duration_days as i64
+        || duration_days > contract_type.max_duration_days as i64
+    {
+        return Err(Error::Decode(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Duration is outside of the contract type limits.",
+        ))));
+    }
+
+    let premium_per_day = formula::premium_per_day(&contract_type.formula_per_day, contract.item.price)
+        .map_err(|err| {
+            eprintln!("Failed to price contract {}: {}", contract.id, err);
+            Error::Decode(Box::new(err))
+        })?;
+
+    let renewal_uuid = ids::assign_id(dto.renewal_uuid, ClientIds::Refused)?;
+
+    // The successor starts where the original ends, or today if it already expired
+    let start_date = contract.end_date.max(Utc::now().naive_utc());
+    let end_date = start_date + Duration::days(duration_days);
+
+    // The successor and its event are stored together or not at all
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO contracts (id, username, contract_type_uuid, item, start_date, end_date, void, renewed_from)
         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         "#,
-        contract_id,
-        dto.username,
-        dto.contract_type_uuid,
-        serde_json::to_value(dto.item).unwrap(), // Serialize the item to JSON
-        dto.start_date,
-        dto.end_date,
+        renewal_uuid,
+        contract.username,
+        contract_type.id,
+        serde_json::to_value(&contract.item).unwrap(), // Serialize the item to JSON
+        start_date,
+        end_date,
         false, // Contract is not void
-        serde_json::to_value(Vec::<Uuid>::new()).unwrap() // Empty claim index
+        contract.id
     )
-    .execute(pool)
+    .execute(&mut tx)
     .await?;
 
-    // Respond with the created user details if a new user was created
-    if user.is_none() {
-        let response = serde_json::json!({
-            "username": dto.username,
-            "password": dto.password // Return the original password
-        });
-        return Ok(response.to_string());
-    }
+    record_contract_event(
+        &mut tx,
+        contract.id,
+        "renewed",
+        serde_json::json!({ "renewal_uuid": renewal_uuid }),
+    )
+    .await?;
+
+    tx.commit().await?;
 
-    Ok("Contract created successfully.".to_string())
+    let result = RenewContractResult {
+        uuid: renewal_uuid.to_string(),
+        renewed_from: contract.id.to_string(),
+        start_date,
+        end_date,
+        premium_per_day,
+        premium: premium_per_day * duration_days as f32,
+    };
+
+    serde_json::to_string(&result).map_err(|err| {
+        eprintln!("Failed to serialize renewal result: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
 }
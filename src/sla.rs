@@ -0,0 +1,231 @@
+use sqlx::{Error, Executor, Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde_json::json;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::config::env_or;
+use crate::data::PeerRole;
+
+// Service level agreements on how long a claim may stay in a status before the
+// responsible peer is considered late. Breaches are marked by the scheduler and
+// escalated by raising the claim's priority in the adjuster queue.
+
+const DEFAULT_ESCALATION_PRIORITY_BUMP: i32 = 10;
+
+#[derive(Debug, Clone)]
+pub struct SlaPolicy {
+    pub peer: PeerRole,
+    pub status: &'static str,
+    pub is_theft: Option<bool>, // None applies to both theft and damage claims
+    pub unrepaired_only: bool,
+    pub days: i64,
+}
+
+pub fn policies() -> Vec<SlaPolicy> {
+    vec![
+        // Police must confirm or reject theft reports
+        SlaPolicy {
+            peer: PeerRole::Police,
+            status: "New",
+            is_theft: Some(true),
+            unrepaired_only: false,
+            days: env_or("SLA_THEFT_POLICE_DAYS", 7),
+        },
+        // Insurer must decide on new damage claims
+        SlaPolicy {
+            peer: PeerRole::Insurer,
+            status: "New",
+            is_theft: Some(false),
+            unrepaired_only: false,
+            days: env_or("SLA_NEW_CLAIM_DAYS", 5),
+        },
+        // Insurer must settle confirmed thefts
+        SlaPolicy {
+            peer: PeerRole::Insurer,
+            status: "TheftConfirmed",
+            is_theft: Some(true),
+            unrepaired_only: false,
+            days: env_or("SLA_THEFT_CONFIRMED_DAYS", 5),
+        },
+        // Insurer must finish fraud reviews
+        SlaPolicy {
+            peer: PeerRole::Insurer,
+            status: "ManualReview",
+            is_theft: None,
+            unrepaired_only: false,
+            days: env_or("SLA_MANUAL_REVIEW_DAYS", 3),
+        },
+        // Insurer must decide on appeals
+        SlaPolicy {
+            peer: PeerRole::Insurer,
+            status: "UnderAppeal",
+            is_theft: None,
+            unrepaired_only: false,
+            days: env_or("SLA_APPEAL_DAYS", 14),
+        },
+        // Repair shop must complete repair orders
+        SlaPolicy {
+            peer: PeerRole::RepairShop,
+            status: "Repair",
+            is_theft: Some(false),
+            unrepaired_only: true,
+            days: env_or("SLA_REPAIR_DAYS", 14),
+        },
+    ]
+}
+
+//Index status and status_changed_at so SLA scans do not walk every claim:
+//CREATE INDEX idx_claims_status_changed_at ON claims (status, status_changed_at);
+pub async fn check_breaches(pool: &Pool<Postgres>) -> Result<u64, Error> {
+    let now = Utc::now().naive_utc();
+    let bump = env_or("SLA_ESCALATION_PRIORITY_BUMP", DEFAULT_ESCALATION_PRIORITY_BUMP);
+    let mut breached = 0;
+
+    // Breaches are marked together with their escalation events
+    let mut tx = pool.begin().await?;
+
+    for policy in policies() {
+        // Mark the claims that just went past their deadline and move them up the queue
+        let rows = sqlx::query!(
+            r#"
+            UPDATE claims
+            SET sla_breached_at = $1, priority = priority + $2
+            WHERE status = $3
+                AND ($4::BOOLEAN IS NULL OR is_theft = $4)
+                AND (NOT $5 OR repaired = FALSE)
+                AND status_changed_at < $6
+                AND sla_breached_at IS NULL
+            RETURNING id, assignee
+            "#,
+            now,
+            bump,
+            policy.status,
+            policy.is_theft,
+            policy.unrepaired_only,
+            now - Duration::days(policy.days)
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        for row in &rows {
+            record_escalation(&mut tx, row.id, &policy, row.assignee.as_deref()).await?;
+        }
+        breached += rows.len() as u64;
+    }
+    tx.commit().await?;
+
+    Ok(breached)
+}
+
+async fn record_escalation<'e, E>(
+    executor: E,
+    claim_uuid: Uuid,
+    policy: &SlaPolicy,
+    assignee: Option<&str>,
+) -> Result<(), Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query!(
+        r#"
+        INSERT INTO claim_events (id, claim_uuid, kind, created_at, payload)
+        VALUES ($1, $2, 'sla_breach', $3, $4)
+        "#,
+        Uuid::new_v4(),
+        claim_uuid,
+        Utc::now().naive_utc(),
+        json!({
+            "peer": policy.peer.to_str(),
+            "status": policy.status,
+            "sla_days": policy.days,
+            "assignee": assignee,
+        })
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct OverdueClaim {
+    pub uuid: String,
+    pub contract_uuid: String,
+    pub status: String,
+    pub is_theft: bool,
+    pub assignee: Option<String>,
+    pub status_changed_at: NaiveDateTime,
+    pub due_at: NaiveDateTime,
+    pub overdue_days: i64,
+    pub escalated: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SlaReportDto {
+    pub peer: Option<PeerRole>, // Report on a single peer
+}
+
+pub async fn sla_report(
+    pool: &Pool<Postgres>,
+    args: Option<String>, // Optional JSON input to report on a single peer
+) -> Result<String, Error> {
+    let input: SlaReportDto = match args {
+        Some(arg) => serde_json::from_str(&arg).map_err(|err| {
+            eprintln!("Failed to parse input JSON: {:?}", err);
+            Error::Decode(Box::new(err))
+        })?,
+        None => SlaReportDto::default(),
+    };
+    let filter_peer = input.peer;
+
+    let now = Utc::now().naive_utc();
+    let mut report: BTreeMap<String, Vec<OverdueClaim>> = BTreeMap::new();
+
+    for policy in policies() {
+        if filter_peer.map_or(false, |peer| peer != policy.peer) {
+            continue;
+        }
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, contract_uuid, status, is_theft, assignee, status_changed_at, sla_breached_at
+            FROM claims
+            WHERE status = $1
+                AND ($2::BOOLEAN IS NULL OR is_theft = $2)
+                AND (NOT $3 OR repaired = FALSE)
+                AND status_changed_at < $4
+            ORDER BY status_changed_at
+            "#,
+            policy.status,
+            policy.is_theft,
+            policy.unrepaired_only,
+            now - Duration::days(policy.days)
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let overdue = report.entry(policy.peer.to_str().to_string()).or_default();
+        for row in rows {
+            let due_at = row.status_changed_at + Duration::days(policy.days);
+            overdue.push(OverdueClaim {
+                uuid: row.id.to_string(),
+                contract_uuid: row.contract_uuid.to_string(),
+                status: row.status,
+                is_theft: row.is_theft,
+                assignee: row.assignee,
+                status_changed_at: row.status_changed_at,
+                due_at,
+                overdue_days: (now - due_at).num_days(),
+                escalated: row.sla_breached_at.is_some(),
+            });
+        }
+    }
+
+    serde_json::to_string(&report).map_err(|err| {
+        eprintln!("Failed to serialize SLA report: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
@@ -0,0 +1,112 @@
+use sqlx::pool::PoolConnection;
+use sqlx::{Database, Error, Pool, Transaction};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+
+// Connections the queries of a SQL storage run on: any connection of the pool,
+// or the one transaction a storage was scoped to with `begin`. Clones of a
+// scoped storage share the transaction; it ends with `commit` or `rollback`,
+// and is rolled back when the last clone is dropped without either.
+pub struct Connections<DB: Database> {
+    pool: Pool<DB>,
+    transaction: Option<Arc<Mutex<Option<Transaction<'static, DB>>>>>,
+}
+
+impl<DB: Database> Clone for Connections<DB> {
+    fn clone(&self) -> Self {
+        Connections {
+            pool: self.pool.clone(),
+            transaction: self.transaction.clone(),
+        }
+    }
+}
+
+// A connection of the pool, or the open transaction held for one query
+pub enum ConnectionGuard<'a, DB: Database> {
+    Pooled(PoolConnection<DB>),
+    Transaction(MutexGuard<'a, Option<Transaction<'static, DB>>>),
+}
+
+impl<DB: Database> Deref for ConnectionGuard<'_, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &DB::Connection {
+        match self {
+            ConnectionGuard::Pooled(conn) => conn,
+            ConnectionGuard::Transaction(tx) => tx.as_ref().unwrap(), // Checked to be open when acquired
+        }
+    }
+}
+
+impl<DB: Database> DerefMut for ConnectionGuard<'_, DB> {
+    fn deref_mut(&mut self) -> &mut DB::Connection {
+        match self {
+            ConnectionGuard::Pooled(conn) => conn,
+            ConnectionGuard::Transaction(tx) => tx.as_mut().unwrap(),
+        }
+    }
+}
+
+fn closed() -> Error {
+    Error::Decode(Box::new(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "Transaction is already closed.",
+    )))
+}
+
+impl<DB: Database> Connections<DB> {
+    pub fn new(pool: Pool<DB>) -> Self {
+        Connections { pool, transaction: None }
+    }
+
+    pub fn pool(&self) -> &Pool<DB> {
+        &self.pool
+    }
+
+    pub async fn acquire(&self) -> Result<ConnectionGuard<'_, DB>, Error> {
+        match self.transaction {
+            None => Ok(ConnectionGuard::Pooled(self.pool.acquire().await?)),
+            Some(ref transaction) => {
+                let tx = transaction.lock().await;
+                if tx.is_none() {
+                    return Err(closed());
+                }
+                Ok(ConnectionGuard::Transaction(tx))
+            }
+        }
+    }
+
+    // Connections running on a new transaction of the pool
+    pub async fn begin(&self) -> Result<Self, Error> {
+        let tx = self.pool.begin().await?;
+        Ok(Connections {
+            pool: self.pool.clone(),
+            transaction: Some(Arc::new(Mutex::new(Some(tx)))),
+        })
+    }
+
+    pub async fn commit(&self) -> Result<(), Error> {
+        match self.take_transaction().await? {
+            Some(tx) => tx.commit().await,
+            None => Err(closed()),
+        }
+    }
+
+    pub async fn rollback(&self) -> Result<(), Error> {
+        match self.take_transaction().await? {
+            Some(tx) => tx.rollback().await,
+            None => Err(closed()),
+        }
+    }
+
+    async fn take_transaction(&self) -> Result<Option<Transaction<'static, DB>>, Error> {
+        match self.transaction {
+            Some(ref transaction) => Ok(transaction.lock().await.take()),
+            None => Err(Error::Decode(Box::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Storage is not scoped to a transaction.",
+            )))),
+        }
+    }
+}
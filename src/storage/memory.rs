@@ -0,0 +1,495 @@
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime};
+use sqlx::Error;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use super::{
+    duplicate, ClaimRepository, ContractRepository, ContractTypeRepository, IdempotencyRepository, RepairOrderRepository,
+    SearchRepository, SettlementRepository, UserRepository,
+};
+use crate::data::{Claim, Contract, ContractType, RepairOrder, User};
+use crate::fraud::ClaimHistory;
+use crate::idempotency::IdempotencyRecord;
+use crate::search::{self, SearchHit, SearchKind, SearchScope};
+use crate::settlements::ClaimSettlement;
+
+// Storage kept in process memory, used to run the workflows in tests.
+// Derived fields (claim and contract indexes) are computed on read like the
+// Postgres backend does.
+
+#[derive(Default)]
+struct MemoryState {
+    contract_types: HashMap<Uuid, ContractType>,
+    users: HashMap<String, User>,
+    contracts: HashMap<Uuid, Contract>,
+    claims: HashMap<Uuid, Claim>,
+    repair_orders: Vec<RepairOrder>,
+    settlements: Vec<ClaimSettlement>,
+    idempotency_keys: HashMap<String, IdempotencyRecord>,
+}
+
+impl MemoryState {
+    fn with_claim_index(&self, contract: &Contract) -> Contract {
+        let mut claims: Vec<&Claim> = self
+            .claims
+            .values()
+            .filter(|claim| claim.contract_uuid == contract.id)
+            .collect();
+        claims.sort_by_key(|claim| claim.date);
+
+        let mut contract = contract.clone();
+        contract.claim_index = Some(claims.iter().map(|claim| claim.id).collect());
+        contract
+    }
+
+    fn with_contract_index(&self, user: &User) -> User {
+        let mut contracts: Vec<&Contract> = self
+            .contracts
+            .values()
+            .filter(|contract| contract.username == user.username)
+            .collect();
+        contracts.sort_by_key(|contract| contract.start_date);
+
+        let mut user = user.clone();
+        user.contract_index = contracts.iter().map(|contract| contract.id.to_string()).collect();
+        user
+    }
+
+    // Whether a search in scope may find the contract and its claims
+    fn contract_in_scope(&self, contract: &Contract, scope: &SearchScope) -> bool {
+        match scope {
+            SearchScope::Everything => true,
+            SearchScope::TheftClaims => self
+                .claims
+                .values()
+                .any(|claim| claim.contract_uuid == contract.id && claim.is_theft),
+            SearchScope::RepairedClaims => self.repair_orders.iter().any(|order| order.contract_uuid == contract.id),
+            SearchScope::Customer(username) => contract.username == *username,
+        }
+    }
+
+    fn claim_in_scope(&self, claim: &Claim, scope: &SearchScope) -> bool {
+        match scope {
+            SearchScope::Everything => true,
+            SearchScope::TheftClaims => claim.is_theft,
+            SearchScope::RepairedClaims => self.repair_orders.iter().any(|order| order.claim_uuid == claim.id),
+            SearchScope::Customer(username) => self
+                .contracts
+                .get(&claim.contract_uuid)
+                .map_or(false, |contract| contract.username == *username),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        // A panicking test must not poison the storage for the assertions that follow
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl ContractTypeRepository for MemoryStorage {
+    async fn find_contract_type(&self, id: Uuid) -> Result<Option<ContractType>, Error> {
+        Ok(self.state().contract_types.get(&id).cloned())
+    }
+
+    async fn insert_contract_type(&self, contract_type: &ContractType) -> Result<(), Error> {
+        let mut state = self.state();
+        if state.contract_types.contains_key(&contract_type.id) {
+            return Err(duplicate("Contract Type"));
+        }
+
+        let mut contract_type = contract_type.clone();
+        contract_type.base_uuid = contract_type.base_uuid.or(Some(contract_type.id));
+        state.contract_types.insert(contract_type.id, contract_type);
+        Ok(())
+    }
+
+    async fn set_contract_type_active(&self, id: Uuid, active: bool) -> Result<bool, Error> {
+        match self.state().contract_types.get_mut(&id) {
+            Some(contract_type) => {
+                contract_type.active = active;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryStorage {
+    async fn find_user(&self, username: &str) -> Result<Option<User>, Error> {
+        let state = self.state();
+        Ok(state.users.get(username).map(|user| state.with_contract_index(user)))
+    }
+
+    async fn find_users(&self, usernames: &[String]) -> Result<Vec<User>, Error> {
+        let state = self.state();
+        Ok(usernames
+            .iter()
+            .filter_map(|username| state.users.get(username))
+            .map(|user| state.with_contract_index(user))
+            .collect())
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), Error> {
+        let mut state = self.state();
+        if state.users.contains_key(&user.username) {
+            return Err(duplicate("User"));
+        }
+
+        state.users.insert(user.username.clone(), user.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContractRepository for MemoryStorage {
+    async fn find_contract(&self, id: Uuid) -> Result<Option<Contract>, Error> {
+        let state = self.state();
+        Ok(state.contracts.get(&id).map(|contract| state.with_claim_index(contract)))
+    }
+
+    async fn find_contracts(&self, ids: &[Uuid]) -> Result<Vec<Contract>, Error> {
+        let state = self.state();
+        Ok(ids
+            .iter()
+            .filter_map(|id| state.contracts.get(id))
+            .map(|contract| state.with_claim_index(contract))
+            .collect())
+    }
+
+    async fn insert_contract(&self, contract: &Contract) -> Result<(), Error> {
+        let mut state = self.state();
+        if state.contracts.contains_key(&contract.id) {
+            return Err(duplicate("Contract"));
+        }
+
+        state.contracts.insert(contract.id, contract.clone());
+        Ok(())
+    }
+
+    async fn contracts_of_user(&self, username: &str) -> Result<Vec<Contract>, Error> {
+        let state = self.state();
+        let mut contracts: Vec<Contract> = state
+            .contracts
+            .values()
+            .filter(|contract| contract.username == username)
+            .map(|contract| state.with_claim_index(contract))
+            .collect();
+        contracts.sort_by_key(|contract| contract.start_date);
+        Ok(contracts)
+    }
+
+    async fn void_contract(&self, id: Uuid) -> Result<(), Error> {
+        if let Some(contract) = self.state().contracts.get_mut(&id) {
+            contract.void = true;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ClaimRepository for MemoryStorage {
+    async fn find_claim(&self, id: Uuid) -> Result<Option<Claim>, Error> {
+        Ok(self.state().claims.get(&id).cloned())
+    }
+
+    async fn find_claims(&self, ids: &[Uuid]) -> Result<Vec<Claim>, Error> {
+        let state = self.state();
+        Ok(ids.iter().filter_map(|id| state.claims.get(id)).cloned().collect())
+    }
+
+    async fn insert_claim(&self, claim: &Claim) -> Result<(), Error> {
+        let mut state = self.state();
+        if state.claims.contains_key(&claim.id) {
+            return Err(duplicate("Claim"));
+        }
+
+        state.claims.insert(claim.id, claim.clone());
+        Ok(())
+    }
+
+    async fn update_claim(&self, claim: &Claim) -> Result<(), Error> {
+        if let Some(stored) = self.state().claims.get_mut(&claim.id) {
+            stored.status = claim.status.clone();
+            stored.reimbursable = claim.reimbursable;
+            stored.repaired = claim.repaired;
+            stored.file_reference = claim.file_reference.clone();
+            stored.status_changed_at = claim.status_changed_at;
+            stored.sla_breached_at = claim.sla_breached_at;
+            stored.rejected_from = claim.rejected_from.clone();
+        }
+        Ok(())
+    }
+
+    async fn claims_of_contracts(&self, contract_uuids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Claim>>, Error> {
+        let state = self.state();
+        let mut claims: Vec<&Claim> = state
+            .claims
+            .values()
+            .filter(|claim| contract_uuids.contains(&claim.contract_uuid))
+            .collect();
+        claims.sort_by_key(|claim| claim.date);
+
+        let mut grouped: HashMap<Uuid, Vec<Claim>> = HashMap::new();
+        for claim in claims {
+            grouped.entry(claim.contract_uuid).or_default().push(claim.clone());
+        }
+        Ok(grouped)
+    }
+
+    async fn theft_claims_to_confirm(&self) -> Result<Vec<Claim>, Error> {
+        let mut claims: Vec<Claim> = self
+            .state()
+            .claims
+            .values()
+            .filter(|claim| claim.is_theft && claim.status == "New")
+            .cloned()
+            .collect();
+        claims.sort_by_key(|claim| claim.date);
+        Ok(claims)
+    }
+
+    async fn claims_in_year(&self, claim: &Claim) -> Result<i64, Error> {
+        let since = claim.date - Duration::days(365);
+        let count = self
+            .state()
+            .claims
+            .values()
+            .filter(|other| {
+                other.contract_uuid == claim.contract_uuid
+                    && other.id != claim.id
+                    && other.status != "Rejected"
+                    && other.date > since
+                    && other.date <= claim.date
+            })
+            .count();
+        Ok(count as i64)
+    }
+
+    async fn claim_history(&self, claim: &Claim, contract: &Contract) -> Result<ClaimHistory, Error> {
+        let state = self.state();
+        let claims_on_contract = state
+            .claims
+            .values()
+            .filter(|other| other.contract_uuid == contract.id && other.id != claim.id)
+            .count();
+
+        let serial_no = contract.item.serial_no.trim();
+        let mut other_users: Vec<&str> = state
+            .contracts
+            .values()
+            .filter(|other| !serial_no.is_empty() && other.item.serial_no == contract.item.serial_no)
+            .filter(|other| other.username != contract.username)
+            .map(|other| other.username.as_str())
+            .collect();
+        other_users.sort_unstable();
+        other_users.dedup();
+
+        let theft_claims_by_user = state
+            .claims
+            .values()
+            .filter(|other| other.is_theft && other.id != claim.id)
+            .filter(|other| {
+                state
+                    .contracts
+                    .get(&other.contract_uuid)
+                    .map_or(false, |other| other.username == contract.username)
+            })
+            .count();
+
+        Ok(ClaimHistory {
+            claims_on_contract: claims_on_contract as i64,
+            other_users_with_serial_no: other_users.len() as i64,
+            theft_claims_by_user: theft_claims_by_user as i64,
+        })
+    }
+}
+
+#[async_trait]
+impl RepairOrderRepository for MemoryStorage {
+    async fn find_repair_order(&self, id: Uuid) -> Result<Option<RepairOrder>, Error> {
+        Ok(self.state().repair_orders.iter().find(|order| order.id == id).cloned())
+    }
+
+    async fn insert_repair_order(&self, repair_order: &RepairOrder) -> Result<(), Error> {
+        let mut state = self.state();
+        if state.repair_orders.iter().any(|order| order.id == repair_order.id) {
+            return Err(duplicate("Repair order"));
+        }
+
+        state.repair_orders.push(repair_order.clone());
+        Ok(())
+    }
+
+    async fn open_repair_orders(&self) -> Result<Vec<RepairOrder>, Error> {
+        Ok(self
+            .state()
+            .repair_orders
+            .iter()
+            .filter(|order| !order.ready)
+            .cloned()
+            .collect())
+    }
+
+    async fn complete_repair_order(&self, repair_order: &RepairOrder) -> Result<(), Error> {
+        let mut state = self.state();
+
+        if let Some(order) = state.repair_orders.iter_mut().find(|order| order.id == repair_order.id) {
+            order.ready = true;
+        }
+        if let Some(claim) = state.claims.get_mut(&repair_order.claim_uuid) {
+            if claim.contract_uuid == repair_order.contract_uuid {
+                claim.repaired = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SettlementRepository for MemoryStorage {
+    async fn settlements_of_claim(&self, claim_uuid: Uuid) -> Result<Vec<ClaimSettlement>, Error> {
+        Ok(self
+            .state()
+            .settlements
+            .iter()
+            .filter(|settlement| settlement.claim_uuid == claim_uuid)
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_settlement(&self, settlement: &ClaimSettlement) -> Result<(), Error> {
+        let mut state = self.state();
+        if state.settlements.iter().any(|line| line.id == settlement.id) {
+            return Err(duplicate("Settlement"));
+        }
+
+        state.settlements.push(settlement.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SearchRepository for MemoryStorage {
+    async fn search(&self, terms: &str, kinds: &[SearchKind], scope: &SearchScope, limit: i64) -> Result<Vec<SearchHit>, Error> {
+        let state = self.state();
+        let mut hits = Vec::new();
+
+        if kinds.contains(&SearchKind::Claim) {
+            let claims = state
+                .claims
+                .values()
+                .filter(|claim| state.claim_in_scope(claim, scope))
+                .map(|claim| SearchHit {
+                    kind: SearchKind::Claim,
+                    uuid: claim.id.to_string(),
+                    title: format!("Claim of {}", claim.date.format("%Y-%m-%d")),
+                    snippet: claim.description.clone(),
+                    rank: search::term_rank(terms, &claim.description),
+                })
+                .filter(|hit| hit.rank > 0.0)
+                .collect();
+            hits.extend(search::best_hits(claims, limit));
+        }
+
+        if kinds.contains(&SearchKind::Contract) {
+            let contracts = state
+                .contracts
+                .values()
+                .filter(|contract| state.contract_in_scope(contract, scope))
+                .map(|contract| {
+                    let item = &contract.item;
+                    SearchHit {
+                        kind: SearchKind::Contract,
+                        uuid: contract.id.to_string(),
+                        title: format!("{} {}", item.brand, item.model),
+                        snippet: item.serial_no.clone(),
+                        rank: search::term_rank(terms, &format!("{} {} {}", item.brand, item.model, item.serial_no)),
+                    }
+                })
+                .filter(|hit| hit.rank > 0.0)
+                .collect();
+            hits.extend(search::best_hits(contracts, limit));
+        }
+
+        if kinds.contains(&SearchKind::Customer) {
+            let customers = state
+                .users
+                .values()
+                .filter(|user| {
+                    state
+                        .contracts
+                        .values()
+                        .filter(|contract| contract.username == user.username)
+                        .any(|contract| state.contract_in_scope(contract, scope))
+                        || *scope == SearchScope::Everything
+                        || scope.username() == Some(user.username.as_str())
+                })
+                .map(|user| {
+                    let name = format!("{} {}", user.first_name, user.last_name);
+                    SearchHit {
+                        kind: SearchKind::Customer,
+                        uuid: user.username.clone(),
+                        rank: search::term_rank(terms, &name),
+                        title: name,
+                        snippet: user.username.clone(),
+                    }
+                })
+                .filter(|hit| hit.rank > 0.0)
+                .collect();
+            hits.extend(search::best_hits(customers, limit));
+        }
+
+        Ok(hits)
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for MemoryStorage {
+    async fn reserve_idempotency_key(&self, record: &IdempotencyRecord) -> Result<bool, Error> {
+        let mut state = self.state();
+        if state.idempotency_keys.contains_key(&record.key) {
+            return Ok(false);
+        }
+
+        state.idempotency_keys.insert(record.key.clone(), record.clone());
+        Ok(true)
+    }
+
+    async fn find_idempotency_key(&self, key: &str) -> Result<Option<IdempotencyRecord>, Error> {
+        Ok(self.state().idempotency_keys.get(key).cloned())
+    }
+
+    async fn complete_idempotency_key(&self, key: &str, response: &str) -> Result<(), Error> {
+        if let Some(record) = self.state().idempotency_keys.get_mut(key) {
+            record.response = Some(response.to_string());
+        }
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), Error> {
+        self.state().idempotency_keys.remove(key);
+        Ok(())
+    }
+
+    async fn purge_idempotency_keys(&self, before: NaiveDateTime, abandoned_before: NaiveDateTime) -> Result<(), Error> {
+        self.state().idempotency_keys.retain(|_, record| {
+            record.created_at >= before && (record.response.is_some() || record.created_at >= abandoned_before)
+        });
+        Ok(())
+    }
+}
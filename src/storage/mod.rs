@@ -0,0 +1,216 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::{Error, PgPool};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::data::{Claim, Contract, ContractType, RepairOrder, User};
+use crate::fraud::ClaimHistory;
+use crate::idempotency::IdempotencyRecord;
+use crate::search::{SearchHit, SearchKind, SearchScope};
+use crate::settlements::ClaimSettlement;
+
+mod connections;
+#[cfg(test)]
+mod memory;
+mod postgres;
+mod sqlite;
+
+#[cfg(test)]
+mod tests;
+
+#[cfg(test)]
+pub use memory::MemoryStorage;
+pub use postgres::PgStorage;
+pub use sqlite::SqliteStorage;
+
+// Versioned schema migrations embedded in the binary, one set per backend
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+// Database the server runs against, selected by the DATABASE_URL scheme
+#[derive(Clone)]
+pub enum Backend {
+    Postgres(PgStorage),
+    Sqlite(SqliteStorage),
+}
+
+impl Backend {
+    pub async fn connect(database_url: &str) -> Result<Backend, Error> {
+        if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+            let pool = PgPool::connect(database_url).await?;
+            Ok(Backend::Postgres(PgStorage::new(&pool)))
+        } else if database_url.starts_with("sqlite:") {
+            Ok(Backend::Sqlite(SqliteStorage::connect(database_url).await?))
+        } else {
+            Err(Error::Configuration(
+                "DATABASE_URL must start with postgres://, postgresql:// or sqlite:".into(),
+            ))
+        }
+    }
+
+    pub async fn migrate(&self) -> Result<(), MigrateError> {
+        match self {
+            Backend::Postgres(storage) => POSTGRES_MIGRATOR.run(storage.pool()).await,
+            Backend::Sqlite(storage) => SQLITE_MIGRATOR.run(storage.pool()).await,
+        }
+    }
+
+    // Backend whose storage runs on a new transaction, see `commit` and `rollback`
+    pub async fn begin(&self) -> Result<Backend, Error> {
+        match self {
+            Backend::Postgres(storage) => Ok(Backend::Postgres(storage.begin().await?)),
+            Backend::Sqlite(storage) => Ok(Backend::Sqlite(storage.begin().await?)),
+        }
+    }
+
+    pub async fn commit(&self) -> Result<(), Error> {
+        match self {
+            Backend::Postgres(storage) => storage.commit().await,
+            Backend::Sqlite(storage) => storage.commit().await,
+        }
+    }
+
+    pub async fn rollback(&self) -> Result<(), Error> {
+        match self {
+            Backend::Postgres(storage) => storage.rollback().await,
+            Backend::Sqlite(storage) => storage.rollback().await,
+        }
+    }
+
+    pub fn storage(&self) -> &dyn Storage {
+        match self {
+            Backend::Postgres(storage) => storage,
+            Backend::Sqlite(storage) => storage,
+        }
+    }
+}
+
+// Repositories for the records shared by the insurance, shop, repair shop and
+// police workflows. Handlers go through these instead of issuing queries so the
+// workflow can run against any backend.
+
+#[async_trait]
+pub trait ContractTypeRepository {
+    async fn find_contract_type(&self, id: Uuid) -> Result<Option<ContractType>, Error>;
+    async fn insert_contract_type(&self, contract_type: &ContractType) -> Result<(), Error>;
+    // Returns false when the contract type does not exist
+    async fn set_contract_type_active(&self, id: Uuid, active: bool) -> Result<bool, Error>;
+}
+
+#[async_trait]
+pub trait UserRepository {
+    async fn find_user(&self, username: &str) -> Result<Option<User>, Error>;
+    // Several users at once, in no particular order
+    async fn find_users(&self, usernames: &[String]) -> Result<Vec<User>, Error>;
+    async fn insert_user(&self, user: &User) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait ContractRepository {
+    async fn find_contract(&self, id: Uuid) -> Result<Option<Contract>, Error>;
+    // Several contracts at once, in no particular order
+    async fn find_contracts(&self, ids: &[Uuid]) -> Result<Vec<Contract>, Error>;
+    async fn insert_contract(&self, contract: &Contract) -> Result<(), Error>;
+    async fn contracts_of_user(&self, username: &str) -> Result<Vec<Contract>, Error>;
+    async fn void_contract(&self, id: Uuid) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait ClaimRepository {
+    async fn find_claim(&self, id: Uuid) -> Result<Option<Claim>, Error>;
+    // Several claims at once, in no particular order
+    async fn find_claims(&self, ids: &[Uuid]) -> Result<Vec<Claim>, Error>;
+    async fn insert_claim(&self, claim: &Claim) -> Result<(), Error>;
+    // Persists the processing state: status, amounts, repair and police outcome, SLA timestamps
+    async fn update_claim(&self, claim: &Claim) -> Result<(), Error>;
+    async fn claims_of_contracts(&self, contract_uuids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Claim>>, Error>;
+    async fn theft_claims_to_confirm(&self) -> Result<Vec<Claim>, Error>;
+    // Non-rejected claims on the claim's contract in the year before its date, excluding itself
+    async fn claims_in_year(&self, claim: &Claim) -> Result<i64, Error>;
+    // Earlier claims and items of the claimant, for fraud scoring; excludes the claim itself
+    async fn claim_history(&self, claim: &Claim, contract: &Contract) -> Result<ClaimHistory, Error>;
+}
+
+#[async_trait]
+pub trait RepairOrderRepository {
+    async fn find_repair_order(&self, id: Uuid) -> Result<Option<RepairOrder>, Error>;
+    async fn insert_repair_order(&self, repair_order: &RepairOrder) -> Result<(), Error>;
+    async fn open_repair_orders(&self) -> Result<Vec<RepairOrder>, Error>;
+    // Marks the order ready and the claim it belongs to repaired
+    async fn complete_repair_order(&self, repair_order: &RepairOrder) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait SettlementRepository {
+    // Settlement lines of a claim, oldest first
+    async fn settlements_of_claim(&self, claim_uuid: Uuid) -> Result<Vec<ClaimSettlement>, Error>;
+    async fn insert_settlement(&self, settlement: &ClaimSettlement) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait SearchRepository {
+    // Records of the given kinds in scope matching the search terms, at most `limit` of each kind
+    async fn search(&self, terms: &str, kinds: &[SearchKind], scope: &SearchScope, limit: i64) -> Result<Vec<SearchHit>, Error>;
+}
+
+#[async_trait]
+pub trait IdempotencyRepository {
+    // Records a call under its key; returns false when the key is already taken
+    async fn reserve_idempotency_key(&self, record: &IdempotencyRecord) -> Result<bool, Error>;
+    async fn find_idempotency_key(&self, key: &str) -> Result<Option<IdempotencyRecord>, Error>;
+    // Stores the result of the call made with the key
+    async fn complete_idempotency_key(&self, key: &str, response: &str) -> Result<(), Error>;
+    // Frees the key of a call that failed
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), Error>;
+    // Forgets the keys recorded before `before`, and the keys of calls still
+    // running that were reserved before `abandoned_before`
+    async fn purge_idempotency_keys(&self, before: NaiveDateTime, abandoned_before: NaiveDateTime) -> Result<(), Error>;
+}
+
+pub trait Storage:
+    ContractTypeRepository
+    + UserRepository
+    + ContractRepository
+    + ClaimRepository
+    + RepairOrderRepository
+    + SettlementRepository
+    + SearchRepository
+    + IdempotencyRepository
+    + Send
+    + Sync
+{
+}
+
+impl<T> Storage for T where
+    T: ContractTypeRepository
+        + UserRepository
+        + ContractRepository
+        + ClaimRepository
+        + RepairOrderRepository
+        + SettlementRepository
+        + SearchRepository
+        + IdempotencyRepository
+        + Send
+        + Sync
+{
+}
+
+// Error returned when inserting a record whose key is already taken
+pub(crate) fn duplicate(what: &str) -> Error {
+    Error::Decode(Box::new(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!("{} already exists.", what),
+    )))
+}
+
+// Maps the unique violation of an insert, on Postgres or SQLite, to `duplicate`
+pub(crate) fn or_duplicate(err: Error, what: &str) -> Error {
+    match err {
+        Error::Database(ref db) if matches!(db.code().as_deref(), Some("23505") | Some("1555") | Some("2067")) => {
+            duplicate(what)
+        }
+        err => err,
+    }
+}
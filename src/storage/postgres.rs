@@ -0,0 +1,633 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::{Connection, Error, Pool, Postgres};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use super::connections::{ConnectionGuard, Connections};
+use super::{
+    or_duplicate, ClaimRepository, ContractRepository, ContractTypeRepository, IdempotencyRepository, RepairOrderRepository,
+    SearchRepository, SettlementRepository, UserRepository,
+};
+use crate::conditions;
+use crate::data::{Claim, Contract, ContractType, RepairOrder, User};
+use crate::fraud::{self, ClaimHistory};
+use crate::idempotency::IdempotencyRecord;
+use crate::search::{SearchHit, SearchKind, SearchScope};
+use crate::settlements::ClaimSettlement;
+
+#[derive(Clone)]
+pub struct PgStorage {
+    connections: Connections<Postgres>,
+}
+
+impl PgStorage {
+    pub fn new(pool: &Pool<Postgres>) -> Self {
+        PgStorage {
+            connections: Connections::new(pool.clone()),
+        }
+    }
+
+    pub fn pool(&self) -> &Pool<Postgres> {
+        self.connections.pool()
+    }
+
+    // Storage whose changes become visible once committed
+    pub async fn begin(&self) -> Result<Self, Error> {
+        Ok(PgStorage {
+            connections: self.connections.begin().await?,
+        })
+    }
+
+    pub async fn commit(&self) -> Result<(), Error> {
+        self.connections.commit().await
+    }
+
+    pub async fn rollback(&self) -> Result<(), Error> {
+        self.connections.rollback().await
+    }
+
+    // Connection for queries outside the repositories, on the transaction if any
+    pub async fn acquire(&self) -> Result<ConnectionGuard<'_, Postgres>, Error> {
+        self.connections.acquire().await
+    }
+}
+
+#[async_trait]
+impl ContractTypeRepository for PgStorage {
+    async fn find_contract_type(&self, id: Uuid) -> Result<Option<ContractType>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        ContractType::find(&mut *conn, id).await
+    }
+
+    async fn insert_contract_type(&self, ct: &ContractType) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO contract_types (id, shop_type, formula_per_day, max_sum_insured, theft_insured,
+                description, conditions, active, min_duration_days, max_duration_days, depreciation,
+                version, base_uuid, policy_conditions)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            "#,
+            ct.id,
+            ct.shop_type,
+            ct.formula_per_day,
+            ct.max_sum_insured,
+            ct.theft_insured,
+            ct.description,
+            ct.conditions,
+            ct.active,
+            ct.min_duration_days,
+            ct.max_duration_days,
+            ct.depreciation
+                .as_ref()
+                .map(|d| serde_json::to_value(d).unwrap()), // Serialize the depreciation rule to JSON
+            ct.version,
+            ct.base_uuid.unwrap_or(ct.id),
+            ct.policy_conditions
+                .as_ref()
+                .map(|c| serde_json::to_value(c).unwrap()) // Serialize the policy conditions to JSON
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| or_duplicate(err, "Contract Type"))?;
+
+        Ok(())
+    }
+
+    async fn set_contract_type_active(&self, id: Uuid, active: bool) -> Result<bool, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let result = sqlx::query!(
+            "UPDATE contract_types SET active = $1 WHERE id = $2",
+            active,
+            id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgStorage {
+    async fn find_user(&self, username: &str) -> Result<Option<User>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT username, password, first_name, last_name,
+                ARRAY(SELECT id::TEXT FROM contracts WHERE contracts.username = users.username ORDER BY start_date) AS "contract_index!"
+            FROM users
+            WHERE username = $1
+            "#,
+            username
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    async fn find_users(&self, usernames: &[String]) -> Result<Vec<User>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT username, password, first_name, last_name,
+                ARRAY(SELECT id::TEXT FROM contracts WHERE contracts.username = users.username ORDER BY start_date) AS "contract_index!"
+            FROM users
+            WHERE username = ANY($1)
+            "#,
+            usernames
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO users (username, password, first_name, last_name)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            user.username,
+            user.password,
+            user.first_name,
+            user.last_name
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| or_duplicate(err, "User"))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContractRepository for PgStorage {
+    async fn find_contract(&self, id: Uuid) -> Result<Option<Contract>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        Contract::find(&mut *conn, id).await
+    }
+
+    async fn find_contracts(&self, ids: &[Uuid]) -> Result<Vec<Contract>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        Contract::find_many(&mut *conn, ids).await
+    }
+
+    async fn insert_contract(&self, contract: &Contract) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO contracts (id, username, contract_type_uuid, item, start_date, end_date, void, renewed_from)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            contract.id,
+            contract.username,
+            contract.contract_type_uuid,
+            serde_json::to_value(&contract.item).unwrap(), // Serialize the item to JSON
+            contract.start_date,
+            contract.end_date,
+            contract.void,
+            contract.renewed_from
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| or_duplicate(err, "Contract"))?;
+
+        Ok(())
+    }
+
+    async fn contracts_of_user(&self, username: &str) -> Result<Vec<Contract>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        Contract::of_user(&mut *conn, username).await
+    }
+
+    async fn void_contract(&self, id: Uuid) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            r#"
+            UPDATE contracts
+            SET void = TRUE
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ClaimRepository for PgStorage {
+    async fn find_claim(&self, id: Uuid) -> Result<Option<Claim>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_as!(
+            Claim,
+            r#"
+            SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference,
+                damage_category, region, fraud_score, fraud_rules, assignee, assigned_at, priority,
+                status_changed_at, sla_breached_at, rejected_from
+            FROM claims
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    async fn find_claims(&self, ids: &[Uuid]) -> Result<Vec<Claim>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        Claim::find_many(&mut *conn, ids).await
+    }
+
+    async fn insert_claim(&self, claim: &Claim) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO claims (id, contract_uuid, date, description, is_theft, status, damage_category, region,
+                fraud_score, fraud_rules, status_changed_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#,
+            claim.id,
+            claim.contract_uuid,
+            claim.date,
+            claim.description,
+            claim.is_theft,
+            claim.status,
+            claim.damage_category,
+            claim.region,
+            claim.fraud_score,
+            &claim.fraud_rules,
+            claim.status_changed_at
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| or_duplicate(err, "Claim"))?;
+
+        Ok(())
+    }
+
+    async fn update_claim(&self, claim: &Claim) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            r#"
+            UPDATE claims
+            SET status = $1, reimbursable = $2, repaired = $3, file_reference = $4,
+                status_changed_at = $5, sla_breached_at = $6, rejected_from = $7
+            WHERE id = $8
+            "#,
+            claim.status,
+            claim.reimbursable,
+            claim.repaired,
+            claim.file_reference,
+            claim.status_changed_at,
+            claim.sla_breached_at,
+            claim.rejected_from,
+            claim.id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn claims_of_contracts(&self, contract_uuids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Claim>>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        Claim::for_contracts(&mut *conn, contract_uuids).await
+    }
+
+    async fn theft_claims_to_confirm(&self) -> Result<Vec<Claim>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_as!(
+            Claim,
+            r#"
+            SELECT id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, file_reference,
+                damage_category, region, fraud_score, fraud_rules, assignee, assigned_at, priority,
+                status_changed_at, sla_breached_at, rejected_from
+            FROM claims
+            WHERE is_theft = TRUE AND status = 'New'
+            ORDER BY date
+            "#
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    async fn claims_in_year(&self, claim: &Claim) -> Result<i64, Error> {
+        let mut conn = self.connections.acquire().await?;
+        conditions::claims_in_year(&mut *conn, claim).await
+    }
+
+    async fn claim_history(&self, claim: &Claim, contract: &Contract) -> Result<ClaimHistory, Error> {
+        let mut conn = self.connections.acquire().await?;
+        fraud::claim_history(&mut *conn, claim, contract).await
+    }
+}
+
+#[async_trait]
+impl RepairOrderRepository for PgStorage {
+    async fn find_repair_order(&self, id: Uuid) -> Result<Option<RepairOrder>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_as!(
+            RepairOrder,
+            r#"
+            SELECT id, claim_uuid, contract_uuid, item, ready
+            FROM repair_orders
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    async fn insert_repair_order(&self, repair_order: &RepairOrder) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO repair_orders (id, claim_uuid, contract_uuid, item, ready)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            repair_order.id,
+            repair_order.claim_uuid,
+            repair_order.contract_uuid,
+            repair_order.item,
+            repair_order.ready
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn open_repair_orders(&self) -> Result<Vec<RepairOrder>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_as!(
+            RepairOrder,
+            r#"
+            SELECT id, claim_uuid, contract_uuid, item, ready
+            FROM repair_orders
+            WHERE ready = FALSE
+            "#
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    async fn complete_repair_order(&self, repair_order: &RepairOrder) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE repair_orders
+            SET ready = TRUE
+            WHERE id = $1
+            "#,
+            repair_order.id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE claims
+            SET repaired = TRUE
+            WHERE id = $1 AND contract_uuid = $2
+            "#,
+            repair_order.claim_uuid,
+            repair_order.contract_uuid
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await
+    }
+}
+
+// The text search vectors match the expression indexes of the search migration
+#[async_trait]
+impl SettlementRepository for PgStorage {
+    async fn settlements_of_claim(&self, claim_uuid: Uuid) -> Result<Vec<ClaimSettlement>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_as!(
+            ClaimSettlement,
+            r#"
+            SELECT id, claim_uuid, kind, amount, note, created_by, created_at
+            FROM claim_settlements
+            WHERE claim_uuid = $1
+            ORDER BY created_at
+            "#,
+            claim_uuid
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    async fn insert_settlement(&self, settlement: &ClaimSettlement) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO claim_settlements (id, claim_uuid, kind, amount, note, created_by, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            settlement.id,
+            settlement.claim_uuid,
+            settlement.kind,
+            settlement.amount,
+            settlement.note,
+            settlement.created_by,
+            settlement.created_at
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SearchRepository for PgStorage {
+    async fn search(&self, terms: &str, kinds: &[SearchKind], scope: &SearchScope, limit: i64) -> Result<Vec<SearchHit>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let mut hits = Vec::new();
+
+        if kinds.contains(&SearchKind::Claim) {
+            let rows = sqlx::query!(
+                r#"
+                SELECT claims.id, claims.date,
+                    ts_rank(to_tsvector('english', claims.description), websearch_to_tsquery('english', $1)) AS "rank!",
+                    ts_headline('english', claims.description, websearch_to_tsquery('english', $1)) AS "snippet!"
+                FROM claims
+                JOIN contracts ON contracts.id = claims.contract_uuid
+                WHERE to_tsvector('english', claims.description) @@ websearch_to_tsquery('english', $1)
+                    AND ($2::TEXT IS NULL OR contracts.username = $2)
+                    AND (NOT $3 OR claims.is_theft)
+                    AND (NOT $4 OR EXISTS (SELECT 1 FROM repair_orders WHERE repair_orders.claim_uuid = claims.id))
+                ORDER BY 3 DESC
+                LIMIT $5
+                "#,
+                terms,
+                scope.username(),
+                scope.theft_only(),
+                scope.repaired_only(),
+                limit
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+
+            hits.extend(rows.into_iter().map(|r| SearchHit {
+                kind: SearchKind::Claim,
+                uuid: r.id.to_string(),
+                title: format!("Claim of {}", r.date.format("%Y-%m-%d")),
+                snippet: r.snippet,
+                rank: r.rank,
+            }));
+        }
+
+        if kinds.contains(&SearchKind::Contract) {
+            let rows = sqlx::query!(
+                r#"
+                SELECT contracts.id, contracts.item->>'brand' AS "brand!", contracts.item->>'model' AS "model!",
+                    contracts.item->>'serial_no' AS "serial_no!",
+                    ts_rank(
+                        to_tsvector('simple', coalesce(contracts.item->>'brand', '') || ' ' || coalesce(contracts.item->>'model', '')
+                            || ' ' || coalesce(contracts.item->>'serial_no', '')),
+                        websearch_to_tsquery('simple', $1)
+                    ) AS "rank!"
+                FROM contracts
+                WHERE to_tsvector('simple', coalesce(contracts.item->>'brand', '') || ' ' || coalesce(contracts.item->>'model', '')
+                        || ' ' || coalesce(contracts.item->>'serial_no', '')) @@ websearch_to_tsquery('simple', $1)
+                    AND ($2::TEXT IS NULL OR contracts.username = $2)
+                    AND (NOT $3 OR EXISTS (SELECT 1 FROM claims WHERE claims.contract_uuid = contracts.id AND claims.is_theft))
+                    AND (NOT $4 OR EXISTS (SELECT 1 FROM repair_orders WHERE repair_orders.contract_uuid = contracts.id))
+                ORDER BY 5 DESC
+                LIMIT $5
+                "#,
+                terms,
+                scope.username(),
+                scope.theft_only(),
+                scope.repaired_only(),
+                limit
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+
+            hits.extend(rows.into_iter().map(|r| SearchHit {
+                kind: SearchKind::Contract,
+                uuid: r.id.to_string(),
+                title: format!("{} {}", r.brand, r.model),
+                snippet: r.serial_no,
+                rank: r.rank,
+            }));
+        }
+
+        if kinds.contains(&SearchKind::Customer) {
+            let rows = sqlx::query!(
+                r#"
+                SELECT username, first_name, last_name,
+                    ts_rank(to_tsvector('simple', first_name || ' ' || last_name), websearch_to_tsquery('simple', $1)) AS "rank!"
+                FROM users
+                WHERE to_tsvector('simple', first_name || ' ' || last_name) @@ websearch_to_tsquery('simple', $1)
+                    AND ($2::TEXT IS NULL OR users.username = $2)
+                    AND (NOT $3 OR EXISTS (
+                        SELECT 1 FROM contracts JOIN claims ON claims.contract_uuid = contracts.id
+                        WHERE contracts.username = users.username AND claims.is_theft
+                    ))
+                    AND (NOT $4 OR EXISTS (
+                        SELECT 1 FROM contracts JOIN repair_orders ON repair_orders.contract_uuid = contracts.id
+                        WHERE contracts.username = users.username
+                    ))
+                ORDER BY 4 DESC
+                LIMIT $5
+                "#,
+                terms,
+                scope.username(),
+                scope.theft_only(),
+                scope.repaired_only(),
+                limit
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+
+            hits.extend(rows.into_iter().map(|r| SearchHit {
+                kind: SearchKind::Customer,
+                uuid: r.username.clone(),
+                title: format!("{} {}", r.first_name, r.last_name),
+                snippet: r.username,
+                rank: r.rank,
+            }));
+        }
+
+        Ok(hits)
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for PgStorage {
+    async fn reserve_idempotency_key(&self, record: &IdempotencyRecord) -> Result<bool, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO idempotency_keys (key, request_hash, response, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+            record.key,
+            record.request_hash,
+            record.response,
+            record.created_at
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_idempotency_key(&self, key: &str) -> Result<Option<IdempotencyRecord>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_as!(
+            IdempotencyRecord,
+            r#"
+            SELECT key, request_hash, response, created_at
+            FROM idempotency_keys
+            WHERE key = $1
+            "#,
+            key
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    async fn complete_idempotency_key(&self, key: &str, response: &str) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!("UPDATE idempotency_keys SET response = $2 WHERE key = $1", key, response)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!("DELETE FROM idempotency_keys WHERE key = $1", key)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn purge_idempotency_keys(&self, before: NaiveDateTime, abandoned_before: NaiveDateTime) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query!(
+            "DELETE FROM idempotency_keys WHERE created_at < $1 OR (response IS NULL AND created_at < $2)",
+            before,
+            abandoned_before
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+}
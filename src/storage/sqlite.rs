@@ -0,0 +1,854 @@
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow};
+use chrono::NaiveDateTime;
+use sqlx::{Connection, Error, Pool, Row, Sqlite};
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use super::connections::Connections;
+use super::{
+    or_duplicate, ClaimRepository, ContractRepository, ContractTypeRepository, IdempotencyRepository, RepairOrderRepository,
+    SearchRepository, SettlementRepository, UserRepository,
+};
+use crate::data::{Claim, Contract, ContractType, RepairOrder, User};
+use crate::fraud::ClaimHistory;
+use crate::idempotency::IdempotencyRecord;
+use crate::search::{self, SearchHit, SearchKind, SearchScope};
+use crate::settlements::ClaimSettlement;
+
+// SQLite backend for single-node deployments of the core claim workflow, see
+// workflow.rs for the functions it serves. UUIDs are stored as text and JSON
+// documents (items, depreciation, conditions, fraud rules) as JSON text.
+
+const CONTRACT_TYPE_COLUMNS: &str = "id, shop_type, formula_per_day, max_sum_insured, theft_insured, description, \
+    conditions, active, min_duration_days, max_duration_days, depreciation, version, base_uuid, superseded_by, \
+    policy_conditions";
+
+const USER_COLUMNS: &str = "username, password, first_name, last_name, \
+    (SELECT json_group_array(id) FROM (SELECT id FROM contracts WHERE contracts.username = users.username ORDER BY start_date)) \
+    AS contract_index";
+
+const CONTRACT_COLUMNS: &str = "id, username, item, start_date, end_date, void, contract_type_uuid, \
+    (SELECT json_group_array(id) FROM (SELECT id FROM claims WHERE claims.contract_uuid = contracts.id ORDER BY date)) AS claim_index, \
+    cancelled_at, cancel_reason, refund, expired, renewed_from";
+
+const CLAIM_COLUMNS: &str = "id, contract_uuid, date, description, is_theft, status, reimbursable, repaired, \
+    file_reference, damage_category, region, fraud_score, fraud_rules, assignee, assigned_at, priority, \
+    status_changed_at, sla_breached_at, rejected_from";
+
+#[derive(Clone)]
+pub struct SqliteStorage {
+    connections: Connections<Sqlite>,
+}
+
+impl SqliteStorage {
+    pub async fn connect(database_url: &str) -> Result<Self, Error> {
+        let options = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .foreign_keys(true);
+
+        // An in-memory database lives as long as its connection, keep exactly one
+        let pool = if database_url.contains(":memory:") {
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect_with(options)
+                .await?
+        } else {
+            SqlitePoolOptions::new().connect_with(options).await?
+        };
+
+        Ok(SqliteStorage {
+            connections: Connections::new(pool),
+        })
+    }
+
+    pub fn pool(&self) -> &Pool<Sqlite> {
+        self.connections.pool()
+    }
+
+    // Storage whose changes become visible once committed
+    pub async fn begin(&self) -> Result<Self, Error> {
+        Ok(SqliteStorage {
+            connections: self.connections.begin().await?,
+        })
+    }
+
+    pub async fn commit(&self) -> Result<(), Error> {
+        self.connections.commit().await
+    }
+
+    pub async fn rollback(&self) -> Result<(), Error> {
+        self.connections.rollback().await
+    }
+
+    // Rows of `sql`, filtered by the search scope bound to ?1 to ?3, whose `text`
+    // expression contains any of the search terms
+    async fn rows_with_terms(&self, sql: &str, text: &str, terms: &[&str], scope: &SearchScope) -> Result<Vec<SqliteRow>, Error> {
+        let any_term = (0..terms.len())
+            .map(|i| format!("instr(lower({}), lower(?{})) > 0", text, i + 4))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        let sql = format!("{} AND ({})", sql, any_term);
+
+        let mut query = sqlx::query(&sql)
+            .bind(scope.username())
+            .bind(scope.theft_only())
+            .bind(scope.repaired_only());
+        for term in terms {
+            query = query.bind(*term);
+        }
+
+        let mut conn = self.connections.acquire().await?;
+        query.fetch_all(&mut *conn).await
+    }
+}
+
+fn decode_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
+    Error::Decode(Box::new(err))
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, Error> {
+    serde_json::to_string(value).map_err(decode_error)
+}
+
+fn get_uuid(row: &SqliteRow, column: &str) -> Result<Uuid, Error> {
+    row.try_get::<String, _>(column)?.parse().map_err(decode_error)
+}
+
+fn get_optional_uuid(row: &SqliteRow, column: &str) -> Result<Option<Uuid>, Error> {
+    match row.try_get::<Option<String>, _>(column)? {
+        Some(value) => Ok(Some(value.parse().map_err(decode_error)?)),
+        None => Ok(None),
+    }
+}
+
+fn get_json<T: DeserializeOwned>(row: &SqliteRow, column: &str) -> Result<T, Error> {
+    serde_json::from_str(&row.try_get::<String, _>(column)?).map_err(decode_error)
+}
+
+fn get_optional_json<T: DeserializeOwned>(row: &SqliteRow, column: &str) -> Result<Option<T>, Error> {
+    match row.try_get::<Option<String>, _>(column)? {
+        Some(value) => Ok(Some(serde_json::from_str(&value).map_err(decode_error)?)),
+        None => Ok(None),
+    }
+}
+
+fn contract_type_from_row(row: &SqliteRow) -> Result<ContractType, Error> {
+    Ok(ContractType {
+        id: get_uuid(row, "id")?,
+        shop_type: row.try_get("shop_type")?,
+        formula_per_day: row.try_get("formula_per_day")?,
+        max_sum_insured: row.try_get::<f64, _>("max_sum_insured")? as f32,
+        theft_insured: row.try_get("theft_insured")?,
+        description: row.try_get::<Option<String>, _>("description")?.unwrap_or_default(),
+        conditions: row.try_get::<Option<String>, _>("conditions")?.unwrap_or_default(),
+        active: row.try_get("active")?,
+        min_duration_days: row.try_get("min_duration_days")?,
+        max_duration_days: row.try_get("max_duration_days")?,
+        depreciation: get_optional_json(row, "depreciation")?,
+        version: row.try_get("version")?,
+        base_uuid: Some(get_uuid(row, "base_uuid")?),
+        superseded_by: get_optional_uuid(row, "superseded_by")?,
+        policy_conditions: get_optional_json(row, "policy_conditions")?,
+    })
+}
+
+fn user_from_row(row: &SqliteRow) -> Result<User, Error> {
+    Ok(User {
+        username: row.try_get("username")?,
+        password: row.try_get("password")?,
+        first_name: row.try_get("first_name")?,
+        last_name: row.try_get("last_name")?,
+        contract_index: get_json(row, "contract_index")?,
+    })
+}
+
+fn contract_from_row(row: &SqliteRow) -> Result<Contract, Error> {
+    Ok(Contract {
+        id: get_uuid(row, "id")?,
+        username: row.try_get("username")?,
+        item: get_json(row, "item")?,
+        start_date: row.try_get("start_date")?,
+        end_date: row.try_get("end_date")?,
+        void: row.try_get("void")?,
+        contract_type_uuid: get_uuid(row, "contract_type_uuid")?,
+        claim_index: Some(get_json(row, "claim_index")?),
+        cancelled_at: row.try_get("cancelled_at")?,
+        cancel_reason: row.try_get("cancel_reason")?,
+        refund: row.try_get::<Option<f64>, _>("refund")?.map(|refund| refund as f32),
+        expired: row.try_get("expired")?,
+        renewed_from: get_optional_uuid(row, "renewed_from")?,
+    })
+}
+
+fn claim_from_row(row: &SqliteRow) -> Result<Claim, Error> {
+    Ok(Claim {
+        id: get_uuid(row, "id")?,
+        contract_uuid: get_uuid(row, "contract_uuid")?,
+        date: row.try_get("date")?,
+        description: row.try_get("description")?,
+        is_theft: row.try_get("is_theft")?,
+        status: row.try_get("status")?,
+        reimbursable: row.try_get::<f64, _>("reimbursable")? as f32,
+        repaired: row.try_get("repaired")?,
+        file_reference: row.try_get("file_reference")?,
+        damage_category: row.try_get("damage_category")?,
+        region: row.try_get("region")?,
+        fraud_score: row.try_get("fraud_score")?,
+        fraud_rules: get_json(row, "fraud_rules")?,
+        assignee: row.try_get("assignee")?,
+        assigned_at: row.try_get("assigned_at")?,
+        priority: row.try_get("priority")?,
+        status_changed_at: row.try_get("status_changed_at")?,
+        sla_breached_at: row.try_get("sla_breached_at")?,
+        rejected_from: row.try_get("rejected_from")?,
+    })
+}
+
+fn repair_order_from_row(row: &SqliteRow) -> Result<RepairOrder, Error> {
+    Ok(RepairOrder {
+        id: get_uuid(row, "id")?,
+        claim_uuid: get_uuid(row, "claim_uuid")?,
+        contract_uuid: get_uuid(row, "contract_uuid")?,
+        item: get_json(row, "item")?,
+        ready: row.try_get("ready")?,
+    })
+}
+
+fn settlement_from_row(row: &SqliteRow) -> Result<ClaimSettlement, Error> {
+    Ok(ClaimSettlement {
+        id: get_uuid(row, "id")?,
+        claim_uuid: get_uuid(row, "claim_uuid")?,
+        kind: row.try_get("kind")?,
+        amount: row.try_get("amount")?,
+        note: row.try_get("note")?,
+        created_by: row.try_get("created_by")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn idempotency_record_from_row(row: &SqliteRow) -> Result<IdempotencyRecord, Error> {
+    Ok(IdempotencyRecord {
+        key: row.try_get("key")?,
+        request_hash: row.try_get("request_hash")?,
+        response: row.try_get("response")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+#[async_trait]
+impl ContractTypeRepository for SqliteStorage {
+    async fn find_contract_type(&self, id: Uuid) -> Result<Option<ContractType>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let row = sqlx::query(&format!("SELECT {} FROM contract_types WHERE id = ?", CONTRACT_TYPE_COLUMNS))
+            .bind(id.to_string())
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        row.as_ref().map(contract_type_from_row).transpose()
+    }
+
+    async fn insert_contract_type(&self, ct: &ContractType) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO contract_types (id, shop_type, formula_per_day, max_sum_insured, theft_insured,
+                description, conditions, active, min_duration_days, max_duration_days, depreciation,
+                version, base_uuid, policy_conditions)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(ct.id.to_string())
+        .bind(&ct.shop_type)
+        .bind(&ct.formula_per_day)
+        .bind(f64::from(ct.max_sum_insured))
+        .bind(ct.theft_insured)
+        .bind(&ct.description)
+        .bind(&ct.conditions)
+        .bind(ct.active)
+        .bind(ct.min_duration_days)
+        .bind(ct.max_duration_days)
+        .bind(ct.depreciation.as_ref().map(to_json).transpose()?)
+        .bind(ct.version)
+        .bind(ct.base_uuid.unwrap_or(ct.id).to_string())
+        .bind(ct.policy_conditions.as_ref().map(to_json).transpose()?)
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| or_duplicate(err, "Contract Type"))?;
+
+        Ok(())
+    }
+
+    async fn set_contract_type_active(&self, id: Uuid, active: bool) -> Result<bool, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let result = sqlx::query("UPDATE contract_types SET active = ? WHERE id = ?")
+            .bind(active)
+            .bind(id.to_string())
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqliteStorage {
+    async fn find_user(&self, username: &str) -> Result<Option<User>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE username = ?", USER_COLUMNS))
+            .bind(username)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    async fn find_users(&self, usernames: &[String]) -> Result<Vec<User>, Error> {
+        if usernames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.connections.acquire().await?;
+        let placeholders = vec!["?"; usernames.len()].join(", ");
+        let sql = format!("SELECT {} FROM users WHERE username IN ({})", USER_COLUMNS, placeholders);
+        let mut query = sqlx::query(&sql);
+        for username in usernames {
+            query = query.bind(username);
+        }
+
+        query.fetch_all(&mut *conn).await?.iter().map(user_from_row).collect()
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO users (username, password, first_name, last_name)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(&user.username)
+        .bind(&user.password)
+        .bind(&user.first_name)
+        .bind(&user.last_name)
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| or_duplicate(err, "User"))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContractRepository for SqliteStorage {
+    async fn find_contract(&self, id: Uuid) -> Result<Option<Contract>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let row = sqlx::query(&format!("SELECT {} FROM contracts WHERE id = ?", CONTRACT_COLUMNS))
+            .bind(id.to_string())
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        row.as_ref().map(contract_from_row).transpose()
+    }
+
+    async fn find_contracts(&self, ids: &[Uuid]) -> Result<Vec<Contract>, Error> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.connections.acquire().await?;
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let sql = format!("SELECT {} FROM contracts WHERE id IN ({})", CONTRACT_COLUMNS, placeholders);
+        let mut query = sqlx::query(&sql);
+        for id in ids {
+            query = query.bind(id.to_string());
+        }
+
+        query.fetch_all(&mut *conn).await?.iter().map(contract_from_row).collect()
+    }
+
+    async fn insert_contract(&self, contract: &Contract) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO contracts (id, username, contract_type_uuid, item, start_date, end_date, void, renewed_from)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(contract.id.to_string())
+        .bind(&contract.username)
+        .bind(contract.contract_type_uuid.to_string())
+        .bind(to_json(&contract.item)?)
+        .bind(contract.start_date)
+        .bind(contract.end_date)
+        .bind(contract.void)
+        .bind(contract.renewed_from.map(|uuid| uuid.to_string()))
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| or_duplicate(err, "Contract"))?;
+
+        Ok(())
+    }
+
+    async fn contracts_of_user(&self, username: &str) -> Result<Vec<Contract>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM contracts WHERE username = ? ORDER BY start_date",
+            CONTRACT_COLUMNS
+        ))
+        .bind(username)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        rows.iter().map(contract_from_row).collect()
+    }
+
+    async fn void_contract(&self, id: Uuid) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query("UPDATE contracts SET void = TRUE WHERE id = ?")
+            .bind(id.to_string())
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ClaimRepository for SqliteStorage {
+    async fn find_claim(&self, id: Uuid) -> Result<Option<Claim>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let row = sqlx::query(&format!("SELECT {} FROM claims WHERE id = ?", CLAIM_COLUMNS))
+            .bind(id.to_string())
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        row.as_ref().map(claim_from_row).transpose()
+    }
+
+    async fn find_claims(&self, ids: &[Uuid]) -> Result<Vec<Claim>, Error> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.connections.acquire().await?;
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let sql = format!("SELECT {} FROM claims WHERE id IN ({})", CLAIM_COLUMNS, placeholders);
+        let mut query = sqlx::query(&sql);
+        for id in ids {
+            query = query.bind(id.to_string());
+        }
+
+        query.fetch_all(&mut *conn).await?.iter().map(claim_from_row).collect()
+    }
+
+    async fn insert_claim(&self, claim: &Claim) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO claims (id, contract_uuid, date, description, is_theft, status, damage_category, region,
+                fraud_score, fraud_rules, status_changed_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(claim.id.to_string())
+        .bind(claim.contract_uuid.to_string())
+        .bind(claim.date)
+        .bind(&claim.description)
+        .bind(claim.is_theft)
+        .bind(&claim.status)
+        .bind(&claim.damage_category)
+        .bind(&claim.region)
+        .bind(claim.fraud_score)
+        .bind(to_json(&claim.fraud_rules)?)
+        .bind(claim.status_changed_at)
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| or_duplicate(err, "Claim"))?;
+
+        Ok(())
+    }
+
+    async fn update_claim(&self, claim: &Claim) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query(
+            r#"
+            UPDATE claims
+            SET status = ?, reimbursable = ?, repaired = ?, file_reference = ?,
+                status_changed_at = ?, sla_breached_at = ?, rejected_from = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&claim.status)
+        .bind(f64::from(claim.reimbursable))
+        .bind(claim.repaired)
+        .bind(&claim.file_reference)
+        .bind(claim.status_changed_at)
+        .bind(claim.sla_breached_at)
+        .bind(&claim.rejected_from)
+        .bind(claim.id.to_string())
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn claims_of_contracts(&self, contract_uuids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Claim>>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let mut grouped: HashMap<Uuid, Vec<Claim>> = HashMap::new();
+        if contract_uuids.is_empty() {
+            return Ok(grouped);
+        }
+
+        let placeholders = vec!["?"; contract_uuids.len()].join(", ");
+        let sql = format!(
+            "SELECT {} FROM claims WHERE contract_uuid IN ({}) ORDER BY date",
+            CLAIM_COLUMNS, placeholders
+        );
+        let mut query = sqlx::query(&sql);
+        for contract_uuid in contract_uuids {
+            query = query.bind(contract_uuid.to_string());
+        }
+
+        for row in query.fetch_all(&mut *conn).await? {
+            let claim = claim_from_row(&row)?;
+            grouped.entry(claim.contract_uuid).or_default().push(claim);
+        }
+
+        Ok(grouped)
+    }
+
+    async fn theft_claims_to_confirm(&self) -> Result<Vec<Claim>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM claims WHERE is_theft = TRUE AND status = 'New' ORDER BY date",
+            CLAIM_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?;
+
+        rows.iter().map(claim_from_row).collect()
+    }
+
+    async fn claims_in_year(&self, claim: &Claim) -> Result<i64, Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*)
+            FROM claims
+            WHERE contract_uuid = ?
+                AND id <> ?
+                AND status <> 'Rejected'
+                AND date > ?
+                AND date <= ?
+            "#,
+        )
+        .bind(claim.contract_uuid.to_string())
+        .bind(claim.id.to_string())
+        .bind(claim.date - chrono::Duration::days(365))
+        .bind(claim.date)
+        .fetch_one(&mut *conn)
+        .await
+    }
+
+    async fn claim_history(&self, claim: &Claim, contract: &Contract) -> Result<ClaimHistory, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let claims_on_contract = sqlx::query_scalar("SELECT COUNT(*) FROM claims WHERE contract_uuid = ? AND id <> ?")
+            .bind(contract.id.to_string())
+            .bind(claim.id.to_string())
+            .fetch_one(&mut *conn)
+            .await?;
+
+        // Items without a serial number cannot be told apart
+        let other_users_with_serial_no = if contract.item.serial_no.trim().is_empty() {
+            0
+        } else {
+            sqlx::query_scalar(
+                "SELECT COUNT(DISTINCT username) FROM contracts WHERE json_extract(item, '$.serial_no') = ? AND username <> ?",
+            )
+            .bind(&contract.item.serial_no)
+            .bind(&contract.username)
+            .fetch_one(&mut *conn)
+            .await?
+        };
+
+        let theft_claims_by_user = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*)
+            FROM claims
+            JOIN contracts ON contracts.id = claims.contract_uuid
+            WHERE contracts.username = ? AND claims.is_theft = TRUE AND claims.id <> ?
+            "#,
+        )
+        .bind(&contract.username)
+        .bind(claim.id.to_string())
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(ClaimHistory {
+            claims_on_contract,
+            other_users_with_serial_no,
+            theft_claims_by_user,
+        })
+    }
+}
+
+#[async_trait]
+impl RepairOrderRepository for SqliteStorage {
+    async fn find_repair_order(&self, id: Uuid) -> Result<Option<RepairOrder>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let row = sqlx::query("SELECT id, claim_uuid, contract_uuid, item, ready FROM repair_orders WHERE id = ?")
+            .bind(id.to_string())
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        row.as_ref().map(repair_order_from_row).transpose()
+    }
+
+    async fn insert_repair_order(&self, repair_order: &RepairOrder) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO repair_orders (id, claim_uuid, contract_uuid, item, ready)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(repair_order.id.to_string())
+        .bind(repair_order.claim_uuid.to_string())
+        .bind(repair_order.contract_uuid.to_string())
+        .bind(to_json(&repair_order.item)?)
+        .bind(repair_order.ready)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn open_repair_orders(&self) -> Result<Vec<RepairOrder>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let rows = sqlx::query("SELECT id, claim_uuid, contract_uuid, item, ready FROM repair_orders WHERE ready = FALSE")
+            .fetch_all(&mut *conn)
+            .await?;
+
+        rows.iter().map(repair_order_from_row).collect()
+    }
+
+    async fn complete_repair_order(&self, repair_order: &RepairOrder) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        sqlx::query("UPDATE repair_orders SET ready = TRUE WHERE id = ?")
+            .bind(repair_order.id.to_string())
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query("UPDATE claims SET repaired = TRUE WHERE id = ? AND contract_uuid = ?")
+            .bind(repair_order.claim_uuid.to_string())
+            .bind(repair_order.contract_uuid.to_string())
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl SettlementRepository for SqliteStorage {
+    async fn settlements_of_claim(&self, claim_uuid: Uuid) -> Result<Vec<ClaimSettlement>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let rows = sqlx::query(
+            "SELECT id, claim_uuid, kind, amount, note, created_by, created_at FROM claim_settlements \
+                WHERE claim_uuid = ? ORDER BY created_at",
+        )
+        .bind(claim_uuid.to_string())
+        .fetch_all(&mut *conn)
+        .await?;
+
+        rows.iter().map(settlement_from_row).collect()
+    }
+
+    async fn insert_settlement(&self, settlement: &ClaimSettlement) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO claim_settlements (id, claim_uuid, kind, amount, note, created_by, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(settlement.id.to_string())
+        .bind(settlement.claim_uuid.to_string())
+        .bind(&settlement.kind)
+        .bind(settlement.amount)
+        .bind(&settlement.note)
+        .bind(&settlement.created_by)
+        .bind(settlement.created_at)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+// Without full-text search, matches are ranked by the share of terms they contain
+#[async_trait]
+impl SearchRepository for SqliteStorage {
+    async fn search(&self, terms: &str, kinds: &[SearchKind], scope: &SearchScope, limit: i64) -> Result<Vec<SearchHit>, Error> {
+        let words: Vec<&str> = terms.split_whitespace().collect();
+        let mut hits = Vec::new();
+
+        if kinds.contains(&SearchKind::Claim) {
+            let rows = self
+                .rows_with_terms(
+                    "SELECT claims.id, claims.date, claims.description \
+                    FROM claims JOIN contracts ON contracts.id = claims.contract_uuid \
+                    WHERE (?1 IS NULL OR contracts.username = ?1) AND (NOT ?2 OR claims.is_theft) \
+                    AND (NOT ?3 OR EXISTS (SELECT 1 FROM repair_orders WHERE repair_orders.claim_uuid = claims.id))",
+                    "claims.description",
+                    &words,
+                    scope,
+                )
+                .await?;
+
+            let mut claims = Vec::new();
+            for row in rows {
+                let date: NaiveDateTime = row.try_get("date")?;
+                let description: String = row.try_get("description")?;
+                claims.push(SearchHit {
+                    kind: SearchKind::Claim,
+                    uuid: get_uuid(&row, "id")?.to_string(),
+                    title: format!("Claim of {}", date.format("%Y-%m-%d")),
+                    rank: search::term_rank(terms, &description),
+                    snippet: description,
+                });
+            }
+            hits.extend(search::best_hits(claims, limit));
+        }
+
+        if kinds.contains(&SearchKind::Contract) {
+            let rows = self
+                .rows_with_terms(
+                    "SELECT contracts.id, json_extract(contracts.item, '$.brand') AS brand, \
+                    json_extract(contracts.item, '$.model') AS model, json_extract(contracts.item, '$.serial_no') AS serial_no \
+                    FROM contracts \
+                    WHERE (?1 IS NULL OR contracts.username = ?1) \
+                    AND (NOT ?2 OR EXISTS (SELECT 1 FROM claims WHERE claims.contract_uuid = contracts.id AND claims.is_theft)) \
+                    AND (NOT ?3 OR EXISTS (SELECT 1 FROM repair_orders WHERE repair_orders.contract_uuid = contracts.id))",
+                    "(json_extract(contracts.item, '$.brand') || ' ' || json_extract(contracts.item, '$.model') \
+                    || ' ' || json_extract(contracts.item, '$.serial_no'))",
+                    &words,
+                    scope,
+                )
+                .await?;
+
+            let mut contracts = Vec::new();
+            for row in rows {
+                let brand: String = row.try_get("brand")?;
+                let model: String = row.try_get("model")?;
+                let serial_no: String = row.try_get("serial_no")?;
+                contracts.push(SearchHit {
+                    kind: SearchKind::Contract,
+                    uuid: get_uuid(&row, "id")?.to_string(),
+                    rank: search::term_rank(terms, &format!("{} {} {}", brand, model, serial_no)),
+                    title: format!("{} {}", brand, model),
+                    snippet: serial_no,
+                });
+            }
+            hits.extend(search::best_hits(contracts, limit));
+        }
+
+        if kinds.contains(&SearchKind::Customer) {
+            let rows = self
+                .rows_with_terms(
+                    "SELECT users.username, users.first_name, users.last_name \
+                    FROM users \
+                    WHERE (?1 IS NULL OR users.username = ?1) \
+                    AND (NOT ?2 OR EXISTS (SELECT 1 FROM contracts JOIN claims ON claims.contract_uuid = contracts.id \
+                        WHERE contracts.username = users.username AND claims.is_theft)) \
+                    AND (NOT ?3 OR EXISTS (SELECT 1 FROM contracts JOIN repair_orders ON repair_orders.contract_uuid = contracts.id \
+                        WHERE contracts.username = users.username))",
+                    "(users.first_name || ' ' || users.last_name)",
+                    &words,
+                    scope,
+                )
+                .await?;
+
+            let mut customers = Vec::new();
+            for row in rows {
+                let username: String = row.try_get("username")?;
+                let name = format!("{} {}", row.try_get::<String, _>("first_name")?, row.try_get::<String, _>("last_name")?);
+                customers.push(SearchHit {
+                    kind: SearchKind::Customer,
+                    uuid: username.clone(),
+                    rank: search::term_rank(terms, &name),
+                    title: name,
+                    snippet: username,
+                });
+            }
+            hits.extend(search::best_hits(customers, limit));
+        }
+
+        Ok(hits)
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for SqliteStorage {
+    async fn reserve_idempotency_key(&self, record: &IdempotencyRecord) -> Result<bool, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let result = sqlx::query(
+            r#"
+            INSERT INTO idempotency_keys (key, request_hash, response, created_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+        )
+        .bind(&record.key)
+        .bind(&record.request_hash)
+        .bind(&record.response)
+        .bind(record.created_at)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_idempotency_key(&self, key: &str) -> Result<Option<IdempotencyRecord>, Error> {
+        let mut conn = self.connections.acquire().await?;
+        let row = sqlx::query("SELECT key, request_hash, response, created_at FROM idempotency_keys WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        row.as_ref().map(idempotency_record_from_row).transpose()
+    }
+
+    async fn complete_idempotency_key(&self, key: &str, response: &str) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query("UPDATE idempotency_keys SET response = ? WHERE key = ?")
+            .bind(response)
+            .bind(key)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query("DELETE FROM idempotency_keys WHERE key = ?")
+            .bind(key)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn purge_idempotency_keys(&self, before: NaiveDateTime, abandoned_before: NaiveDateTime) -> Result<(), Error> {
+        let mut conn = self.connections.acquire().await?;
+        sqlx::query("DELETE FROM idempotency_keys WHERE created_at < ? OR (response IS NULL AND created_at < ?)")
+            .bind(before)
+            .bind(abandoned_before)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+}
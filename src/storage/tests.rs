@@ -0,0 +1,1046 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use sqlx::Error;
+use uuid::Uuid;
+
+use super::{Backend, MemoryStorage, PgStorage, SqliteStorage, Storage};
+use crate::adjusters::{self, AdjusterRole};
+use crate::batch::{self, BatchError};
+use crate::data::{ClaimStatus, ContractType, Item, PeerRole};
+use crate::idempotency::{caller, scoped_key, IdempotencyRecord};
+use crate::ids::ClientIds;
+use crate::listing::{ListQuery, SortOrder};
+use crate::messages::Caller;
+use crate::insurance::{
+    close_decision, decide_claim, prepare_claim, register_contract_type, submit_claim, FileClaimDto,
+};
+use crate::police::{confirm_theft, theft_claims_to_confirm, ProcessTheftClaimDto};
+use crate::repairs::{finish_repair_order, open_repair_orders};
+use crate::rpc;
+use crate::search::{search_records, SearchDto, SearchKind};
+use crate::shop::{open_contract, CreateContractDto, CreateUserDto};
+
+// Workflow suite shared by every storage backend: a shop sells contracts, the
+// customer files a damage and a theft claim, the insurer, repair shop and police
+// process them. Runs only look at their own records so they can share a database.
+
+fn io_kind(err: &Error) -> Option<std::io::ErrorKind> {
+    match err {
+        Error::Decode(source) => source.downcast_ref::<std::io::Error>().map(|err| err.kind()),
+        _ => None,
+    }
+}
+
+fn contract_type(theft_insured: bool) -> ContractType {
+    ContractType {
+        id: Uuid::nil(),
+        shop_type: "Bicycle".to_string(),
+        formula_per_day: "price * 0.001".to_string(),
+        max_sum_insured: 2000.0,
+        theft_insured,
+        description: "Bicycle insurance".to_string(),
+        conditions: String::new(),
+        active: true,
+        min_duration_days: 30,
+        max_duration_days: 730,
+        depreciation: None,
+        version: 1,
+        base_uuid: None,
+        superseded_by: None,
+        policy_conditions: None,
+    }
+}
+
+fn contract_dto(contract_type_uuid: Uuid, username: &str, password: &str, start_date: NaiveDateTime) -> CreateContractDto {
+    CreateContractDto {
+        uuid: None,
+        contract_type_uuid,
+        username: username.to_string(),
+        password: password.to_string(),
+        first_name: "Jane".to_string(),
+        last_name: "Doe".to_string(),
+        item: Item {
+            id: 1,
+            brand: "Gazelle".to_string(),
+            model: "Ultimate".to_string(),
+            price: 1500.0,
+            description: "E-bike".to_string(),
+            serial_no: "GZ-1234".to_string(),
+        },
+        start_date,
+        end_date: start_date + Duration::days(365),
+    }
+}
+
+// Id the server generated for a new record
+fn created_uuid(response: &str) -> Uuid {
+    let response: serde_json::Value = serde_json::from_str(response).unwrap();
+    response["uuid"].as_str().unwrap().parse().unwrap()
+}
+
+fn username() -> String {
+    format!("jdoe-{}", Uuid::new_v4().simple())
+}
+
+fn of_user(username: &str) -> ListQuery {
+    ListQuery {
+        username: Some(username.to_string()),
+        ..ListQuery::default()
+    }
+}
+
+// Entries of a JSON list page whose `field` equals `value`
+fn entries_with(response: &str, field: &str, value: Uuid) -> Vec<serde_json::Value> {
+    let page: serde_json::Value = serde_json::from_str(response).unwrap();
+    page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry[field] == value.to_string())
+        .cloned()
+        .collect()
+}
+
+fn claim_dto(contract_uuid: Uuid, date: NaiveDateTime, is_theft: bool) -> FileClaimDto {
+    FileClaimDto {
+        uuid: None,
+        contract_uuid,
+        date,
+        description: if is_theft { "Stolen at the station" } else { "Broken frame" }.to_string(),
+        is_theft,
+        damage_category: None,
+        region: None,
+    }
+}
+
+pub(super) async fn run_workflow(storage: &dyn Storage) {
+    let now = Utc::now().naive_utc();
+    let start_date = now - Duration::days(30);
+
+    // Insurer offers a contract type
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(true)).await.unwrap();
+    let username = username();
+
+    // First purchase registers the customer and returns the credentials
+    let first = contract_dto(contract_type_uuid, &username, "secret", start_date);
+    let response = open_contract(storage, first, ClientIds::Refused).await.unwrap();
+    assert!(response.contains(&username));
+    let first_uuid = created_uuid(&response);
+
+    // Later purchases need the customer's password
+    let wrong = contract_dto(contract_type_uuid, &username, "guess", start_date);
+    let wrong = open_contract(storage, wrong, ClientIds::Refused).await.unwrap_err();
+    assert_eq!(io_kind(&wrong), Some(std::io::ErrorKind::PermissionDenied));
+
+    let second = contract_dto(contract_type_uuid, &username, "secret", start_date);
+    let response = open_contract(storage, second, ClientIds::Refused).await.unwrap();
+    assert!(!response.contains("password"));
+    let second_uuid = created_uuid(&response);
+    assert_ne!(second_uuid, first_uuid);
+
+    let contracts = storage.contracts_of_user(&username).await.unwrap();
+    assert_eq!(contracts.len(), 2);
+
+    // Damage claim is filed, approved for repair and repaired
+    let (mut damage, _) = prepare_claim(
+        storage,
+        claim_dto(first_uuid, now - Duration::days(2), false),
+        now,
+        ClientIds::Refused,
+    )
+    .await
+    .unwrap();
+    storage.insert_claim(&damage).await.unwrap();
+
+    let contract = storage.find_contract(first_uuid).await.unwrap().unwrap();
+    assert_eq!(contract.claim_index, Some(vec![damage.id]));
+
+    decide_claim(storage, &mut damage, ClaimStatus::Repair).await.unwrap();
+    close_decision(storage, &damage, "New", now).await.unwrap();
+
+    let orders = entries_with(&open_repair_orders(storage, of_user(&username)).await.unwrap(), "claim_uuid", damage.id);
+    assert_eq!(orders.len(), 1);
+
+    let order_uuid: Uuid = orders[0]["uuid"].as_str().unwrap().parse().unwrap();
+    finish_repair_order(storage, order_uuid).await.unwrap();
+
+    let repaired = storage.find_claim(damage.id).await.unwrap().unwrap();
+    assert_eq!(repaired.status, "Repair");
+    assert!(repaired.repaired);
+    let orders = open_repair_orders(storage, of_user(&username)).await.unwrap();
+    assert!(entries_with(&orders, "claim_uuid", damage.id).is_empty());
+
+    // A decided claim cannot be approved again
+    let mut again = repaired.clone();
+    assert!(decide_claim(storage, &mut again, ClaimStatus::Reimbursement).await.is_err());
+
+    // Theft claim waits for the police before it can be reimbursed
+    let (mut theft, _) = prepare_claim(
+        storage,
+        claim_dto(second_uuid, now - Duration::days(1), true),
+        now,
+        ClientIds::Refused,
+    )
+    .await
+    .unwrap();
+    storage.insert_claim(&theft).await.unwrap();
+
+    let err = decide_claim(storage, &mut theft.clone(), ClaimStatus::Reimbursement).await.unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+
+    let reports = entries_with(&theft_claims_to_confirm(storage, of_user(&username)).await.unwrap(), "uuid", theft.id);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0]["name"], "Jane Doe");
+
+    confirm_theft(
+        storage,
+        ProcessTheftClaimDto {
+            uuid: theft.id,
+            contract_uuid: second_uuid,
+            is_theft: true,
+            file_reference: "PD-2024-001".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    theft = storage.find_claim(theft.id).await.unwrap().unwrap();
+    assert_eq!(theft.status, "TheftConfirmed");
+    assert_eq!(theft.file_reference, "PD-2024-001");
+    let reports = theft_claims_to_confirm(storage, of_user(&username)).await.unwrap();
+    assert!(entries_with(&reports, "uuid", theft.id).is_empty());
+
+    // Reimbursing the theft voids the contract
+    decide_claim(storage, &mut theft, ClaimStatus::Reimbursement).await.unwrap();
+    theft.reimbursable = 1500.0;
+    close_decision(storage, &theft, "TheftConfirmed", now).await.unwrap();
+
+    let reimbursed = storage.find_claim(theft.id).await.unwrap().unwrap();
+    assert_eq!(reimbursed.status, "Reimbursement");
+    assert_eq!(reimbursed.reimbursable, 1500.0);
+    assert!(storage.find_contract(second_uuid).await.unwrap().unwrap().void);
+
+    // No further claims on a void contract
+    let err = prepare_claim(storage, claim_dto(second_uuid, now, false), now, ClientIds::Refused).await.unwrap_err();
+    assert!(err.to_string().contains("void"));
+}
+
+pub(super) async fn run_rejections(storage: &dyn Storage) {
+    let now = Utc::now().naive_utc();
+    let start_date = now - Duration::days(60);
+
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(false)).await.unwrap();
+
+    let dto = contract_dto(contract_type_uuid, &username(), "secret", start_date);
+    let contract_uuid = created_uuid(&open_contract(storage, dto, ClientIds::Refused).await.unwrap());
+
+    // Unknown contracts, uninsured thefts and late reports are refused at filing
+    let err = prepare_claim(storage, claim_dto(Uuid::new_v4(), now, false), now, ClientIds::Refused).await.unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::NotFound));
+
+    let err = prepare_claim(storage, claim_dto(contract_uuid, now, true), now, ClientIds::Refused).await.unwrap_err();
+    assert!(err.to_string().contains("Theft is not insured"));
+
+    let err = prepare_claim(storage, claim_dto(contract_uuid, now - Duration::days(45), false), now, ClientIds::Refused)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("within"));
+
+    // Duplicate ids are refused by the storage
+    let (claim, _) = prepare_claim(storage, claim_dto(contract_uuid, now, false), now, ClientIds::Refused)
+        .await
+        .unwrap();
+    storage.insert_claim(&claim).await.unwrap();
+    let err = storage.insert_claim(&claim).await.unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::AlreadyExists));
+
+    // Rejected claims carry no amount and remember the status they had
+    let mut rejected = claim.clone();
+    decide_claim(storage, &mut rejected, ClaimStatus::Rejected).await.unwrap();
+    close_decision(storage, &rejected, "New", now).await.unwrap();
+
+    let stored = storage.find_claim(claim.id).await.unwrap().unwrap();
+    assert_eq!(stored.status, "Rejected");
+    assert_eq!(stored.reimbursable, 0.0);
+    assert_eq!(stored.rejected_from.as_deref(), Some("New"));
+    assert!(!storage.find_contract(contract_uuid).await.unwrap().unwrap().void);
+
+    // Only an appeal reopens a rejection
+    let err = decide_claim(storage, &mut stored.clone(), ClaimStatus::Repair).await.unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+
+    // Rejecting again keeps the original rejection and its appeal window
+    let rejected_at = stored.status_changed_at;
+    let mut again = stored.clone();
+    decide_claim(storage, &mut again, ClaimStatus::Rejected).await.unwrap();
+    close_decision(storage, &again, "Rejected", now + Duration::days(10)).await.unwrap();
+
+    let stored = storage.find_claim(claim.id).await.unwrap().unwrap();
+    assert_eq!(stored.status_changed_at, rejected_at);
+    assert_eq!(stored.rejected_from.as_deref(), Some("New"));
+}
+
+pub(super) async fn run_paging(storage: &dyn Storage) {
+    let now = Utc::now().naive_utc();
+
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(true)).await.unwrap();
+
+    let username = username();
+    let dto = contract_dto(contract_type_uuid, &username, "secret", now - Duration::days(60));
+    let contract_uuid = created_uuid(&open_contract(storage, dto, ClientIds::Refused).await.unwrap());
+
+    // Three theft reports waiting for the police, filed on consecutive days
+    let mut thefts = Vec::new();
+    for days in 1..=3 {
+        let (claim, _) = prepare_claim(
+            storage,
+            claim_dto(contract_uuid, now - Duration::days(days), true),
+            now,
+            ClientIds::Refused,
+        )
+        .await
+        .unwrap();
+        storage.insert_claim(&claim).await.unwrap();
+        thefts.push(claim.id);
+    }
+
+    // Newest first, two per page
+    let mut query = ListQuery {
+        limit: Some(2),
+        order: Some(SortOrder::Desc),
+        ..of_user(&username)
+    };
+    let first: serde_json::Value = serde_json::from_str(&theft_claims_to_confirm(storage, query).await.unwrap()).unwrap();
+    assert_eq!(first["total"], 3);
+    assert_eq!(first["items"][0]["uuid"], thefts[0].to_string());
+    assert_eq!(first["items"][1]["uuid"], thefts[1].to_string());
+
+    query = ListQuery {
+        limit: Some(2),
+        order: Some(SortOrder::Desc),
+        cursor: first["next_cursor"].as_str().map(str::to_string),
+        ..of_user(&username)
+    };
+    let second: serde_json::Value = serde_json::from_str(&theft_claims_to_confirm(storage, query).await.unwrap()).unwrap();
+    assert_eq!(second["items"].as_array().unwrap().len(), 1);
+    assert_eq!(second["items"][0]["uuid"], thefts[2].to_string());
+    assert!(second["next_cursor"].is_null());
+
+    // Date ranges compose with the owner filter
+    query = ListQuery {
+        date_from: Some(now - Duration::hours(36)),
+        ..of_user(&username)
+    };
+    let recent: serde_json::Value = serde_json::from_str(&theft_claims_to_confirm(storage, query).await.unwrap()).unwrap();
+    assert_eq!(recent["total"], 1);
+
+    // Unknown sort keys and filters that do not apply are refused
+    query = ListQuery {
+        sort: Some("fraud_score".to_string()),
+        ..ListQuery::default()
+    };
+    let err = theft_claims_to_confirm(storage, query).await.unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+
+    query = ListQuery {
+        is_theft: Some(false),
+        ..ListQuery::default()
+    };
+    let err = open_repair_orders(storage, query).await.unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+}
+
+fn search_as(role: PeerRole, username: &str, password: &str, query: &str) -> SearchDto {
+    SearchDto {
+        query: query.to_string(),
+        kinds: None,
+        limit: None,
+        caller: Caller {
+            username: username.to_string(),
+            role,
+            password: Some(password.to_string()),
+        },
+    }
+}
+
+// Ids of the hits of a search response
+fn hit_ids(response: &str) -> Vec<String> {
+    let hits: serde_json::Value = serde_json::from_str(response).unwrap();
+    hits.as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["uuid"].as_str().unwrap().to_string())
+        .collect()
+}
+
+pub(super) async fn run_search(storage: &dyn Storage) {
+    let now = Utc::now().naive_utc();
+
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(true)).await.unwrap();
+
+    let username = username();
+    let dto = contract_dto(contract_type_uuid, &username, "secret", now - Duration::days(60));
+    let contract_uuid = created_uuid(&open_contract(storage, dto, ClientIds::Refused).await.unwrap());
+
+    let (damage, _) = prepare_claim(
+        storage,
+        claim_dto(contract_uuid, now - Duration::days(2), false),
+        now,
+        ClientIds::Refused,
+    )
+    .await
+    .unwrap();
+    storage.insert_claim(&damage).await.unwrap();
+    let (theft, _) = prepare_claim(
+        storage,
+        claim_dto(contract_uuid, now - Duration::days(1), true),
+        now,
+        ClientIds::Refused,
+    )
+    .await
+    .unwrap();
+    storage.insert_claim(&theft).await.unwrap();
+
+    // Claimants find their claims by description, contracts by item and themselves by name
+    let own = |query: &str| search_as(PeerRole::Claimant, &username, "secret", query);
+    let hits = hit_ids(&search_records(storage, own("frame")).await.unwrap());
+    assert!(hits.contains(&damage.id.to_string()));
+    assert!(!hits.contains(&theft.id.to_string()));
+
+    let hits = hit_ids(&search_records(storage, own("Gazelle GZ-1234")).await.unwrap());
+    assert!(hits.contains(&contract_uuid.to_string()));
+
+    let mut by_name = own("Jane Doe");
+    by_name.kinds = Some(vec![SearchKind::Customer]);
+    assert_eq!(hit_ids(&search_records(storage, by_name).await.unwrap()), vec![username.clone()]);
+
+    let err = search_records(storage, search_as(PeerRole::Claimant, &username, "guess", "frame"))
+        .await
+        .unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::PermissionDenied));
+
+    // Police only find theft claims
+    let hits = hit_ids(&search_records(storage, search_as(PeerRole::Police, "pd", "", "frame")).await.unwrap());
+    assert!(!hits.contains(&damage.id.to_string()));
+    let hits = hit_ids(&search_records(storage, search_as(PeerRole::Police, "pd", "", "station")).await.unwrap());
+    assert!(hits.contains(&theft.id.to_string()));
+
+    let err = search_records(storage, own("  ")).await.unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+}
+
+// Claims are scored for fraud on every backend; an item insured by someone else
+// and claimed right after the purchase is held for review
+pub(super) async fn run_fraud_scoring(storage: &dyn Storage) {
+    let now = Utc::now().naive_utc();
+
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(false)).await.unwrap();
+
+    let serial_no = Uuid::new_v4().to_string();
+    let mut uuids = Vec::new();
+    for start_date in [now - Duration::days(60), now - Duration::days(3)] {
+        let mut dto = contract_dto(contract_type_uuid, &username(), "secret", start_date);
+        dto.item.serial_no = serial_no.clone();
+        uuids.push(created_uuid(&open_contract(storage, dto, ClientIds::Refused).await.unwrap()));
+    }
+
+    let clean = submit_claim(storage, claim_dto(uuids[0], now - Duration::days(1), false), ClientIds::Refused).await;
+    let clean = created_uuid(&clean.unwrap());
+    let clean = storage.find_claim(clean).await.unwrap().unwrap();
+    assert_eq!(clean.status, "New");
+    assert_eq!(clean.fraud_rules, vec!["serial_no_shared_across_users"]);
+
+    let held = submit_claim(storage, claim_dto(uuids[1], now - Duration::days(1), false), ClientIds::Refused).await;
+    let held = created_uuid(&held.unwrap());
+    let held = storage.find_claim(held).await.unwrap().unwrap();
+    assert_eq!(held.status, "ManualReview");
+    assert_eq!(held.fraud_score, 65);
+}
+
+// JSON-RPC call of contract_create
+fn create_contract_call(id: Option<i64>, dto: &CreateContractDto) -> serde_json::Value {
+    let mut call = serde_json::json!({ "jsonrpc": "2.0", "method": "contract_create", "params": dto });
+    if let Some(id) = id {
+        call["id"] = serde_json::json!(id);
+    }
+    call
+}
+
+// A point of sale submits several contracts in one batch; every call with an id
+// gets its own response, notifications run without one
+pub(super) async fn run_rpc_batch(backend: &Backend) {
+    let storage = backend.storage();
+    let now = Utc::now().naive_utc();
+
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(false)).await.unwrap();
+
+    let first = contract_dto(contract_type_uuid, &username(), "secret", now);
+    let second = contract_dto(contract_type_uuid, &username(), "secret", now);
+    let notified = contract_dto(contract_type_uuid, &username(), "secret", now);
+    let batch = serde_json::json!([
+        create_contract_call(Some(1), &first),
+        create_contract_call(None, &notified),
+        create_contract_call(Some(2), &second),
+        { "jsonrpc": "2.0", "method": "contract_shred", "id": 3 },
+        { "jsonrpc": "2.0", "method": "contract_get", "params": { "uuid": "not-a-uuid" }, "id": "4" },
+        { "method": "contract_get", "id": 5 },
+    ]);
+
+    let responses = rpc::respond_to(backend, batch, None).await.unwrap();
+    let responses = responses.as_array().unwrap();
+    assert_eq!(responses.len(), 5);
+
+    // New customers get their credentials back
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(responses[0]["result"]["username"], first.username.as_str());
+    assert_eq!(responses[1]["id"], 2);
+    assert_eq!(responses[1]["result"]["username"], second.username.as_str());
+    for dto in [&first, &second, &notified] {
+        assert_eq!(storage.contracts_of_user(&dto.username).await.unwrap().len(), 1);
+    }
+
+    assert_eq!(responses[2]["id"], 3);
+    assert_eq!(responses[2]["error"]["code"], -32601);
+    assert_eq!(responses[3]["id"], "4");
+    assert_eq!(responses[3]["error"]["code"], -32602);
+    assert_eq!(responses[4]["id"], serde_json::Value::Null);
+    assert_eq!(responses[4]["error"]["code"], -32600);
+
+    // Single calls get a single response, notifications alone none
+    let call = create_contract_call(Some(6), &contract_dto(contract_type_uuid, &username(), "secret", now));
+    let single = rpc::respond_to(backend, call, None).await.unwrap();
+    assert_eq!(single["id"], 6);
+    assert!(single["result"].is_object());
+
+    let notification = create_contract_call(None, &contract_dto(contract_type_uuid, &username(), "secret", now));
+    assert!(rpc::respond_to(backend, serde_json::json!([notification]), None).await.is_none());
+    assert_eq!(rpc::respond_to(backend, serde_json::json!([]), None).await.unwrap()["error"]["code"], -32600);
+}
+
+fn user_dto(username: &str) -> CreateUserDto {
+    CreateUserDto {
+        username: username.to_string(),
+        password: "secret".to_string(),
+        first_name: "Jane".to_string(),
+        last_name: "Doe".to_string(),
+    }
+}
+
+fn batch_of(calls: Vec<(&str, serde_json::Value)>) -> String {
+    let calls: Vec<serde_json::Value> = calls
+        .into_iter()
+        .map(|(function, parameters)| serde_json::json!({ "function": function, "parameters": parameters }))
+        .collect();
+    serde_json::json!({ "calls": calls }).to_string()
+}
+
+fn failed_call(err: &Error) -> Option<usize> {
+    match err {
+        Error::Decode(source) => source.downcast_ref::<BatchError>().map(|err| err.index),
+        _ => None,
+    }
+}
+
+// Kind of the error of the call that failed a batch
+fn failed_kind(err: &Error) -> Option<std::io::ErrorKind> {
+    match err {
+        Error::Decode(source) => source.downcast_ref::<BatchError>().and_then(|err| io_kind(&err.source)),
+        _ => None,
+    }
+}
+
+// A customer migrated from a legacy system with their contract and a first claim:
+// all of it is stored, or nothing when one call fails
+pub(super) async fn run_batch(backend: &Backend) {
+    let storage = backend.storage();
+    let now = Utc::now().naive_utc();
+
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(false)).await.unwrap();
+
+    // Records created in a batch keep the ids chosen for them, so the claim can
+    // refer to the contract
+    let username = username();
+    let contract_uuid = Uuid::new_v4();
+    let contract = CreateContractDto {
+        uuid: Some(contract_uuid),
+        ..contract_dto(contract_type_uuid, &username, "secret", now - Duration::days(60))
+    };
+    let claim_uuid = Uuid::new_v4();
+    let claim = FileClaimDto {
+        uuid: Some(claim_uuid),
+        ..claim_dto(contract_uuid, now - Duration::days(1), false)
+    };
+    let args = batch_of(vec![
+        ("user_create", serde_json::json!(user_dto(&username))),
+        ("contract_create", serde_json::json!(contract)),
+        ("claim_file", serde_json::json!(claim)),
+    ]);
+
+    let response = batch::batch(backend, args, ClientIds::Allowed).await.unwrap();
+    let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(
+        response["results"],
+        serde_json::json!([null, { "uuid": contract_uuid }, { "uuid": claim_uuid }])
+    );
+    assert!(storage.find_user(&username).await.unwrap().is_some());
+    assert!(storage.find_contract(contract_uuid).await.unwrap().is_some());
+    assert_eq!(storage.find_claim(claim_uuid).await.unwrap().unwrap().status, "New");
+
+    // Claims filed in a batch are fraud scored: the same item bought by another
+    // customer a few days ago is held for review
+    let buyer = self::username();
+    let bought_uuid = Uuid::new_v4();
+    let bought = CreateContractDto {
+        uuid: Some(bought_uuid),
+        ..contract_dto(contract_type_uuid, &buyer, "secret", now - Duration::days(3))
+    };
+    let held_uuid = Uuid::new_v4();
+    let held = FileClaimDto {
+        uuid: Some(held_uuid),
+        ..claim_dto(bought_uuid, now - Duration::days(1), false)
+    };
+    let args = batch_of(vec![
+        ("user_create", serde_json::json!(user_dto(&buyer))),
+        ("contract_create", serde_json::json!(bought)),
+        ("claim_file", serde_json::json!(held)),
+    ]);
+    batch::batch(backend, args, ClientIds::Allowed).await.unwrap();
+    assert_eq!(storage.find_claim(held_uuid).await.unwrap().unwrap().status, "ManualReview");
+
+    // Ids already taken are conflicts
+    let taken = CreateContractDto {
+        uuid: Some(contract_uuid),
+        ..contract_dto(contract_type_uuid, &username, "secret", now)
+    };
+    let args = batch_of(vec![("contract_create", serde_json::json!(taken))]);
+    let err = batch::batch(backend, args, ClientIds::Allowed).await.unwrap_err();
+    assert_eq!(failed_call(&err), Some(0));
+    assert_eq!(failed_kind(&err), Some(std::io::ErrorKind::AlreadyExists));
+
+    // Batches sent over the API choose no ids unless BATCH_CLIENT_IDS is set,
+    // and outside a batch the server assigns every id
+    let chosen = Uuid::new_v4();
+    let contract = CreateContractDto {
+        uuid: Some(chosen),
+        ..contract_dto(contract_type_uuid, &self::username(), "secret", now)
+    };
+    let claim = FileClaimDto {
+        uuid: Some(chosen),
+        ..claim_dto(contract_uuid, now - Duration::days(1), false)
+    };
+    let calls = [("contract_create", serde_json::json!(contract)), ("claim_file", serde_json::json!(claim))];
+    let err = rpc::invoke(backend, "batch", batch_of(calls.to_vec())).await.unwrap().unwrap_err();
+    assert_eq!(failed_call(&err), Some(0));
+    assert_eq!(failed_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+    for (function, args) in calls {
+        let err = rpc::invoke(backend, function, args.to_string()).await.unwrap().unwrap_err();
+        assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+    }
+    assert!(storage.find_contract(chosen).await.unwrap().is_none());
+    assert!(storage.find_claim(chosen).await.unwrap().is_none());
+
+    // The claim is filed on a contract that does not exist: customer and contract are rolled back
+    let username = self::username();
+    let contract = contract_dto(contract_type_uuid, &username, "secret", now - Duration::days(60));
+    let args = batch_of(vec![
+        ("user_create", serde_json::json!(user_dto(&username))),
+        ("contract_create", serde_json::json!(contract)),
+        ("claim_file", serde_json::json!(claim_dto(Uuid::new_v4(), now, false))),
+    ]);
+
+    let err = batch::batch(backend, args, ClientIds::Refused).await.unwrap_err();
+    assert_eq!(failed_call(&err), Some(2));
+    assert!(storage.find_user(&username).await.unwrap().is_none());
+    assert!(storage.contracts_of_user(&contract.username).await.unwrap().is_empty());
+
+    // Functions that need Postgres cannot be batched
+    let args = batch_of(vec![
+        ("user_create", serde_json::json!(user_dto(&self::username()))),
+        ("claim_assign", serde_json::json!({})),
+    ]);
+    let err = batch::batch(backend, args, ClientIds::Refused).await.unwrap_err();
+    assert_eq!(failed_call(&err), Some(1));
+    assert!(err.to_string().contains("need Postgres"));
+
+    let err = batch::batch(backend, batch_of(vec![]), ClientIds::Refused).await.unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+}
+
+// A shop retries calls whose response got lost: the retry gets the first
+// response back instead of selling the contract a second time
+pub(super) async fn run_idempotency(backend: &Backend) {
+    let storage = backend.storage();
+    let now = Utc::now().naive_utc();
+
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(false)).await.unwrap();
+
+    let key = Uuid::new_v4().to_string();
+    let contract = contract_dto(contract_type_uuid, &username(), "secret", now);
+    let args = serde_json::json!(contract).to_string();
+    let first = rpc::invoke_once(backend, Some(&key), "contract_create", args.clone()).await.unwrap().unwrap();
+    let retried = rpc::invoke_once(backend, Some(&key), "contract_create", args.clone()).await.unwrap().unwrap();
+    assert_eq!(retried, first);
+    assert_eq!(storage.contracts_of_user(&contract.username).await.unwrap().len(), 1);
+
+    // Without a key the retry runs again
+    let again = rpc::invoke_once(backend, None, "contract_create", args).await.unwrap().unwrap();
+    assert_ne!(created_uuid(&again), created_uuid(&first));
+    assert_eq!(storage.contracts_of_user(&contract.username).await.unwrap().len(), 2);
+
+    // The key cannot be reused for another call of the same caller
+    let other = serde_json::json!(contract_dto(contract_type_uuid, &contract.username, "secret", now + Duration::days(1)));
+    let err = rpc::invoke_once(backend, Some(&key), "contract_create", other.to_string()).await.unwrap().unwrap_err();
+    assert_eq!(io_kind(&err), Some(std::io::ErrorKind::InvalidInput));
+
+    // Another caller with the same key makes a call of its own
+    let other = serde_json::json!(contract_dto(contract_type_uuid, &username(), "secret", now)).to_string();
+    let response = rpc::invoke_once(backend, Some(&key), "contract_create", other).await.unwrap().unwrap();
+    assert_ne!(created_uuid(&response), created_uuid(&first));
+
+    // The same key given with another function is another key
+    let user = serde_json::json!(user_dto(&username())).to_string();
+    assert!(rpc::invoke_once(backend, Some(&key), "user_create", user).await.unwrap().is_ok());
+
+    // Failed calls are not recorded and can be retried with their key
+    let key = Uuid::new_v4().to_string();
+    let claim = claim_dto(Uuid::new_v4(), now, false);
+    let args = serde_json::json!(claim).to_string();
+    assert!(rpc::invoke_once(backend, Some(&key), "claim_file", args.clone()).await.unwrap().is_err());
+    let scoped = scoped_key("claim_file", &caller(&args), &key);
+    assert!(storage.find_idempotency_key(&scoped).await.unwrap().is_none());
+
+    // JSON-RPC calls take the key of the header, or their own
+    let key = Uuid::new_v4().to_string();
+    let call = create_contract_call(Some(1), &contract_dto(contract_type_uuid, &username(), "secret", now));
+    let first = rpc::respond_to(backend, call.clone(), Some(key.clone())).await.unwrap();
+    let retried = rpc::respond_to(backend, call.clone(), Some(key)).await.unwrap();
+    assert!(first["result"].is_object());
+    assert_eq!(retried, first);
+
+    // A new key runs the call again
+    let mut call = call;
+    call["idempotency_key"] = serde_json::json!(Uuid::new_v4().to_string());
+    let other = rpc::respond_to(backend, call, None).await.unwrap();
+    assert_ne!(other["result"]["uuid"], first["result"]["uuid"]);
+
+    // Keys past the retention window are forgotten
+    let key = Uuid::new_v4().to_string();
+    let args = serde_json::json!(contract_dto(contract_type_uuid, &username(), "secret", now)).to_string();
+    let expired = IdempotencyRecord {
+        key: scoped_key("contract_create", &caller(&args), &key),
+        request_hash: "stale".to_string(),
+        response: Some("stale".to_string()),
+        created_at: now - Duration::days(30),
+    };
+    assert!(storage.reserve_idempotency_key(&expired).await.unwrap());
+    let response = rpc::invoke_once(backend, Some(&key), "contract_create", args).await.unwrap().unwrap();
+    assert_ne!(response, "stale");
+    let contract_uuid = created_uuid(&response);
+    assert!(storage.find_contract(contract_uuid).await.unwrap().is_some());
+
+    // Calls that never finished give up their key once the lease runs out
+    let key = Uuid::new_v4().to_string();
+    let args = serde_json::json!(contract_dto(contract_type_uuid, &username(), "secret", now)).to_string();
+    let abandoned = IdempotencyRecord {
+        key: scoped_key("contract_create", &caller(&args), &key),
+        request_hash: "abandoned".to_string(),
+        response: None,
+        created_at: now - Duration::hours(1),
+    };
+    assert!(storage.reserve_idempotency_key(&abandoned).await.unwrap());
+    let response = rpc::invoke_once(backend, Some(&key), "contract_create", args).await.unwrap().unwrap();
+    assert!(storage.find_contract(created_uuid(&response)).await.unwrap().is_some());
+
+    // Keys given with functions that change nothing are ignored
+    let args = serde_json::json!({ "uuid": contract_uuid }).to_string();
+    for _ in 0..2 {
+        assert!(rpc::invoke_once(backend, Some("lookup"), "contract_get", args.clone()).await.unwrap().is_ok());
+    }
+    assert!(storage.find_idempotency_key(&scoped_key("contract_get", "", "lookup")).await.unwrap().is_none());
+}
+
+// Call of a registered function that must fail, and how
+async fn failing_call(backend: &Backend, function: &str, args: serde_json::Value) -> Option<std::io::ErrorKind> {
+    let err = rpc::invoke(backend, function, args.to_string()).await.unwrap().unwrap_err();
+    io_kind(&err)
+}
+
+// An adjuster decides claims through the public functions: only the assigned
+// adjuster or a supervisor may decide, settlements stay within the insured value
+// and a rejection is reopened only by a supervisor overturning it on appeal
+pub(super) async fn run_claim_processing(backend: &Backend) {
+    let pool = match backend {
+        Backend::Postgres(storage) => storage.pool(),
+        Backend::Sqlite(_) => panic!("Claim processing needs Postgres."),
+    };
+    let storage = backend.storage();
+    let now = Utc::now().naive_utc();
+
+    let contract_type_uuid = Uuid::new_v4();
+    register_contract_type(storage, contract_type_uuid, contract_type(false)).await.unwrap();
+
+    // A unique serial number keeps the claims clear of the fraud rules
+    let username = username();
+    let mut dto = contract_dto(contract_type_uuid, &username, "secret", now - Duration::days(60));
+    dto.item.serial_no = Uuid::new_v4().to_string();
+    let contract_uuid = created_uuid(&open_contract(storage, dto, ClientIds::Refused).await.unwrap());
+
+    let supervisor = format!("supervisor-{}", Uuid::new_v4().simple());
+    let assignee = format!("adjuster-{}", Uuid::new_v4().simple());
+    let other = format!("adjuster-{}", Uuid::new_v4().simple());
+    adjusters::create(pool, &supervisor, AdjusterRole::Supervisor, "secret").await.unwrap();
+    adjusters::create(pool, &assignee, AdjusterRole::Adjuster, "secret").await.unwrap();
+    adjusters::create(pool, &other, AdjusterRole::Adjuster, "secret").await.unwrap();
+    let credentials = |username: &str| serde_json::json!({ "username": username, "password": "secret" });
+
+    let file = |date: NaiveDateTime| serde_json::json!(claim_dto(contract_uuid, date, false)).to_string();
+    let filed = rpc::invoke(backend, "claim_file", file(now - Duration::days(2))).await.unwrap();
+    let damage = created_uuid(&filed.unwrap());
+    let assign = |claim: Uuid| {
+        serde_json::json!({ "uuid": claim, "adjuster": assignee, "assigned_by": credentials(&supervisor) }).to_string()
+    };
+    rpc::invoke(backend, "claim_assign", assign(damage)).await.unwrap().unwrap();
+
+    let process = |claim: Uuid, status: &str, adjuster: &str, settlements: serde_json::Value| {
+        serde_json::json!({
+            "uuid": claim,
+            "contract_uuid": contract_uuid,
+            "status": status,
+            "adjuster": credentials(adjuster),
+            "settlements": settlements,
+        })
+    };
+
+    // Other adjusters and wrong passwords are turned away
+    let args = process(damage, "Reimbursement", &other, serde_json::Value::Null);
+    assert_eq!(failing_call(backend, "claim_process", args).await, Some(std::io::ErrorKind::PermissionDenied));
+    let mut args = process(damage, "Reimbursement", &assignee, serde_json::Value::Null);
+    args["adjuster"]["password"] = serde_json::json!("guess");
+    assert_eq!(failing_call(backend, "claim_process", args).await, Some(std::io::ErrorKind::PermissionDenied));
+
+    // Settling above the insured value rolls back the whole decision
+    let lines = serde_json::json!([{ "kind": "cash", "amount": 5000.0 }]);
+    let args = process(damage, "Reimbursement", &assignee, lines);
+    assert_eq!(failing_call(backend, "claim_process", args).await, Some(std::io::ErrorKind::InvalidInput));
+    let stored = storage.find_claim(damage).await.unwrap().unwrap();
+    assert_eq!(stored.status, "New");
+    assert_eq!(stored.reimbursable, 0.0);
+
+    // Cash less the deductible, then a second installment
+    let lines = serde_json::json!([{ "kind": "cash", "amount": 400.0 }, { "kind": "deductible", "amount": 50.0 }]);
+    let args = process(damage, "Reimbursement", &assignee, lines).to_string();
+    rpc::invoke(backend, "claim_process", args).await.unwrap().unwrap();
+    let lines = serde_json::json!([{ "kind": "cash", "amount": 100.0 }]);
+    let args = process(damage, "Reimbursement", &supervisor, lines).to_string();
+    rpc::invoke(backend, "claim_process", args).await.unwrap().unwrap();
+
+    let stored = storage.find_claim(damage).await.unwrap().unwrap();
+    assert_eq!(stored.status, "Reimbursement");
+    assert_eq!(stored.reimbursable, 450.0);
+
+    // Settlements are shown to the adjusters and the claimant, not to other peers
+    let settlements_of = |caller: &str, role: &str| {
+        serde_json::json!({ "uuid": damage, "username": caller, "role": role, "password": "secret" })
+    };
+    for (caller, role) in [(assignee.as_str(), "insurer"), (username.as_str(), "claimant")] {
+        let args = settlements_of(caller, role).to_string();
+        let listed = rpc::result_value(rpc::invoke(backend, "claim_settlement_ls", args).await.unwrap().unwrap());
+        assert_eq!(listed["settlements"].as_array().unwrap().len(), 3);
+        assert_eq!(listed["total"], 450.0);
+    }
+    let mut args = settlements_of(&username, "claimant");
+    args["password"] = serde_json::json!("guess");
+    assert_eq!(failing_call(backend, "claim_settlement_ls", args).await, Some(std::io::ErrorKind::PermissionDenied));
+    let args = settlements_of("police", "police");
+    assert_eq!(failing_call(backend, "claim_settlement_ls", args).await, Some(std::io::ErrorKind::PermissionDenied));
+
+    // A rejected claim is reopened only through its appeal
+    let filed = rpc::invoke(backend, "claim_file", file(now - Duration::days(1))).await.unwrap();
+    let rejected = created_uuid(&filed.unwrap());
+    rpc::invoke(backend, "claim_assign", assign(rejected)).await.unwrap().unwrap();
+    let args = process(rejected, "Rejected", &assignee, serde_json::Value::Null).to_string();
+    rpc::invoke(backend, "claim_process", args).await.unwrap().unwrap();
+    let args = process(rejected, "Repair", &supervisor, serde_json::Value::Null);
+    assert_eq!(failing_call(backend, "claim_process", args).await, Some(std::io::ErrorKind::InvalidInput));
+
+    // Listings filter on the status codes
+    let list = |status: &str| {
+        let args = serde_json::json!({ "adjuster": credentials(&supervisor), "username": username, "status": status });
+        async move { rpc::invoke(backend, "claim_ls", args.to_string()).await.unwrap().unwrap() }
+    };
+    let listed = list("J").await;
+    assert_eq!(entries_with(&listed, "uuid", rejected).len(), 1);
+    assert!(entries_with(&listed, "uuid", damage).is_empty());
+    assert_eq!(entries_with(&list("F").await, "uuid", damage).len(), 1);
+    let args = serde_json::json!({ "adjuster": credentials(&supervisor), "status": "Closed" });
+    assert_eq!(failing_call(backend, "claim_ls", args).await, Some(std::io::ErrorKind::InvalidInput));
+
+    let appeal = serde_json::json!({
+        "uuid": rejected,
+        "username": username,
+        "password": "secret",
+        "reason": "Frame was fine before",
+    });
+    rpc::invoke(backend, "claim_appeal", appeal.to_string()).await.unwrap().unwrap();
+    assert_eq!(failing_call(backend, "claim_appeal", appeal).await, Some(std::io::ErrorKind::AlreadyExists));
+    let args = process(rejected, "Repair", &supervisor, serde_json::Value::Null);
+    assert_eq!(failing_call(backend, "claim_process", args).await, Some(std::io::ErrorKind::InvalidInput));
+
+    let decide = |adjuster: &str| {
+        serde_json::json!({
+            "uuid": rejected,
+            "adjuster": credentials(adjuster),
+            "decision": "overturned",
+            "reason": "Photos confirm the damage",
+        })
+    };
+    let err = failing_call(backend, "claim_appeal_decide", decide(&assignee)).await;
+    assert_eq!(err, Some(std::io::ErrorKind::PermissionDenied));
+    rpc::invoke(backend, "claim_appeal_decide", decide(&supervisor).to_string()).await.unwrap().unwrap();
+
+    let stored = storage.find_claim(rejected).await.unwrap().unwrap();
+    assert_eq!(stored.status, "New");
+    assert_eq!(stored.rejected_from, None);
+}
+
+#[tokio::test]
+async fn workflow_in_memory() {
+    run_workflow(&MemoryStorage::new()).await;
+}
+
+#[tokio::test]
+async fn rejections_in_memory() {
+    run_rejections(&MemoryStorage::new()).await;
+}
+
+#[tokio::test]
+async fn paging_in_memory() {
+    run_paging(&MemoryStorage::new()).await;
+}
+
+#[tokio::test]
+async fn search_in_memory() {
+    run_search(&MemoryStorage::new()).await;
+}
+
+#[tokio::test]
+async fn fraud_scoring_in_memory() {
+    run_fraud_scoring(&MemoryStorage::new()).await;
+}
+
+async fn sqlite() -> Backend {
+    let backend = Backend::Sqlite(SqliteStorage::connect("sqlite::memory:").await.unwrap());
+    backend.migrate().await.unwrap();
+    backend
+}
+
+// Postgres runs need a database to migrate, given by TEST_DATABASE_URL; they are
+// ignored by default, run them with `cargo test -- --ignored`.
+async fn postgres() -> Backend {
+    let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    let pool = sqlx::PgPool::connect(&database_url).await.unwrap();
+    let backend = Backend::Postgres(PgStorage::new(&pool));
+    backend.migrate().await.unwrap();
+    backend
+}
+
+#[tokio::test]
+async fn workflow_on_sqlite() {
+    run_workflow(sqlite().await.storage()).await;
+}
+
+#[tokio::test]
+async fn rejections_on_sqlite() {
+    run_rejections(sqlite().await.storage()).await;
+}
+
+#[tokio::test]
+async fn paging_on_sqlite() {
+    run_paging(sqlite().await.storage()).await;
+}
+
+#[tokio::test]
+async fn search_on_sqlite() {
+    run_search(sqlite().await.storage()).await;
+}
+
+#[tokio::test]
+async fn fraud_scoring_on_sqlite() {
+    run_fraud_scoring(sqlite().await.storage()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn workflow_on_postgres() {
+    run_workflow(postgres().await.storage()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejections_on_postgres() {
+    run_rejections(postgres().await.storage()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn paging_on_postgres() {
+    run_paging(postgres().await.storage()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn search_on_postgres() {
+    run_search(postgres().await.storage()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn fraud_scoring_on_postgres() {
+    run_fraud_scoring(postgres().await.storage()).await;
+}
+
+#[tokio::test]
+async fn rpc_batch_on_sqlite() {
+    run_rpc_batch(&sqlite().await).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rpc_batch_on_postgres() {
+    run_rpc_batch(&postgres().await).await;
+}
+
+#[tokio::test]
+async fn batch_on_sqlite() {
+    run_batch(&sqlite().await).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn batch_on_postgres() {
+    run_batch(&postgres().await).await;
+}
+
+#[tokio::test]
+async fn idempotency_on_sqlite() {
+    run_idempotency(&sqlite().await).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn idempotency_on_postgres() {
+    run_idempotency(&postgres().await).await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn claim_processing_on_postgres() {
+    run_claim_processing(&postgres().await).await;
+}
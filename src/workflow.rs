@@ -0,0 +1,103 @@
+use serde::de::DeserializeOwned;
+use sqlx::Error;
+
+use crate::ids::{self, ClientIds};
+use crate::insurance::{self, ClaimValuationDto, FileClaimDto};
+use crate::listing::{self, ListQuery};
+use crate::police::{self, ProcessTheftClaimDto};
+use crate::repairs::{self, CompleteRepairOrderDto};
+use crate::search::{self, SearchDto};
+use crate::shop::{self, CreateContractDto, CreateUserDto};
+use crate::storage::Storage;
+
+// Functions of the core claim workflow that only go through the storage layer and
+// are therefore served on every backend: selling contracts, filing (fraud-scored)
+// claims, repair orders, theft reports and search. SQLite serves these and nothing
+// else. Listings, accounts and authentication, cancellations and renewals, claim
+// processing and settlements, adjusters, SLAs, appeals, documents and messages need
+// Postgres, as does the scheduler; on SQLite they fail as unsupported.
+
+fn parse<T: DeserializeOwned>(args: &str) -> Result<T, Error> {
+    serde_json::from_str(args).map_err(|err| {
+        eprintln!("Failed to parse input JSON: {:?}", err);
+        Error::Decode(Box::new(err))
+    })
+}
+
+// Returns None when the function is not served through the storage layer;
+// `client_ids` tells whether new records may get the ids the caller chose
+pub async fn invoke(
+    storage: &dyn Storage,
+    function: &str,
+    args: String,
+    client_ids: ClientIds,
+) -> Option<Result<String, Error>> {
+    let result = match function {
+        // Insurance Peer
+        "contract_type_create" => match insurance::parse_new_contract_type(&args, client_ids) {
+            Ok((uuid, ct)) => match insurance::register_contract_type(storage, uuid, ct).await {
+                Ok(()) => ids::created(uuid),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        },
+        "contract_type_set_active" => match insurance::parse_activation(&args) {
+            Ok((uuid, active)) => insurance::activate_contract_type(storage, uuid, active).await.map(|_| String::new()),
+            Err(err) => Err(err),
+        },
+        "contract_get" => match parse::<insurance::GetContractDto>(&args) {
+            Ok(dto) => insurance::contract_details(storage, dto.uuid).await,
+            Err(err) => Err(err),
+        },
+        "claim_file" => file_claim(storage, &args, client_ids).await,
+        "claim_valuation" => match parse::<ClaimValuationDto>(&args) {
+            Ok(dto) => insurance::value_claim(storage, dto).await,
+            Err(err) => Err(err),
+        },
+
+        // Shop Peer
+        "contract_create" => match parse::<CreateContractDto>(&args) {
+            Ok(dto) => shop::open_contract(storage, dto, client_ids).await,
+            Err(err) => Err(err),
+        },
+        "user_create" => match parse::<CreateUserDto>(&args) {
+            Ok(dto) => shop::register_user(storage, dto).await.map(|_| String::new()),
+            Err(err) => Err(err),
+        },
+
+        // Repair Shop Peer
+        "repair_order_ls" => match listing::parse_list::<ListQuery>(Some(args)) {
+            Ok(query) => repairs::open_repair_orders(storage, query).await,
+            Err(err) => Err(err),
+        },
+        "repair_order_complete" => match parse::<CompleteRepairOrderDto>(&args) {
+            Ok(dto) => repairs::finish_repair_order(storage, dto.uuid).await.map(|_| String::new()),
+            Err(err) => Err(err),
+        },
+
+        // Police Peer
+        "theft_claim_ls" => match listing::parse_list::<ListQuery>(Some(args)) {
+            Ok(query) => police::theft_claims_to_confirm(storage, query).await,
+            Err(err) => Err(err),
+        },
+        "theft_claim_process" => match parse::<ProcessTheftClaimDto>(&args) {
+            Ok(dto) => police::confirm_theft(storage, dto).await.map(|_| String::new()),
+            Err(err) => Err(err),
+        },
+
+        // Search (all peers)
+        "search" => match parse::<SearchDto>(&args) {
+            Ok(dto) => search::search_records(storage, dto).await,
+            Err(err) => Err(err),
+        },
+
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+async fn file_claim(storage: &dyn Storage, args: &str, client_ids: ClientIds) -> Result<String, Error> {
+    let dto: FileClaimDto = parse(args)?;
+    insurance::submit_claim(storage, dto, client_ids).await
+}